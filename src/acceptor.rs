@@ -1,9 +1,12 @@
 use std::fmt;
 use std::marker::Unpin;
+use std::sync::Arc;
+
+use zeroize::Zeroizing;
 
 use crate::handshake::handshake;
 use crate::runtime::{AsyncRead, AsyncReadExt, AsyncWrite};
-use crate::TlsStream;
+use crate::{Certificate, Protocol, TlsStream};
 
 /// A wrapper around a `native_tls::TlsAcceptor`, providing an async `accept`
 /// method.
@@ -38,9 +41,19 @@ use crate::TlsStream;
 /// # fn main() {}
 /// ```
 #[derive(Clone)]
-pub struct TlsAcceptor(native_tls::TlsAcceptor);
+pub struct TlsAcceptor(Inner);
+
+#[derive(Clone)]
+enum Inner {
+    Native(native_tls::TlsAcceptor),
+    #[cfg(all(
+        feature = "openssl",
+        not(any(target_os = "windows", target_vendor = "apple"))
+    ))]
+    OpenSsl(openssl::ssl::SslAcceptor),
+}
 
-/// An error returned from creating an acceptor.
+/// An error returned from creating an acceptor or accepting a connection.
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     /// NativeTls error.
@@ -49,6 +62,251 @@ pub enum Error {
     /// Io error.
     #[error("Io({})", 0)]
     Io(#[from] std::io::Error),
+    /// OpenSSL error, from acceptors using settings native-tls does not expose.
+    #[cfg(all(
+        feature = "openssl",
+        not(any(target_os = "windows", target_vendor = "apple"))
+    ))]
+    #[error("OpenSsl({0})")]
+    OpenSsl(#[from] openssl::ssl::Error),
+    /// The setting is not supported by the TLS backend.
+    #[error("{0} is not supported by the TLS backend")]
+    Unsupported(&'static str),
+}
+
+#[cfg(all(
+    feature = "openssl",
+    not(any(target_os = "windows", target_vendor = "apple"))
+))]
+impl From<openssl::error::ErrorStack> for Error {
+    fn from(err: openssl::error::ErrorStack) -> Self {
+        Error::OpenSsl(err.into())
+    }
+}
+
+/// Whether an acceptor asks clients for certificates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientAuth {
+    /// Ask for a certificate, but accept clients without one. Certificates that are presented
+    /// must still be valid.
+    Request,
+    /// Reject clients without a valid certificate.
+    Require,
+}
+
+/// A builder for [`TlsAcceptor`]s with settings beyond an identity.
+///
+/// The defaults are those of `native_tls::TlsAcceptorBuilder` and [`TlsAcceptor::new`]: TLS 1.2
+/// is the oldest protocol accepted, unless
+/// [`min_protocol_version`](TlsAcceptorBuilder::min_protocol_version) lowers it, and no client
+/// certificates or ALPN protocols are asked for.
+///
+/// # Example
+///
+/// ```no_run
+/// # #[cfg(feature = "runtime-async-std")]
+/// # fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> { async_std::task::block_on(async {
+/// #
+/// use async_std::net::TcpListener;
+/// use async_native_tls::{Certificate, ClientAuth, TlsAcceptorBuilder};
+///
+/// let clients_ca = Certificate::from_pem(&std::fs::read("clients-ca.pem")?)?;
+/// let acceptor = TlsAcceptorBuilder::from_pkcs8(
+///     std::fs::read("cert.pem")?,
+///     std::fs::read("key.pem")?,
+/// )
+/// .client_auth(ClientAuth::Require, vec![clients_ca])
+/// .build()?;
+///
+/// let listener = TcpListener::bind("127.0.0.1:8443").await?;
+/// let (stream, _) = listener.accept().await?;
+/// let stream = acceptor.accept(stream).await?;
+/// let chain = stream.peer_certificate_chain()?;
+/// #
+/// # Ok(()) }) }
+/// # #[cfg(feature = "runtime-tokio")]
+/// # fn main() {}
+/// ```
+#[derive(Clone)]
+pub struct TlsAcceptorBuilder {
+    pub(crate) identity: IdentityData,
+    pub(crate) min_protocol: Option<Protocol>,
+    pub(crate) max_protocol: Option<Protocol>,
+    pub(crate) client_auth: Option<(ClientAuth, Vec<Certificate>)>,
+    pub(crate) alpn: Vec<String>,
+    pub(crate) select_alpn: Option<Arc<SelectAlpn>>,
+    #[cfg(all(
+        feature = "openssl",
+        not(any(target_os = "windows", target_vendor = "apple"))
+    ))]
+    pub(crate) configure: Vec<Arc<crate::ossl::Configure<openssl::ssl::SslAcceptorBuilder>>>,
+}
+
+/// Picks one of the protocols offered by a client, see [`TlsAcceptorBuilder::select_alpn`].
+pub(crate) type SelectAlpn = dyn for<'a> Fn(&[&'a str]) -> Option<&'a str> + Send + Sync;
+
+/// The encoded identity of an acceptor, kept so that each backend can load it. The password
+/// and the key are zeroized on drop.
+#[derive(Clone)]
+pub(crate) enum IdentityData {
+    Pkcs12 {
+        der: Vec<u8>,
+        password: Zeroizing<String>,
+    },
+    Pkcs8 {
+        cert: Vec<u8>,
+        key: Zeroizing<Vec<u8>>,
+    },
+}
+
+impl TlsAcceptorBuilder {
+    /// Starts a builder for the identity in a DER-formatted PKCS #12 archive.
+    pub fn from_pkcs12(der: impl Into<Vec<u8>>, password: impl Into<String>) -> Self {
+        Self::with_identity(IdentityData::Pkcs12 {
+            der: der.into(),
+            password: Zeroizing::new(password.into()),
+        })
+    }
+
+    /// Starts a builder for a PEM-formatted certificate chain, leaf first, and a PEM-formatted
+    /// PKCS #8 private key.
+    pub fn from_pkcs8(cert: impl Into<Vec<u8>>, key: impl Into<Vec<u8>>) -> Self {
+        Self::with_identity(IdentityData::Pkcs8 {
+            cert: cert.into(),
+            key: Zeroizing::new(key.into()),
+        })
+    }
+
+    pub(crate) fn with_identity(identity: IdentityData) -> Self {
+        Self {
+            identity,
+            min_protocol: Some(Protocol::Tlsv12),
+            max_protocol: None,
+            client_auth: None,
+            alpn: vec![],
+            select_alpn: None,
+            #[cfg(all(
+                feature = "openssl",
+                not(any(target_os = "windows", target_vendor = "apple"))
+            ))]
+            configure: vec![],
+        }
+    }
+
+    /// Replaces the identity, keeping the other settings.
+    pub(crate) fn identity(mut self, identity: IdentityData) -> Self {
+        self.identity = identity;
+        self
+    }
+
+    /// Sets the minimum supported protocol version.
+    ///
+    /// A value of `None` enables support for the oldest protocols supported by the
+    /// implementation. Defaults to `Some(Protocol::Tlsv12)`.
+    pub fn min_protocol_version(mut self, protocol: Option<Protocol>) -> Self {
+        self.min_protocol = protocol;
+        self
+    }
+
+    /// Sets the maximum supported protocol version.
+    ///
+    /// A value of `None` enables support for the newest protocols supported by the
+    /// implementation. Defaults to `None`.
+    pub fn max_protocol_version(mut self, protocol: Option<Protocol>) -> Self {
+        self.max_protocol = protocol;
+        self
+    }
+
+    /// Asks clients for certificates, verified against `roots`.
+    ///
+    /// Only supported with the `openssl` feature; [`build`](TlsAcceptorBuilder::build) fails
+    /// with [`Error::Unsupported`] otherwise. The verified chain of the client is available from
+    /// [`TlsStream::peer_certificate_chain`].
+    pub fn client_auth(
+        mut self,
+        mode: ClientAuth,
+        roots: impl IntoIterator<Item = Certificate>,
+    ) -> Self {
+        self.client_auth = Some((mode, roots.into_iter().collect()));
+        self
+    }
+
+    /// Sets the protocols to accept via ALPN, most preferred first.
+    ///
+    /// The first of these protocols the client offers is selected. If the client offers none of
+    /// them, the handshake completes without a protocol. The selected protocol is available
+    /// from [`TlsStream::negotiated_alpn`].
+    pub fn accept_alpns(mut self, protocols: &[&str]) -> Self {
+        self.alpn = protocols.iter().map(|p| p.to_string()).collect();
+        self
+    }
+
+    /// Selects the ALPN protocol with a callback, which is passed the protocols offered by the
+    /// client in their order of preference and returns the one to use, if any. Offered
+    /// protocols which are not valid UTF-8 are left out.
+    ///
+    /// Takes precedence over [`accept_alpns`](TlsAcceptorBuilder::accept_alpns). Only supported
+    /// with the `openssl` feature; [`build`](TlsAcceptorBuilder::build) fails with
+    /// [`Error::Unsupported`] otherwise.
+    pub fn select_alpn<F>(mut self, select: F) -> Self
+    where
+        F: for<'a> Fn(&[&'a str]) -> Option<&'a str> + Send + Sync + 'static,
+    {
+        self.select_alpn = Some(Arc::new(select));
+        self
+    }
+
+    /// Creates a new acceptor with these settings.
+    pub fn build(self) -> Result<TlsAcceptor, Error> {
+        // load the identity with native-tls first, for its errors
+        let identity = match &self.identity {
+            IdentityData::Pkcs12 { der, password } => {
+                native_tls::Identity::from_pkcs12(der, password)?
+            }
+            IdentityData::Pkcs8 { cert, key } => native_tls::Identity::from_pkcs8(cert, key)?,
+        };
+        #[cfg(all(
+            feature = "openssl",
+            not(any(target_os = "windows", target_vendor = "apple"))
+        ))]
+        if self.client_auth.is_some() || self.select_alpn.is_some() || !self.configure.is_empty() {
+            return Ok(TlsAcceptor(Inner::OpenSsl(crate::ossl::acceptor(&self)?)));
+        }
+        #[cfg(not(all(
+            feature = "openssl",
+            not(any(target_os = "windows", target_vendor = "apple"))
+        )))]
+        if self.client_auth.is_some() {
+            return Err(Error::Unsupported("client authentication"));
+        }
+        #[cfg(not(all(
+            feature = "openssl",
+            not(any(target_os = "windows", target_vendor = "apple"))
+        )))]
+        if self.select_alpn.is_some() {
+            return Err(Error::Unsupported("ALPN selection callbacks"));
+        }
+        let acceptor = native_tls::TlsAcceptor::builder(identity)
+            .min_protocol_version(self.min_protocol)
+            .max_protocol_version(self.max_protocol)
+            .accept_alpn(&self.alpn)
+            .build()?;
+        Ok(acceptor.into())
+    }
+}
+
+impl fmt::Debug for TlsAcceptorBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TlsAcceptorBuilder")
+            .field("min_protocol", &self.min_protocol)
+            .field("max_protocol", &self.max_protocol)
+            .field(
+                "client_auth",
+                &self.client_auth.as_ref().map(|(mode, _)| mode),
+            )
+            .field("alpn", &self.alpn)
+            .finish_non_exhaustive()
+    }
 }
 
 impl TlsAcceptor {
@@ -62,11 +320,14 @@ impl TlsAcceptor {
         file.read_to_end(&mut identity).await?;
 
         let identity = native_tls::Identity::from_pkcs12(&identity, password.as_ref())?;
-        Ok(TlsAcceptor(native_tls::TlsAcceptor::new(identity)?))
+        Ok(native_tls::TlsAcceptor::new(identity)?.into())
     }
 
     /// Accepts a new client connection with the provided stream.
     ///
+    /// Before 0.6, this failed with a [`native_tls::Error`], which is now wrapped in
+    /// [`Error::NativeTls`].
+    ///
     /// This function will internally call `TlsAcceptor::accept` to connect
     /// the stream and returns a future representing the resolution of the
     /// connection operation. The returned future will resolve to either
@@ -75,13 +336,35 @@ impl TlsAcceptor {
     /// This is typically used after a new socket has been accepted from a
     /// `TcpListener`. That socket is then passed to this function to perform
     /// the server half of accepting a client connection.
-    pub async fn accept<S>(&self, stream: S) -> Result<TlsStream<S>, native_tls::Error>
+    pub async fn accept<S>(&self, stream: S) -> Result<TlsStream<S>, Error>
     where
         S: AsyncRead + AsyncWrite + Unpin,
     {
-        let stream = handshake(move |s| self.0.accept(s), stream).await?;
+        let stream = match &self.0 {
+            Inner::Native(acceptor) => handshake(move |s| acceptor.accept(s), stream).await?,
+            #[cfg(all(
+                feature = "openssl",
+                not(any(target_os = "windows", target_vendor = "apple"))
+            ))]
+            Inner::OpenSsl(acceptor) => {
+                crate::handshake::openssl_handshake(move |s| acceptor.accept(s), stream).await?
+            }
+        };
         Ok(stream)
     }
+
+    /// The OpenSSL acceptor, for acceptors built with settings native-tls does not expose.
+    #[cfg(all(
+        feature = "openssl",
+        feature = "x509",
+        not(any(target_os = "windows", target_vendor = "apple"))
+    ))]
+    pub(crate) fn openssl(&self) -> Option<&openssl::ssl::SslAcceptor> {
+        match &self.0 {
+            Inner::Native(_) => None,
+            Inner::OpenSsl(acceptor) => Some(acceptor),
+        }
+    }
 }
 
 impl fmt::Debug for TlsAcceptor {
@@ -92,7 +375,7 @@ impl fmt::Debug for TlsAcceptor {
 
 impl From<native_tls::TlsAcceptor> for TlsAcceptor {
     fn from(inner: native_tls::TlsAcceptor) -> TlsAcceptor {
-        TlsAcceptor(inner)
+        TlsAcceptor(Inner::Native(inner))
     }
 }
 
@@ -100,6 +383,7 @@ impl From<native_tls::TlsAcceptor> for TlsAcceptor {
 mod tests {
     use super::*;
     use crate::runtime::AsyncWriteExt;
+    use crate::test_util::{ca, localhost, serve, tcp};
     use crate::TlsConnector;
     use async_std::fs::File;
     use async_std::net::{TcpListener, TcpStream};
@@ -131,4 +415,142 @@ mod tests {
         stream.read_to_end(&mut res).await.unwrap();
         assert_eq!(res, b"hello");
     }
+
+    /// Returns the chain of the client as seen by the server, or `None` if the handshake failed.
+    #[cfg(all(
+        feature = "openssl",
+        not(any(target_os = "windows", target_vendor = "apple"))
+    ))]
+    async fn accept_client(
+        mode: ClientAuth,
+        identity: Option<crate::Identity>,
+    ) -> Option<Option<Vec<Certificate>>> {
+        let acceptor = localhost().client_auth(mode, vec![ca()]).build().unwrap();
+        let (addr, server) = serve(acceptor, |stream| async move {
+            stream.peer_certificate_chain().unwrap()
+        })
+        .await;
+
+        let mut connector = TlsConnector::new().danger_accept_invalid_certs(true);
+        if let Some(identity) = identity {
+            connector = connector.identity(identity);
+        }
+        // with TLS 1.3, a rejected client only notices when reading
+        if let Ok(mut stream) = connector.connect("localhost", tcp(addr).await).await {
+            let _ = stream.read_to_end(&mut Vec::new()).await;
+        }
+        server.await
+    }
+
+    #[cfg(all(
+        feature = "openssl",
+        not(any(target_os = "windows", target_vendor = "apple"))
+    ))]
+    #[async_std::test]
+    async fn client_auth() {
+        let cert = std::fs::read("tests/client.pem").unwrap();
+        let key = std::fs::read("tests/client-key.pem").unwrap();
+        let identity = crate::Identity::from_pkcs8(&cert, &key).unwrap();
+
+        let chain = accept_client(ClientAuth::Require, Some(identity))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(chain.len(), 2);
+        assert_eq!(
+            chain[0].to_der().unwrap(),
+            Certificate::from_pem(&cert).unwrap().to_der().unwrap()
+        );
+
+        assert!(accept_client(ClientAuth::Require, None).await.is_none());
+        assert!(accept_client(ClientAuth::Request, None)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[cfg(not(all(
+        feature = "openssl",
+        not(any(target_os = "windows", target_vendor = "apple"))
+    )))]
+    #[test]
+    fn client_auth_unsupported() {
+        let res = localhost()
+            .client_auth(ClientAuth::Require, vec![ca()])
+            .build();
+        assert!(matches!(res, Err(Error::Unsupported(_))));
+    }
+
+    async fn negotiate(
+        acceptor: TlsAcceptorBuilder,
+        protocols: &[&str],
+    ) -> (Option<Vec<u8>>, Option<Vec<u8>>) {
+        let acceptor = acceptor.build().unwrap();
+        let (addr, server) = serve(acceptor, |stream| async move {
+            stream.negotiated_alpn().unwrap()
+        })
+        .await;
+
+        let mut stream = TlsConnector::new()
+            .danger_accept_invalid_certs(true)
+            .request_alpns(protocols)
+            .connect("localhost", tcp(addr).await)
+            .await
+            .unwrap();
+        stream.read_to_end(&mut Vec::new()).await.unwrap();
+        (server.await.unwrap(), stream.negotiated_alpn().unwrap())
+    }
+
+    #[async_std::test]
+    async fn accept_alpns() {
+        let acceptor = || localhost().accept_alpns(&["h2", "http/1.1"]);
+        let h2 = Some(b"h2".to_vec());
+        assert_eq!(
+            negotiate(acceptor(), &["http/1.1", "h2"]).await,
+            (h2.clone(), h2)
+        );
+        assert_eq!(negotiate(acceptor(), &["imap"]).await, (None, None));
+        assert_eq!(negotiate(localhost(), &["h2"]).await, (None, None));
+    }
+
+    #[cfg(all(
+        feature = "openssl",
+        not(any(target_os = "windows", target_vendor = "apple"))
+    ))]
+    #[async_std::test]
+    async fn select_alpn() {
+        let acceptor = || {
+            localhost()
+                .accept_alpns(&["h2"])
+                .select_alpn(|offered| offered.iter().copied().find(|p| p.starts_with("imap")))
+        };
+        let imap = Some(b"imap".to_vec());
+        assert_eq!(
+            negotiate(acceptor(), &["h2", "imap", "smtp"]).await,
+            (imap.clone(), imap)
+        );
+        assert_eq!(negotiate(acceptor(), &["h2"]).await, (None, None));
+
+        // the preference list, through OpenSSL
+        let acceptor = localhost()
+            .client_auth(ClientAuth::Request, vec![])
+            .accept_alpns(&["smtp", "imap"]);
+        let smtp = Some(b"smtp".to_vec());
+        assert_eq!(
+            negotiate(acceptor, &["imap", "smtp"]).await,
+            (smtp.clone(), smtp)
+        );
+    }
+
+    #[cfg(not(all(
+        feature = "openssl",
+        not(any(target_os = "windows", target_vendor = "apple"))
+    )))]
+    #[test]
+    fn select_alpn_unsupported() {
+        let res = localhost()
+            .select_alpn(|offered| offered.first().copied())
+            .build();
+        assert!(matches!(res, Err(Error::Unsupported(_))));
+    }
 }
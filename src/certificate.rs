@@ -0,0 +1,287 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use x509_parser::oid_registry::{
+    OID_EC_P256, OID_KEY_TYPE_EC_PUBLIC_KEY, OID_NIST_EC_P384, OID_NIST_EC_P521,
+    OID_PKCS1_RSAENCRYPTION, OID_SIG_ED25519, OID_SIG_ED448,
+};
+use x509_parser::prelude::{
+    FromDer, GeneralName, ParsedExtension, SubjectPublicKeyInfo, X509Certificate,
+};
+use x509_parser::public_key::PublicKey;
+
+/// The parsed contents of an X.509 certificate.
+///
+/// # Example
+///
+/// ```no_run
+/// # #[cfg(feature = "runtime-async-std")]
+/// # fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> { async_std::task::block_on(async {
+/// #
+/// use async_std::net::TcpStream;
+/// use async_native_tls::SubjectAltName;
+///
+/// let stream = TcpStream::connect("google.com:443").await?;
+/// let stream = async_native_tls::connect("google.com", stream).await?;
+/// if let Some(info) = stream.peer_certificate_info()? {
+///     println!("issued by {} until {:?}", info.issuer, info.not_after);
+///     for name in &info.subject_alt_names {
+///         if let SubjectAltName::Dns(dns) = name {
+///             println!("valid for {}", dns);
+///         }
+///     }
+/// }
+/// #
+/// # Ok(()) }) }
+/// # #[cfg(feature = "runtime-tokio")]
+/// # fn main() {}
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CertificateInfo {
+    /// The subject distinguished name.
+    pub subject: String,
+    /// The first common name of the subject, if any.
+    pub common_name: Option<String>,
+    /// The issuer distinguished name.
+    pub issuer: String,
+    /// The serial number, as big-endian bytes.
+    pub serial: Vec<u8>,
+    /// The entries of the subject alternative name extension.
+    pub subject_alt_names: Vec<SubjectAltName>,
+    /// Start of the validity period.
+    pub not_before: SystemTime,
+    /// End of the validity period.
+    pub not_after: SystemTime,
+    /// The type of the certified public key.
+    pub key_type: KeyType,
+    /// SHA-256 digest of the DER-encoded certificate.
+    pub sha256_fingerprint: [u8; 32],
+    /// SHA-1 digest of the DER-encoded certificate.
+    pub sha1_fingerprint: [u8; 20],
+    /// SHA-256 digest of the DER-encoded subject public key info.
+    pub spki_sha256: [u8; 32],
+}
+
+/// An entry of the subject alternative name extension.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SubjectAltName {
+    /// A DNS name.
+    Dns(String),
+    /// An IP address.
+    Ip(IpAddr),
+    /// An email address.
+    Email(String),
+    /// A URI.
+    Uri(String),
+}
+
+impl fmt::Display for SubjectAltName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SubjectAltName::Dns(name) => f.write_str(name),
+            SubjectAltName::Ip(ip) => write!(f, "{}", ip),
+            SubjectAltName::Email(email) => f.write_str(email),
+            SubjectAltName::Uri(uri) => f.write_str(uri),
+        }
+    }
+}
+
+/// The type of a public key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyType {
+    /// RSA with the given modulus size in bits.
+    Rsa(usize),
+    /// ECDSA over the named curve.
+    Ec(String),
+    /// Ed25519.
+    Ed25519,
+    /// Ed448.
+    Ed448,
+    /// Any other algorithm, by OID.
+    Other(String),
+}
+
+/// An error returned from parsing a certificate.
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    /// NativeTls error.
+    #[error("NativeTls({0})")]
+    NativeTls(#[from] native_tls::Error),
+    /// The certificate is not valid DER-encoded X.509.
+    #[error("failed to parse certificate: {0}")]
+    Parse(String),
+}
+
+impl CertificateInfo {
+    /// Parses a DER-encoded certificate.
+    pub fn from_der(der: &[u8]) -> Result<Self, Error> {
+        let (_, cert) = X509Certificate::from_der(der).map_err(|e| Error::Parse(e.to_string()))?;
+        Ok(Self::from_x509(&cert, der))
+    }
+
+    /// Parses a certificate.
+    pub fn from_certificate(cert: &crate::Certificate) -> Result<Self, Error> {
+        Self::from_der(&cert.to_der()?)
+    }
+
+    pub(crate) fn from_x509(cert: &X509Certificate<'_>, der: &[u8]) -> Self {
+        let validity = cert.validity();
+        Self {
+            subject: cert.subject().to_string(),
+            common_name: cert
+                .subject()
+                .iter_common_name()
+                .next()
+                .and_then(|cn| cn.as_str().ok())
+                .map(String::from),
+            issuer: cert.issuer().to_string(),
+            serial: cert.raw_serial().to_vec(),
+            subject_alt_names: subject_alt_names(cert),
+            not_before: system_time(validity.not_before.timestamp()),
+            not_after: system_time(validity.not_after.timestamp()),
+            key_type: key_type(cert.public_key()),
+            sha256_fingerprint: Sha256::digest(der).into(),
+            sha1_fingerprint: Sha1::digest(der).into(),
+            spki_sha256: Sha256::digest(cert.public_key().raw).into(),
+        }
+    }
+
+    /// Returns the serial number as colon-separated hex, as printed by most tools.
+    pub fn serial_hex(&self) -> String {
+        hex(&self.serial)
+    }
+
+    /// Returns the DNS names of the subject alternative name extension.
+    pub fn dns_names(&self) -> impl Iterator<Item = &str> {
+        self.subject_alt_names.iter().filter_map(|name| match name {
+            SubjectAltName::Dns(dns) => Some(dns.as_str()),
+            _ => None,
+        })
+    }
+}
+
+/// Formats bytes as colon-separated uppercase hex.
+fn hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+pub(crate) fn system_time(timestamp: i64) -> SystemTime {
+    if timestamp >= 0 {
+        UNIX_EPOCH + Duration::from_secs(timestamp as u64)
+    } else {
+        UNIX_EPOCH - Duration::from_secs(timestamp.unsigned_abs())
+    }
+}
+
+fn subject_alt_names(cert: &X509Certificate<'_>) -> Vec<SubjectAltName> {
+    let mut names = vec![];
+    for ext in cert.extensions() {
+        if let ParsedExtension::SubjectAlternativeName(san) = ext.parsed_extension() {
+            for name in &san.general_names {
+                match name {
+                    GeneralName::DNSName(dns) => names.push(SubjectAltName::Dns(dns.to_string())),
+                    GeneralName::RFC822Name(email) => {
+                        names.push(SubjectAltName::Email(email.to_string()))
+                    }
+                    GeneralName::URI(uri) => names.push(SubjectAltName::Uri(uri.to_string())),
+                    GeneralName::IPAddress(ip) => {
+                        if let Some(ip) = ip_addr(ip) {
+                            names.push(SubjectAltName::Ip(ip));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+    names
+}
+
+fn ip_addr(bytes: &[u8]) -> Option<IpAddr> {
+    match bytes.len() {
+        4 => <[u8; 4]>::try_from(bytes).ok().map(Into::into),
+        16 => <[u8; 16]>::try_from(bytes).ok().map(Into::into),
+        _ => None,
+    }
+}
+
+fn key_type(spki: &SubjectPublicKeyInfo<'_>) -> KeyType {
+    let alg = &spki.algorithm.algorithm;
+    if *alg == OID_PKCS1_RSAENCRYPTION {
+        let bits = match spki.parsed() {
+            Ok(key @ PublicKey::RSA(_)) => key.key_size(),
+            _ => 0,
+        };
+        KeyType::Rsa(bits)
+    } else if *alg == OID_KEY_TYPE_EC_PUBLIC_KEY {
+        let curve = spki
+            .algorithm
+            .parameters
+            .as_ref()
+            .and_then(|params| params.as_oid().ok());
+        let name = match curve {
+            Some(oid) if oid == OID_EC_P256 => "P-256".to_string(),
+            Some(oid) if oid == OID_NIST_EC_P384 => "P-384".to_string(),
+            Some(oid) if oid == OID_NIST_EC_P521 => "P-521".to_string(),
+            Some(oid) => oid.to_id_string(),
+            None => "unknown".to_string(),
+        };
+        KeyType::Ec(name)
+    } else if *alg == OID_SIG_ED25519 {
+        KeyType::Ed25519
+    } else if *alg == OID_SIG_ED448 {
+        KeyType::Ed448
+    } else {
+        KeyType::Other(alg.to_id_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_certificate() {
+        let pem = std::fs::read("tests/localhost.pem").unwrap();
+        let cert = crate::Certificate::from_pem(&pem).unwrap();
+        let info = CertificateInfo::from_certificate(&cert).unwrap();
+
+        assert_eq!(info.subject, "O=async-native-tls, CN=localhost");
+        assert_eq!(info.common_name.as_deref(), Some("localhost"));
+        assert_eq!(
+            info.issuer,
+            "O=async-native-tls, CN=async-native-tls test CA"
+        );
+        assert_eq!(info.serial_hex(), "10:01");
+        assert_eq!(
+            info.subject_alt_names,
+            [
+                SubjectAltName::Dns("localhost".into()),
+                SubjectAltName::Ip([127, 0, 0, 1].into()),
+            ]
+        );
+        assert_eq!(info.dns_names().collect::<Vec<_>>(), ["localhost"]);
+        assert_eq!(info.key_type, KeyType::Ec("P-256".into()));
+        assert!(info.not_before < info.not_after);
+        assert_eq!(
+            info.sha256_fingerprint[..],
+            Sha256::digest(cert.to_der().unwrap())[..]
+        );
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        assert!(matches!(
+            CertificateInfo::from_der(b"not a certificate"),
+            Err(Error::Parse(_))
+        ));
+    }
+}
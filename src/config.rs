@@ -0,0 +1,650 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Deserializer};
+use zeroize::Zeroizing;
+
+use crate::roots::split_pem;
+use crate::{
+    Certificate, ConnectorPolicy, HostSettings, Identity, Protocol, TlsAcceptor, TlsConnector,
+};
+
+/// Client TLS settings, as found in a service configuration.
+///
+/// Every field is optional. The `danger_*` flags only take effect together with
+/// `allow_danger = true`, so they cannot be switched on by a stray line.
+///
+/// # Example
+///
+/// ```
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use async_native_tls::ConnectorConfig;
+///
+/// let config: ConnectorConfig = toml::from_str(r#"
+///     min_protocol = "tls1.2"
+///     alpn = ["h2", "http/1.1"]
+/// "#)?;
+/// let connector = config.build()?;
+/// # Ok(()) }
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConnectorConfig {
+    /// The minimum supported protocol version: `sslv3`, `tls1.0`, `tls1.1`, `tls1.2` or
+    /// `tls1.3`. Defaults to `tls1.2`.
+    #[serde(deserialize_with = "protocol")]
+    pub min_protocol: Option<Protocol>,
+    /// The maximum supported protocol version. Defaults to the newest version the backend
+    /// supports.
+    #[serde(deserialize_with = "protocol")]
+    pub max_protocol: Option<Protocol>,
+    /// Paths of PEM files with root certificates to trust, one or more per file.
+    pub root_certificates: Vec<PathBuf>,
+    /// Trust only `root_certificates`.
+    pub disable_built_in_roots: bool,
+    /// The client identity to authenticate with.
+    pub identity: Option<IdentityConfig>,
+    /// The protocols to request via ALPN.
+    pub alpn: Vec<String>,
+    /// Whether to use Server Name Indication. Defaults to `true`.
+    pub sni: Option<bool>,
+    /// Explicit opt-in to the `danger_*` flags.
+    pub allow_danger: bool,
+    /// Accept invalid certificates. Requires `allow_danger`.
+    pub danger_accept_invalid_certs: bool,
+    /// Accept certificates not matching the host name. Requires `allow_danger`.
+    pub danger_accept_invalid_hostnames: bool,
+}
+
+/// Server TLS settings, as found in a service configuration.
+///
+/// # Example
+///
+/// ```no_run
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use async_native_tls::AcceptorConfig;
+///
+/// let config: AcceptorConfig = toml::from_str(r#"
+///     min_protocol = "tls1.2"
+///
+///     [identity]
+///     pkcs12 = "/etc/service/identity.pfx"
+///     password = { env = "IDENTITY_PASSWORD" }
+/// "#)?;
+/// let acceptor = config.build()?;
+/// # Ok(()) }
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AcceptorConfig {
+    /// The server identity.
+    pub identity: IdentityConfig,
+    /// The minimum supported protocol version: `sslv3`, `tls1.0`, `tls1.1`, `tls1.2` or
+    /// `tls1.3`. Defaults to `tls1.2`.
+    #[serde(default, deserialize_with = "protocol")]
+    pub min_protocol: Option<Protocol>,
+    /// The maximum supported protocol version. Defaults to the newest version the backend
+    /// supports.
+    #[serde(default, deserialize_with = "protocol")]
+    pub max_protocol: Option<Protocol>,
+    /// The protocols to accept via ALPN, most preferred first.
+    #[serde(default)]
+    pub alpn: Vec<String>,
+}
+
+/// A [`ConnectorPolicy`], as found in a service configuration.
+///
+/// Hosts are keyed by the patterns described for [`ConnectorPolicy`].
+///
+/// # Example
+///
+/// ```no_run
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use async_native_tls::PolicyConfig;
+///
+/// let config: PolicyConfig = toml::from_str(r#"
+///     [default]
+///     min_protocol = "tls1.2"
+///
+///     [hosts.".internal.example.com"]
+///     root_certificates = ["/etc/service/internal-ca.pem"]
+///     disable_built_in_roots = true
+///
+///     [hosts."legacy.example.com"]
+///     min_protocol = "tls1.0"
+/// "#)?;
+/// let policy = config.build()?;
+/// # Ok(()) }
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PolicyConfig {
+    /// The settings for hosts matching no pattern.
+    pub default: HostConfig,
+    /// The settings for hosts matching each pattern.
+    pub hosts: BTreeMap<String, HostConfig>,
+}
+
+/// The [`HostSettings`] of a [`PolicyConfig`].
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HostConfig {
+    /// The minimum supported protocol version: `sslv3`, `tls1.0`, `tls1.1`, `tls1.2` or
+    /// `tls1.3`. Defaults to `tls1.2`.
+    #[serde(deserialize_with = "protocol")]
+    pub min_protocol: Option<Protocol>,
+    /// The maximum supported protocol version. Defaults to the newest version the backend
+    /// supports.
+    #[serde(deserialize_with = "protocol")]
+    pub max_protocol: Option<Protocol>,
+    /// Paths of PEM files with root certificates to trust, one or more per file.
+    pub root_certificates: Vec<PathBuf>,
+    /// Trust only `root_certificates`.
+    pub disable_built_in_roots: bool,
+    /// The client identity to authenticate with.
+    pub identity: Option<IdentityConfig>,
+    /// The protocols to request via ALPN.
+    pub alpn: Vec<String>,
+}
+
+/// Where to load an identity from: either `pkcs12` with a `password`, or `cert` and `key`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IdentityConfig {
+    /// Path of a DER-formatted PKCS #12 archive.
+    pub pkcs12: Option<PathBuf>,
+    /// The password of the PKCS #12 archive.
+    pub password: Option<PasswordSource>,
+    /// Path of a PEM-formatted certificate chain, leaf first.
+    pub cert: Option<PathBuf>,
+    /// Path of a PEM-formatted PKCS #8 private key.
+    pub key: Option<PathBuf>,
+}
+
+/// Where to read a password from.
+#[derive(Clone, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum PasswordSource {
+    /// The password itself.
+    Value(Zeroizing<String>),
+    /// The named environment variable.
+    Env(String),
+    /// The contents of a file, without a trailing newline.
+    File(PathBuf),
+}
+
+impl fmt::Debug for PasswordSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PasswordSource::Value(_) => f.write_str("Value(..)"),
+            PasswordSource::Env(name) => f.debug_tuple("Env").field(name).finish(),
+            PasswordSource::File(path) => f.debug_tuple("File").field(path).finish(),
+        }
+    }
+}
+
+/// An error returned from building a connector or acceptor from its configuration.
+///
+/// Every variant names the field at fault, e.g. `identity.password`.
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    /// The configuration is inconsistent.
+    #[error("{field}: {message}")]
+    Invalid {
+        /// The field at fault.
+        field: String,
+        /// What is wrong with it.
+        message: String,
+    },
+    /// A file named by the configuration could not be read.
+    #[error("{field}: failed to read {}: {source}", path.display())]
+    Io {
+        /// The field at fault.
+        field: String,
+        /// The file that could not be read.
+        path: PathBuf,
+        /// The underlying error.
+        source: io::Error,
+    },
+    /// The contents of a file or a setting were rejected by the TLS backend.
+    #[error("{field}: {source}")]
+    NativeTls {
+        /// The field at fault.
+        field: String,
+        /// The underlying error.
+        source: native_tls::Error,
+    },
+}
+
+impl ConnectorConfig {
+    /// Builds a connector with these settings.
+    pub fn build(&self) -> Result<TlsConnector, Error> {
+        check_protocols("min_protocol", self.min_protocol, self.max_protocol)?;
+        if !self.allow_danger {
+            for (field, set) in &[
+                (
+                    "danger_accept_invalid_certs",
+                    self.danger_accept_invalid_certs,
+                ),
+                (
+                    "danger_accept_invalid_hostnames",
+                    self.danger_accept_invalid_hostnames,
+                ),
+            ] {
+                if *set {
+                    return Err(invalid(field, "requires allow_danger = true"));
+                }
+            }
+        }
+        if self.disable_built_in_roots && self.root_certificates.is_empty() {
+            return Err(invalid(
+                "disable_built_in_roots",
+                "no root_certificates would be trusted",
+            ));
+        }
+
+        let mut connector = TlsConnector::new()
+            .disable_built_in_roots(self.disable_built_in_roots)
+            .use_sni(self.sni.unwrap_or(true))
+            .danger_accept_invalid_certs(self.danger_accept_invalid_certs)
+            .danger_accept_invalid_hostnames(self.danger_accept_invalid_hostnames);
+        // unset bounds keep the defaults of the connector rather than lifting them
+        if let Some(protocol) = self.min_protocol {
+            connector = connector.min_protocol_version(Some(protocol));
+        }
+        if let Some(protocol) = self.max_protocol {
+            connector = connector.max_protocol_version(Some(protocol));
+        }
+        for (i, path) in self.root_certificates.iter().enumerate() {
+            let field = format!("root_certificates[{}]", i);
+            for cert in read_certificates(&field, path)? {
+                connector = connector.add_root_certificate(cert);
+            }
+        }
+        if let Some(identity) = &self.identity {
+            connector = connector.identity(identity.load("identity")?);
+        }
+        if !self.alpn.is_empty() {
+            connector = connector.request_alpns(&alpn_protocols("alpn", &self.alpn)?);
+        }
+        Ok(connector)
+    }
+}
+
+impl AcceptorConfig {
+    /// Builds an acceptor with these settings.
+    pub fn build(&self) -> Result<TlsAcceptor, Error> {
+        check_protocols("min_protocol", self.min_protocol, self.max_protocol)?;
+        let alpn = alpn_protocols("alpn", &self.alpn)?;
+        let identity = self.identity.load("identity")?;
+        let mut builder = native_tls::TlsAcceptor::builder(identity);
+        if let Some(protocol) = self.min_protocol {
+            builder.min_protocol_version(Some(protocol));
+        }
+        if let Some(protocol) = self.max_protocol {
+            builder.max_protocol_version(Some(protocol));
+        }
+        let acceptor = builder
+            .accept_alpn(&alpn)
+            .build()
+            .map_err(|source| Error::NativeTls {
+                field: "identity".to_string(),
+                source,
+            })?;
+        Ok(acceptor.into())
+    }
+}
+
+impl PolicyConfig {
+    /// Builds the registry described, loading the files it names.
+    pub fn build(&self) -> Result<ConnectorPolicy, Error> {
+        let mut policy = ConnectorPolicy::new().default_settings(self.default.load("default")?);
+        for (pattern, host) in &self.hosts {
+            policy.insert(pattern, host.load(&format!("hosts.{:?}", pattern))?);
+        }
+        Ok(policy)
+    }
+}
+
+impl HostConfig {
+    /// Builds the settings described, loading the files they name.
+    pub fn build(&self) -> Result<HostSettings, Error> {
+        self.load("")
+    }
+
+    /// Loads the settings, naming fields relative to `field`.
+    fn load(&self, field: &str) -> Result<HostSettings, Error> {
+        let sub = |name: &str| match field {
+            "" => name.to_string(),
+            field => format!("{}.{}", field, name),
+        };
+        check_protocols(&sub("min_protocol"), self.min_protocol, self.max_protocol)?;
+        if self.disable_built_in_roots && self.root_certificates.is_empty() {
+            return Err(invalid(
+                &sub("disable_built_in_roots"),
+                "no root_certificates would be trusted",
+            ));
+        }
+        let mut root_certificates = vec![];
+        for (i, path) in self.root_certificates.iter().enumerate() {
+            let field = sub(&format!("root_certificates[{}]", i));
+            root_certificates.extend(read_certificates(&field, path)?);
+        }
+        let identity = match &self.identity {
+            Some(identity) => Some(identity.load(&sub("identity"))?),
+            None => None,
+        };
+        let alpn_protocols = alpn_protocols(&sub("alpn"), &self.alpn)?
+            .into_iter()
+            .map(String::from)
+            .collect();
+        Ok(HostSettings {
+            root_certificates,
+            disable_built_in_roots: self.disable_built_in_roots,
+            min_protocol_version: self.min_protocol,
+            max_protocol_version: self.max_protocol,
+            identity,
+            alpn_protocols,
+        })
+    }
+}
+
+impl IdentityConfig {
+    /// Loads the identity, naming fields relative to `field`.
+    fn load(&self, field: &str) -> Result<Identity, Error> {
+        let sub = |name: &str| format!("{}.{}", field, name);
+        let identity = match (&self.pkcs12, &self.cert, &self.key) {
+            (Some(path), None, None) => {
+                let password = match &self.password {
+                    Some(password) => password.read(&sub("password"))?,
+                    None => return Err(invalid(&sub("password"), "required with pkcs12")),
+                };
+                let der = read(&sub("pkcs12"), path)?;
+                Identity::from_pkcs12(&der, &password)
+                    .map_err(|source| native_tls_error(sub("pkcs12"), source))?
+            }
+            (None, Some(cert), Some(key)) => {
+                if self.password.is_some() {
+                    return Err(invalid(&sub("password"), "only supported with pkcs12"));
+                }
+                let cert = read(&sub("cert"), cert)?;
+                let key = read(&sub("key"), key)?;
+                Identity::from_pkcs8(&cert, &key)
+                    .map_err(|source| native_tls_error(sub("key"), source))?
+            }
+            (None, Some(_), None) => return Err(invalid(&sub("key"), "required with cert")),
+            (None, None, Some(_)) => return Err(invalid(&sub("cert"), "required with key")),
+            (None, None, None) => {
+                return Err(invalid(field, "either pkcs12 or cert and key are required"))
+            }
+            (Some(_), _, _) => {
+                return Err(invalid(
+                    &sub("pkcs12"),
+                    "cannot be combined with cert and key",
+                ))
+            }
+        };
+        Ok(identity)
+    }
+}
+
+impl PasswordSource {
+    fn read(&self, field: &str) -> Result<Zeroizing<String>, Error> {
+        match self {
+            PasswordSource::Value(password) => Ok(password.clone()),
+            PasswordSource::Env(name) => std::env::var(name)
+                .map(Zeroizing::new)
+                .map_err(|_| invalid(field, &format!("environment variable {} is not set", name))),
+            PasswordSource::File(path) => {
+                let contents = Zeroizing::new(read(field, path)?);
+                let password = std::str::from_utf8(&contents)
+                    .map_err(|_| invalid(field, "password file is not UTF-8"))?;
+                Ok(Zeroizing::new(
+                    password.trim_end_matches(&['\r', '\n'][..]).to_string(),
+                ))
+            }
+        }
+    }
+}
+
+fn check_protocols(field: &str, min: Option<Protocol>, max: Option<Protocol>) -> Result<(), Error> {
+    if let (Some(min), Some(max)) = (min.and_then(rank), max.and_then(rank)) {
+        if min > max {
+            return Err(invalid(field, "is newer than max_protocol"));
+        }
+    }
+    Ok(())
+}
+
+fn alpn_protocols<'a>(field: &str, alpn: &'a [String]) -> Result<Vec<&'a str>, Error> {
+    if let Some(i) = alpn.iter().position(|p| p.is_empty() || p.len() > 255) {
+        return Err(invalid(
+            &format!("{}[{}]", field, i),
+            "protocol names have to be 1 to 255 bytes long",
+        ));
+    }
+    Ok(alpn.iter().map(String::as_str).collect())
+}
+
+/// Orders the protocol versions, or `None` for versions added to native-tls since, which are
+/// left to the backend to check.
+fn rank(protocol: Protocol) -> Option<u8> {
+    match protocol {
+        Protocol::Sslv3 => Some(0),
+        Protocol::Tlsv10 => Some(1),
+        Protocol::Tlsv11 => Some(2),
+        Protocol::Tlsv12 => Some(3),
+        Protocol::Tlsv13 => Some(4),
+        _ => None,
+    }
+}
+
+fn protocol<'de, D>(deserializer: D) -> Result<Option<Protocol>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let name = match Option::<String>::deserialize(deserializer)? {
+        Some(name) => name,
+        None => return Ok(None),
+    };
+    let protocol = match name.to_ascii_lowercase().as_str() {
+        "sslv3" => Protocol::Sslv3,
+        "tls1.0" => Protocol::Tlsv10,
+        "tls1.1" => Protocol::Tlsv11,
+        "tls1.2" => Protocol::Tlsv12,
+        "tls1.3" => Protocol::Tlsv13,
+        _ => {
+            return Err(serde::de::Error::unknown_variant(
+                &name,
+                &["sslv3", "tls1.0", "tls1.1", "tls1.2", "tls1.3"],
+            ))
+        }
+    };
+    Ok(Some(protocol))
+}
+
+/// Reads every certificate of the PEM file at `path`.
+fn read_certificates(field: &str, path: &Path) -> Result<Vec<Certificate>, Error> {
+    let pem =
+        String::from_utf8(read(field, path)?).map_err(|_| invalid(field, "not a PEM file"))?;
+    let certs = split_pem(&pem)
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|message| invalid(field, &message))?;
+    if certs.is_empty() {
+        return Err(invalid(field, "no certificates found"));
+    }
+    Ok(certs)
+}
+
+fn read(field: &str, path: &Path) -> Result<Vec<u8>, Error> {
+    fs::read(path).map_err(|source| Error::Io {
+        field: field.to_string(),
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+fn invalid(field: &str, message: &str) -> Error {
+    Error::Invalid {
+        field: field.to_string(),
+        message: message.to_string(),
+    }
+}
+
+fn native_tls_error(field: String, source: native_tls::Error) -> Error {
+    Error::NativeTls { field, source }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(err: Error) -> String {
+        match err {
+            Error::Invalid { field, .. }
+            | Error::Io { field, .. }
+            | Error::NativeTls { field, .. } => field,
+        }
+    }
+
+    #[test]
+    fn connector() {
+        let config: ConnectorConfig = toml::from_str(
+            r#"
+            min_protocol = "tls1.2"
+            root_certificates = ["tests/ca.pem"]
+            alpn = ["h2"]
+
+            [identity]
+            cert = "tests/localhost.pem"
+            key = "tests/localhost-key.pem"
+            "#,
+        )
+        .unwrap();
+        assert!(config.build().is_ok());
+
+        let config: ConnectorConfig = toml::from_str("min_protocol = \"tls1.3\"").unwrap();
+        assert!(matches!(config.min_protocol, Some(Protocol::Tlsv13)));
+        assert!(config.build().is_ok());
+        let config: ConnectorConfig =
+            toml::from_str("min_protocol = \"tls1.3\"\nmax_protocol = \"tls1.2\"").unwrap();
+        assert_eq!(field(config.build().unwrap_err()), "min_protocol");
+
+        let err = toml::from_str::<ConnectorConfig>("min_protocol = \"tls0.9\"").unwrap_err();
+        assert!(err.to_string().contains("min_protocol"));
+        assert!(toml::from_str::<ConnectorConfig>("sni_name = true").is_err());
+    }
+
+    #[cfg(feature = "runtime-async-std")]
+    #[async_std::test]
+    async fn keeps_default_protocol_floor() {
+        use crate::test_util::offered_versions;
+
+        let config: ConnectorConfig = toml::from_str("").unwrap();
+        assert_eq!(
+            offered_versions(config.build().unwrap()).await,
+            [0x0304, 0x0303]
+        );
+        let config: ConnectorConfig = toml::from_str("min_protocol = \"tls1.0\"").unwrap();
+        assert!(offered_versions(config.build().unwrap())
+            .await
+            .contains(&0x0301));
+    }
+
+    #[test]
+    fn danger_requires_opt_in() {
+        let config: ConnectorConfig = toml::from_str("danger_accept_invalid_certs = true").unwrap();
+        assert_eq!(
+            config.build().unwrap_err().to_string(),
+            "danger_accept_invalid_certs: requires allow_danger = true"
+        );
+
+        let config: ConnectorConfig =
+            toml::from_str("danger_accept_invalid_certs = true\nallow_danger = true").unwrap();
+        assert!(config.build().is_ok());
+    }
+
+    #[test]
+    fn names_field_at_fault() {
+        let config: ConnectorConfig =
+            toml::from_str("root_certificates = [\"tests/ca.pem\", \"tests/missing.pem\"]")
+                .unwrap();
+        assert_eq!(field(config.build().unwrap_err()), "root_certificates[1]");
+
+        let config: AcceptorConfig =
+            toml::from_str("[identity]\npkcs12 = \"tests/identity.pfx\"").unwrap();
+        assert_eq!(field(config.build().unwrap_err()), "identity.password");
+
+        let config: AcceptorConfig = toml::from_str(
+            "[identity]\npkcs12 = \"tests/identity.pfx\"\npassword = { env = \"ASYNC_NATIVE_TLS_UNSET\" }",
+        )
+        .unwrap();
+        assert_eq!(field(config.build().unwrap_err()), "identity.password");
+
+        let config: AcceptorConfig =
+            toml::from_str("min_protocol = \"tls1.2\"\nmax_protocol = \"tls1.0\"\n[identity]\ncert = \"tests/localhost.pem\"").unwrap();
+        assert_eq!(field(config.build().unwrap_err()), "min_protocol");
+    }
+
+    #[test]
+    fn policy() {
+        let config: PolicyConfig = toml::from_str(
+            r#"
+            [default]
+            min_protocol = "tls1.2"
+
+            [hosts.".internal.example.com"]
+            root_certificates = ["tests/ca.pem"]
+            disable_built_in_roots = true
+
+            [hosts."mx.example.com"]
+            alpn = ["smtp"]
+
+            [hosts."mx.example.com".identity]
+            cert = "tests/localhost.pem"
+            key = "tests/localhost-key.pem"
+            "#,
+        )
+        .unwrap();
+        let policy = config.build().unwrap();
+        let settings = policy.resolve("db.internal.example.com");
+        assert_eq!(settings.root_certificates.len(), 1);
+        assert!(settings.disable_built_in_roots);
+        let settings = policy.resolve("mx.example.com");
+        assert_eq!(settings.alpn_protocols, ["smtp"]);
+        assert!(settings.identity.is_some());
+        let settings = policy.resolve("example.org");
+        assert!(matches!(
+            settings.min_protocol_version,
+            Some(Protocol::Tlsv12)
+        ));
+
+        let config: PolicyConfig = toml::from_str(
+            "[hosts.\"*.example.com\"]\nroot_certificates = [\"tests/missing.pem\"]",
+        )
+        .unwrap();
+        assert_eq!(
+            field(config.build().unwrap_err()),
+            "hosts.\"*.example.com\".root_certificates[0]"
+        );
+        assert!(toml::from_str::<PolicyConfig>("[hosts.a]\nsni = false").is_err());
+    }
+
+    #[test]
+    fn acceptor() {
+        let config: AcceptorConfig = toml::from_str(
+            "alpn = [\"h2\", \"http/1.1\"]\n[identity]\npkcs12 = \"tests/identity.pfx\"\npassword = { value = \"hello\" }",
+        )
+        .unwrap();
+        assert!(config.build().is_ok());
+
+        let config: AcceptorConfig =
+            toml::from_str("alpn = [\"h2\", \"\"]\n[identity]\ncert = \"tests/localhost.pem\"")
+                .unwrap();
+        assert_eq!(field(config.build().unwrap_err()), "alpn[1]");
+    }
+}
@@ -0,0 +1,540 @@
+use std::fmt;
+
+use sha2::{Digest, Sha256, Sha512};
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+use crate::certificate::{CertificateInfo, SubjectAltName};
+use crate::host::{matches_name, Host};
+use crate::runtime::{AsyncRead, AsyncWrite};
+use crate::{Certificate, ConnectError, TlsConnector, TlsStream};
+
+/// A TLSA resource record, as published in DNS for a service.
+///
+/// The numeric fields use the values assigned in [RFC 6698](https://tools.ietf.org/html/rfc6698).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsaRecord {
+    /// The certificate usage field.
+    pub usage: u8,
+    /// The selector field.
+    pub selector: u8,
+    /// The matching type field.
+    pub matching_type: u8,
+    /// The certificate association data.
+    pub data: Vec<u8>,
+}
+
+impl TlsaRecord {
+    /// Certificate usage PKIX-TA(0).
+    pub const PKIX_TA: u8 = 0;
+    /// Certificate usage PKIX-EE(1).
+    pub const PKIX_EE: u8 = 1;
+    /// Certificate usage DANE-TA(2).
+    pub const DANE_TA: u8 = 2;
+    /// Certificate usage DANE-EE(3).
+    pub const DANE_EE: u8 = 3;
+    /// Selector Cert(0), the full certificate.
+    pub const CERT: u8 = 0;
+    /// Selector SPKI(1), the subject public key info.
+    pub const SPKI: u8 = 1;
+    /// Matching type Full(0), the exact data.
+    pub const FULL: u8 = 0;
+    /// Matching type SHA2-256(1).
+    pub const SHA2_256: u8 = 1;
+    /// Matching type SHA2-512(2).
+    pub const SHA2_512: u8 = 2;
+
+    /// Create a new record from its fields.
+    pub fn new(usage: u8, selector: u8, matching_type: u8, data: impl Into<Vec<u8>>) -> Self {
+        Self {
+            usage,
+            selector,
+            matching_type,
+            data: data.into(),
+        }
+    }
+
+    /// Whether the record can be used for SMTP, as described in
+    /// [RFC 7672, section 3.1.3](https://tools.ietf.org/html/rfc7672#section-3.1.3).
+    pub fn is_usable(&self) -> bool {
+        matches!(self.usage, Self::DANE_TA | Self::DANE_EE)
+            && matches!(self.selector, Self::CERT | Self::SPKI)
+            && matches!(
+                self.matching_type,
+                Self::FULL | Self::SHA2_256 | Self::SHA2_512
+            )
+    }
+
+    /// Whether the record matches a DER-encoded certificate.
+    pub fn matches(&self, der: &[u8]) -> bool {
+        let cert = match X509Certificate::from_der(der) {
+            Ok((_, cert)) => cert,
+            Err(_) => return false,
+        };
+        let selected = match self.selector {
+            Self::CERT => der,
+            Self::SPKI => cert.public_key().raw,
+            _ => return false,
+        };
+        match self.matching_type {
+            Self::FULL => selected == self.data.as_slice(),
+            Self::SHA2_256 => Sha256::digest(selected)[..] == self.data[..],
+            Self::SHA2_512 => Sha512::digest(selected)[..] == self.data[..],
+            _ => false,
+        }
+    }
+}
+
+/// Verifies SMTP servers against TLSA records, following [RFC 7672](https://tools.ietf.org/html/rfc7672).
+///
+/// Looking up the TLSA records, with DNSSEC validation, is left to the caller.
+///
+/// DANE-EE(3) records are matched against the server certificate, without checking its names,
+/// expiry or issuer. DANE-TA(2) records are matched against the chain presented by the server,
+/// or carry the full trust anchor certificate with selector Cert(0) and matching type Full(0);
+/// the server certificate then has to chain to the anchor and be issued for one of the reference
+/// identifiers. Both kinds of records are tried, and any match authenticates the server, as
+/// required by [RFC 7672, section 2.1](https://tools.ietf.org/html/rfc7672#section-2.1).
+///
+/// native-tls does not expose the chain presented by the server, so with it only the server
+/// certificate and full anchor certificates are available. With the `openssl` feature, the
+/// connection is made with OpenSSL when the connector settings allow it. DANE-TA records are
+/// checked with OpenSSL, and never match on Windows and Apple platforms.
+///
+/// # Example
+///
+/// ```no_run
+/// # #[cfg(feature = "runtime-async-std")]
+/// # fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> { async_std::task::block_on(async {
+/// #
+/// use async_std::net::TcpStream;
+/// use async_native_tls::{DaneVerifier, TlsConnector, TlsaRecord};
+///
+/// // records for _25._tcp.mx.example.org, looked up by the caller
+/// let records = vec![TlsaRecord::new(3, 1, 1, vec![0; 32])];
+/// let stream = TcpStream::connect("mx.example.org:25").await?;
+/// // issue STARTTLS here
+/// let (stream, found) = DaneVerifier::new(records)
+///     .reference_name("example.org")
+///     .connect(TlsConnector::new(), "mx.example.org", stream)
+///     .await?;
+/// println!("authenticated by {}", found);
+/// #
+/// # Ok(()) }) }
+/// # #[cfg(feature = "runtime-tokio")]
+/// # fn main() {}
+/// ```
+#[derive(Debug, Clone)]
+pub struct DaneVerifier {
+    records: Vec<TlsaRecord>,
+    reference_names: Vec<String>,
+}
+
+/// How a connection was authenticated by a [`DaneVerifier`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DaneMatch {
+    /// The server certificate matched the DANE-EE record at this index.
+    EndEntity(usize),
+    /// The server certificate chained to the DANE-TA record at this index.
+    TrustAnchor(usize),
+}
+
+/// An error returned from verifying a connection with a [`DaneVerifier`].
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    /// Connecting failed.
+    #[error("Connect({0})")]
+    Connect(#[from] ConnectError),
+    /// None of the records is usable for SMTP, the destination should be treated as having no
+    /// TLSA records.
+    #[error("no usable TLSA records")]
+    NoUsableRecords,
+    /// The server did not present a certificate.
+    #[error("server presented no certificate")]
+    NoPeerCertificate,
+    /// The server certificate matched no DANE-EE record and chained to no DANE-TA anchor.
+    #[error("certificate of {host} matches none of {records} usable TLSA records")]
+    Mismatch {
+        /// The host connected to.
+        host: String,
+        /// Number of usable records checked.
+        records: usize,
+        /// The subject of the server certificate.
+        subject: String,
+        /// SHA-256 digest of the server certificate.
+        certificate_sha256: [u8; 32],
+        /// SHA-256 digest of the subject public key info of the server certificate.
+        spki_sha256: [u8; 32],
+    },
+    /// The server certificate chained to a DANE-TA anchor but was issued for other names.
+    #[error("certificate names {presented:?} match none of {reference:?}")]
+    NameMismatch {
+        /// The reference identifiers that were accepted.
+        reference: Vec<String>,
+        /// The names found in the certificate.
+        presented: Vec<String>,
+    },
+    /// The server certificate could not be parsed.
+    #[error("Certificate({0})")]
+    Certificate(#[from] crate::CertificateError),
+}
+
+impl DaneVerifier {
+    /// Create a new instance checking the given records.
+    pub fn new(records: impl IntoIterator<Item = TlsaRecord>) -> Self {
+        Self {
+            records: records.into_iter().collect(),
+            reference_names: vec![],
+        }
+    }
+
+    /// Adds a name the server certificate may be issued for when matching DANE-TA records.
+    ///
+    /// The host connected to is always accepted. [RFC 7672, section
+    /// 3.2.2](https://tools.ietf.org/html/rfc7672#section-3.2.2) also allows the TLSA base
+    /// domain, if it differs from the MX host after CNAME expansion, and the next-hop domain.
+    pub fn reference_name(mut self, name: impl Into<String>) -> Self {
+        self.reference_names.push(name.into());
+        self
+    }
+
+    /// Connects to `host` and verifies the server against the records.
+    ///
+    /// The certificate validation settings of `connector` are replaced as required by the
+    /// records; other settings, such as the client identity, are kept. [RFC 7672, section
+    /// 8.1](https://tools.ietf.org/html/rfc7672#section-8.1) requires SNI, so keep
+    /// [`TlsConnector::use_sni`] enabled, as it is by default.
+    pub async fn connect<S>(
+        &self,
+        connector: TlsConnector,
+        host: impl Into<Host>,
+        stream: S,
+    ) -> Result<(TlsStream<S>, DaneMatch), Error>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let host = host.into().as_string();
+        let usable = self.usable();
+        if usable.is_empty() {
+            return Err(Error::NoUsableRecords);
+        }
+
+        let connector = connector
+            .danger_accept_invalid_certs(true)
+            .danger_accept_invalid_hostnames(true);
+        #[cfg(all(
+            feature = "openssl",
+            not(any(target_os = "windows", target_vendor = "apple"))
+        ))]
+        let connector = {
+            // native-tls only exposes the server certificate
+            let mut connector = connector;
+            connector.openssl_mut().peer_chain = true;
+            connector
+        };
+
+        let stream = connector.connect(host.as_str(), stream).await?;
+        // with verification disabled, the verified chain may hold certificates of the trust
+        // store that the server never presented
+        let chain = stream
+            .presented_certificate_chain()
+            .map_err(ConnectError::from)?
+            .unwrap_or_default()
+            .iter()
+            .map(Certificate::to_der)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(ConnectError::from)?;
+        let found = self.verify_chain(&host, &chain)?;
+        Ok((stream, found))
+    }
+
+    /// Matches the DER-encoded chain presented by the server, starting with its certificate,
+    /// against the DANE-EE and DANE-TA records.
+    pub fn verify_chain(&self, host: &str, chain: &[Vec<u8>]) -> Result<DaneMatch, Error> {
+        let leaf = chain.first().ok_or(Error::NoPeerCertificate)?;
+        let mismatch = match self.verify_end_entity(host, leaf) {
+            Err(err @ Error::Mismatch { .. }) => err,
+            res => return res,
+        };
+        match self.trust_anchor(chain) {
+            Some(i) => {
+                self.verify_names(host, leaf)?;
+                Ok(DaneMatch::TrustAnchor(i))
+            }
+            None => Err(mismatch),
+        }
+    }
+
+    /// Matches a DER-encoded server certificate against the DANE-EE records.
+    pub fn verify_end_entity(&self, host: &str, der: &[u8]) -> Result<DaneMatch, Error> {
+        let usable = self.usable();
+        if usable.is_empty() {
+            return Err(Error::NoUsableRecords);
+        }
+        let found = usable
+            .iter()
+            .find(|(_, record)| record.usage == TlsaRecord::DANE_EE && record.matches(der));
+        if let Some((i, _)) = found {
+            return Ok(DaneMatch::EndEntity(*i));
+        }
+
+        let info = CertificateInfo::from_der(der)?;
+        Err(Error::Mismatch {
+            host: host.to_string(),
+            records: usable.len(),
+            subject: info.subject,
+            certificate_sha256: info.sha256_fingerprint,
+            spki_sha256: info.spki_sha256,
+        })
+    }
+
+    /// Checks that a DER-encoded server certificate is issued for one of the reference names.
+    pub fn verify_names(&self, host: &str, der: &[u8]) -> Result<(), Error> {
+        let info = CertificateInfo::from_der(der)?;
+        let reference = std::iter::once(host)
+            .chain(self.reference_names.iter().map(String::as_str))
+            .map(|name| name.trim_end_matches('.').to_ascii_lowercase())
+            .collect::<Vec<_>>();
+        let presented = info
+            .subject_alt_names
+            .iter()
+            .filter_map(|name| match name {
+                SubjectAltName::Dns(dns) => Some(dns.to_ascii_lowercase()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        let matched = presented
+            .iter()
+            .any(|pattern| reference.iter().any(|name| matches_name(pattern, name)));
+        if matched {
+            Ok(())
+        } else {
+            Err(Error::NameMismatch {
+                reference,
+                presented,
+            })
+        }
+    }
+
+    fn usable(&self) -> Vec<(usize, &TlsaRecord)> {
+        self.records
+            .iter()
+            .enumerate()
+            .filter(|(_, record)| record.is_usable())
+            .collect()
+    }
+
+    /// Finds the DANE-TA record whose anchor the server certificate chains to.
+    #[cfg(not(any(target_os = "windows", target_vendor = "apple")))]
+    fn trust_anchor(&self, chain: &[Vec<u8>]) -> Option<usize> {
+        use openssl::x509::X509;
+
+        let parsed = chain
+            .iter()
+            .map(|der| X509::from_der(der))
+            .collect::<Result<Vec<_>, _>>()
+            .ok()?;
+        let (leaf, untrusted) = parsed.split_first()?;
+        self.usable()
+            .into_iter()
+            .filter(|(_, record)| record.usage == TlsaRecord::DANE_TA)
+            .find(|(_, record)| {
+                let anchor = match chain.iter().position(|der| record.matches(der)) {
+                    Some(k) => parsed[k].clone(),
+                    // a full anchor certificate need not be presented by the server
+                    None if record.selector == TlsaRecord::CERT
+                        && record.matching_type == TlsaRecord::FULL =>
+                    {
+                        match X509::from_der(&record.data) {
+                            Ok(anchor) => anchor,
+                            Err(_) => return false,
+                        }
+                    }
+                    None => return false,
+                };
+                chains_to(leaf, untrusted, anchor).unwrap_or(false)
+            })
+            .map(|(i, _)| i)
+    }
+
+    #[cfg(any(target_os = "windows", target_vendor = "apple"))]
+    fn trust_anchor(&self, _chain: &[Vec<u8>]) -> Option<usize> {
+        None
+    }
+}
+
+/// Whether `leaf` chains to `anchor`, possibly through the `untrusted` certificates.
+#[cfg(not(any(target_os = "windows", target_vendor = "apple")))]
+fn chains_to(
+    leaf: &openssl::x509::X509Ref,
+    untrusted: &[openssl::x509::X509],
+    anchor: openssl::x509::X509,
+) -> Result<bool, openssl::error::ErrorStack> {
+    use openssl::stack::Stack;
+    use openssl::x509::store::X509StoreBuilder;
+    use openssl::x509::verify::X509VerifyFlags;
+    use openssl::x509::X509StoreContext;
+
+    let mut store = X509StoreBuilder::new()?;
+    store.add_cert(anchor)?;
+    // the anchor is trusted even when it is not self-signed
+    store.set_flags(X509VerifyFlags::PARTIAL_CHAIN)?;
+    let store = store.build();
+    let mut stack = Stack::new()?;
+    for cert in untrusted {
+        stack.push(cert.clone())?;
+    }
+    X509StoreContext::new()?.init(&store, leaf, &stack, |ctx| ctx.verify_cert())
+}
+
+impl fmt::Display for DaneMatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DaneMatch::EndEntity(i) => write!(f, "DANE-EE record {}", i),
+            DaneMatch::TrustAnchor(i) => write!(f, "DANE-TA record {}", i),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "runtime-async-std"))]
+mod tests {
+    use super::*;
+    use crate::test_util::{hello, localhost, pem_der, tcp};
+
+    async fn connect(verifier: DaneVerifier, host: &str) -> Result<DaneMatch, Error> {
+        let addr = hello(localhost().build().unwrap()).await;
+        let stream = tcp(addr).await;
+        let (_, found) = verifier.connect(TlsConnector::new(), host, stream).await?;
+        Ok(found)
+    }
+
+    #[async_std::test]
+    async fn dane_ee() {
+        let leaf = pem_der("tests/localhost.pem");
+        let spki = X509Certificate::from_der(&leaf)
+            .unwrap()
+            .1
+            .public_key()
+            .raw
+            .to_vec();
+        let records = vec![
+            TlsaRecord::new(1, 1, 1, Sha256::digest(&spki).to_vec()),
+            TlsaRecord::new(3, 0, 1, vec![0; 32]),
+            TlsaRecord::new(3, 1, 2, Sha512::digest(&spki).to_vec()),
+        ];
+        let found = connect(DaneVerifier::new(records), "mx.example.org").await;
+        assert_eq!(found.unwrap(), DaneMatch::EndEntity(2));
+
+        let records = vec![TlsaRecord::new(3, 1, 1, vec![0; 32])];
+        let res = connect(DaneVerifier::new(records), "mx.example.org").await;
+        assert!(matches!(res, Err(Error::Mismatch { records: 1, .. })));
+    }
+
+    #[cfg(not(any(target_os = "windows", target_vendor = "apple")))]
+    #[async_std::test]
+    async fn dane_ta() {
+        let records = vec![TlsaRecord::new(2, 0, 0, pem_der("tests/ca.pem"))];
+        let found = connect(DaneVerifier::new(records.clone()), "localhost").await;
+        assert_eq!(found.unwrap(), DaneMatch::TrustAnchor(0));
+
+        let res = connect(DaneVerifier::new(records.clone()), "mx.example.org").await;
+        assert!(matches!(res, Err(Error::NameMismatch { .. })));
+
+        let verifier = DaneVerifier::new(records).reference_name("localhost");
+        let found = connect(verifier, "mx.example.org").await;
+        assert_eq!(found.unwrap(), DaneMatch::TrustAnchor(0));
+    }
+
+    #[async_std::test]
+    async fn dane_ta_untrusted() {
+        let records = vec![TlsaRecord::new(2, 0, 0, pem_der("tests/public.pem"))];
+        let res = connect(DaneVerifier::new(records), "localhost").await;
+        assert!(matches!(res, Err(Error::Mismatch { records: 1, .. })));
+    }
+
+    #[cfg(not(any(target_os = "windows", target_vendor = "apple")))]
+    fn spki_sha256(path: &str) -> Vec<u8> {
+        let der = pem_der(path);
+        let (_, cert) = X509Certificate::from_der(&der).unwrap();
+        Sha256::digest(cert.public_key().raw).to_vec()
+    }
+
+    #[cfg(not(any(target_os = "windows", target_vendor = "apple")))]
+    #[test]
+    fn dane_ta_digest() {
+        let chain = vec![pem_der("tests/localhost.pem"), pem_der("tests/ca.pem")];
+        let records = vec![TlsaRecord::new(2, 1, 1, spki_sha256("tests/ca.pem"))];
+        let verifier = DaneVerifier::new(records);
+        let found = verifier.verify_chain("localhost", &chain);
+        assert_eq!(found.unwrap(), DaneMatch::TrustAnchor(0));
+
+        // the anchor has to be presented by the server
+        let res = verifier.verify_chain("localhost", &chain[..1]);
+        assert!(matches!(res, Err(Error::Mismatch { .. })));
+
+        let res = verifier.verify_chain("mx.example.org", &chain);
+        assert!(matches!(res, Err(Error::NameMismatch { .. })));
+
+        let records = vec![TlsaRecord::new(2, 0, 1, spki_sha256("tests/public.pem"))];
+        let res = DaneVerifier::new(records).verify_chain("localhost", &chain);
+        assert!(matches!(res, Err(Error::Mismatch { .. })));
+    }
+
+    #[cfg(not(any(target_os = "windows", target_vendor = "apple")))]
+    #[test]
+    fn mixed_records() {
+        let chain = vec![pem_der("tests/localhost.pem"), pem_der("tests/ca.pem")];
+        let records = vec![
+            TlsaRecord::new(3, 1, 1, vec![0; 32]),
+            TlsaRecord::new(2, 1, 1, spki_sha256("tests/ca.pem")),
+        ];
+        let found = DaneVerifier::new(records).verify_chain("localhost", &chain);
+        assert_eq!(found.unwrap(), DaneMatch::TrustAnchor(1));
+
+        let records = vec![
+            TlsaRecord::new(2, 1, 1, vec![0; 32]),
+            TlsaRecord::new(3, 1, 1, spki_sha256("tests/localhost.pem")),
+        ];
+        let found = DaneVerifier::new(records).verify_chain("mx.example.org", &chain);
+        assert_eq!(found.unwrap(), DaneMatch::EndEntity(1));
+    }
+
+    #[cfg(all(
+        feature = "openssl",
+        not(any(target_os = "windows", target_vendor = "apple"))
+    ))]
+    #[async_std::test]
+    async fn dane_ta_presented_chain() {
+        let mut chain = std::fs::read("tests/localhost.pem").unwrap();
+        chain.extend(std::fs::read("tests/ca.pem").unwrap());
+        let key = std::fs::read("tests/localhost-key.pem").unwrap();
+        let acceptor = crate::TlsAcceptorBuilder::from_pkcs8(chain, key);
+        let addr = hello(acceptor.build().unwrap()).await;
+
+        let records = vec![
+            TlsaRecord::new(3, 1, 1, vec![0; 32]),
+            TlsaRecord::new(2, 1, 1, spki_sha256("tests/ca.pem")),
+        ];
+        let verifier = DaneVerifier::new(records);
+        let (_, found) = verifier
+            .connect(TlsConnector::new(), "localhost", tcp(addr).await)
+            .await
+            .unwrap();
+        assert_eq!(found, DaneMatch::TrustAnchor(1));
+
+        // trusted certificates the server did not present are no anchors
+        let addr = hello(localhost().build().unwrap()).await;
+        let connector = TlsConnector::new().add_root_certificate(crate::test_util::ca());
+        let res = verifier
+            .connect(connector, "localhost", tcp(addr).await)
+            .await;
+        assert!(matches!(res, Err(Error::Mismatch { .. })));
+    }
+
+    #[async_std::test]
+    async fn no_usable_records() {
+        let records = vec![TlsaRecord::new(0, 0, 1, vec![0; 32])];
+        let res = connect(DaneVerifier::new(records), "localhost").await;
+        assert!(matches!(res, Err(Error::NoUsableRecords)));
+    }
+}
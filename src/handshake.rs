@@ -97,3 +97,86 @@ impl<S: AsyncRead + AsyncWrite + Unpin> Future for MidHandshake<S> {
         }
     }
 }
+
+#[cfg(all(
+    feature = "openssl",
+    not(any(target_os = "windows", target_vendor = "apple"))
+))]
+pub(crate) use self::openssl_handshake::handshake as openssl_handshake;
+
+#[cfg(all(
+    feature = "openssl",
+    not(any(target_os = "windows", target_vendor = "apple"))
+))]
+mod openssl_handshake {
+    use std::future::Future;
+    use std::io::{Read, Write};
+    use std::marker::Unpin;
+    use std::pin::Pin;
+    use std::ptr::null_mut;
+    use std::task::{Context, Poll};
+
+    use openssl::ssl::{Error, HandshakeError, MidHandshakeSslStream, SslStream};
+
+    use crate::runtime::{AsyncRead, AsyncWrite};
+    use crate::std_adapter::StdAdapter;
+    use crate::TlsStream;
+
+    pub(crate) async fn handshake<F, S>(f: F, stream: S) -> Result<TlsStream<S>, Error>
+    where
+        F: FnOnce(StdAdapter<S>) -> Result<SslStream<StdAdapter<S>>, HandshakeError<StdAdapter<S>>>
+            + Unpin,
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        Handshake {
+            start: Some((f, stream)),
+            mid: None,
+        }
+        .await
+    }
+
+    struct Handshake<F, S> {
+        start: Option<(F, S)>,
+        mid: Option<MidHandshakeSslStream<StdAdapter<S>>>,
+    }
+
+    impl<F, S> Future for Handshake<F, S>
+    where
+        F: FnOnce(StdAdapter<S>) -> Result<SslStream<StdAdapter<S>>, HandshakeError<StdAdapter<S>>>
+            + Unpin,
+        S: Unpin,
+        StdAdapter<S>: Read + Write,
+    {
+        type Output = Result<TlsStream<S>, Error>;
+
+        fn poll(self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Self::Output> {
+            let this = self.get_mut();
+            let context = ctx as *mut _ as *mut ();
+            let result = match this.start.take() {
+                Some((f, stream)) => f(StdAdapter {
+                    inner: stream,
+                    context,
+                }),
+                None => {
+                    let mut s = this.mid.take().expect("future polled after completion");
+                    s.get_mut().context = context;
+                    s.handshake()
+                }
+            };
+
+            match result {
+                Ok(mut s) => {
+                    s.get_mut().context = null_mut();
+                    Poll::Ready(Ok(TlsStream::from_openssl(s)))
+                }
+                Err(HandshakeError::WouldBlock(mut s)) => {
+                    s.get_mut().context = null_mut();
+                    this.mid = Some(s);
+                    Poll::Pending
+                }
+                Err(HandshakeError::Failure(s)) => Poll::Ready(Err(s.into_error())),
+                Err(HandshakeError::SetupFailure(e)) => Poll::Ready(Err(e.into())),
+            }
+        }
+    }
+}
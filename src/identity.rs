@@ -0,0 +1,464 @@
+use std::fmt;
+use std::marker::Unpin;
+use std::time::{Duration, SystemTime};
+
+use x509_parser::der_parser::ber::BerObject;
+use x509_parser::der_parser::parse_der;
+use x509_parser::oid_registry::{OID_KEY_TYPE_EC_PUBLIC_KEY, OID_PKCS1_RSAENCRYPTION};
+use x509_parser::prelude::{FromDer, Pem, SubjectPublicKeyInfo, X509Certificate};
+
+use crate::certificate::{CertificateInfo, KeyType, SubjectAltName};
+use crate::runtime::{AsyncRead, AsyncReadExt};
+use crate::TlsAcceptor;
+
+/// Loads an identity and checks it before it is put to use.
+///
+/// The leaf certificate of the chain is parsed and summarized in an [`IdentityReport`]. Loading
+/// fails if the certificate is expired or not yet valid, or if the private key does not belong
+/// to it. Certificates expiring within the configured window produce a warning, or an error with
+/// [`IdentityLoader::reject_expiring`].
+///
+/// # Example
+///
+/// ```no_run
+/// # #[cfg(feature = "runtime-async-std")]
+/// # fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> { async_std::task::block_on(async {
+/// #
+/// use std::time::Duration;
+/// use async_std::fs::File;
+/// use async_native_tls::IdentityLoader;
+///
+/// let cert = File::open("cert.pem").await?;
+/// let key = File::open("key.pem").await?;
+/// let loaded = IdentityLoader::new()
+///     .expiry_window(Duration::from_secs(14 * 24 * 60 * 60))
+///     .read_pem(cert, key)
+///     .await?;
+/// for warning in &loaded.report.warnings {
+///     eprintln!("{}: {}", loaded.report.subject, warning);
+/// }
+/// let acceptor = loaded.into_acceptor()?;
+/// #
+/// # Ok(()) }) }
+/// # #[cfg(feature = "runtime-tokio")]
+/// # fn main() {}
+/// ```
+#[derive(Debug, Clone)]
+pub struct IdentityLoader {
+    expiry_window: Duration,
+    reject_expiring: bool,
+    now: Option<SystemTime>,
+}
+
+/// An error returned from loading an identity with [`IdentityLoader`].
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    /// NativeTls error.
+    #[error("NativeTls({0})")]
+    NativeTls(#[from] native_tls::Error),
+    /// Io error.
+    #[error("Io({0})")]
+    Io(#[from] std::io::Error),
+    /// The certificate chain or private key could not be parsed.
+    #[error("failed to parse identity: {0}")]
+    Parse(String),
+    /// The chain does not contain any certificate.
+    #[error("identity contains no certificate")]
+    EmptyChain,
+    /// The private key does not belong to the leaf certificate.
+    #[error("private key does not match the certificate")]
+    KeyMismatch,
+    /// The leaf certificate is not valid yet.
+    #[error("certificate is not valid before {0:?}")]
+    NotYetValid(SystemTime),
+    /// The leaf certificate has expired.
+    #[error("certificate expired at {0:?}")]
+    Expired(SystemTime),
+    /// The leaf certificate expires within the configured window.
+    #[error("certificate expires at {0:?}, within the configured window")]
+    ExpiresSoon(SystemTime),
+    /// The identity format cannot be inspected on this platform.
+    #[error("inspecting this identity format is not supported on this platform")]
+    Unsupported,
+}
+
+/// A summary of the leaf certificate of a loaded identity.
+#[derive(Debug, Clone)]
+pub struct IdentityReport {
+    /// The subject distinguished name.
+    pub subject: String,
+    /// The entries of the subject alternative name extension.
+    pub subject_alt_names: Vec<SubjectAltName>,
+    /// Start of the validity period.
+    pub not_before: SystemTime,
+    /// End of the validity period.
+    pub not_after: SystemTime,
+    /// The type of the certified public key.
+    pub key_type: KeyType,
+    /// Number of certificates in the chain, including the leaf.
+    pub chain_len: usize,
+    /// Whether the private key was verified to match the certificate.
+    ///
+    /// Some key formats do not carry enough information to be checked without performing a
+    /// signature, in which case this is `false` and a mismatch surfaces during the handshake.
+    pub key_verified: bool,
+    /// Problems that did not prevent loading.
+    pub warnings: Vec<Warning>,
+}
+
+/// A problem found while loading an identity that did not prevent loading.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Warning {
+    /// The leaf certificate expires within the configured window.
+    ExpiresSoon(SystemTime),
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Warning::ExpiresSoon(at) => write!(f, "certificate expires soon, at {:?}", at),
+        }
+    }
+}
+
+/// An identity that passed the checks of an [`IdentityLoader`].
+pub struct LoadedIdentity {
+    /// The identity, ready to be used by an acceptor or connector.
+    pub identity: native_tls::Identity,
+    /// What was found while loading it.
+    pub report: IdentityReport,
+}
+
+impl LoadedIdentity {
+    /// Builds an acceptor serving this identity.
+    pub fn into_acceptor(self) -> Result<TlsAcceptor, native_tls::Error> {
+        Ok(native_tls::TlsAcceptor::new(self.identity)?.into())
+    }
+}
+
+impl fmt::Debug for LoadedIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LoadedIdentity")
+            .field("report", &self.report)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for IdentityLoader {
+    fn default() -> Self {
+        IdentityLoader::new()
+    }
+}
+
+impl IdentityLoader {
+    /// Create a new instance warning about certificates that expire within 30 days.
+    pub fn new() -> Self {
+        Self {
+            expiry_window: Duration::from_secs(30 * 24 * 60 * 60),
+            reject_expiring: false,
+            now: None,
+        }
+    }
+
+    /// Sets how long before its expiry a certificate is reported.
+    ///
+    /// Defaults to 30 days.
+    pub fn expiry_window(mut self, window: Duration) -> Self {
+        self.expiry_window = window;
+        self
+    }
+
+    /// Controls whether certificates expiring within the window fail to load.
+    ///
+    /// Defaults to `false`, which only adds a warning to the report.
+    pub fn reject_expiring(mut self, reject: bool) -> Self {
+        self.reject_expiring = reject;
+        self
+    }
+
+    /// Sets the time the validity period is checked against.
+    ///
+    /// Defaults to the current time.
+    pub fn now(mut self, now: SystemTime) -> Self {
+        self.now = Some(now);
+        self
+    }
+
+    /// Loads a PEM-formatted certificate chain, leaf first, and a PEM-formatted PKCS #8 key.
+    pub fn pem(&self, cert: &[u8], key: &[u8]) -> Result<LoadedIdentity, Error> {
+        let chain = Pem::iter_from_buffer(cert)
+            .map(|pem| pem.map(|pem| pem.contents))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| Error::Parse(e.to_string()))?;
+        let key_der = Pem::iter_from_buffer(key)
+            .next()
+            .ok_or_else(|| Error::Parse("no private key found".into()))?
+            .map_err(|e| Error::Parse(e.to_string()))?
+            .contents;
+
+        let report = self.inspect(&chain, Some(&key_der))?;
+        let identity = native_tls::Identity::from_pkcs8(cert, key)?;
+        Ok(LoadedIdentity { identity, report })
+    }
+
+    /// Loads a DER-formatted PKCS #12 archive.
+    ///
+    /// Inspecting the archive requires the OpenSSL backend; other platforms return
+    /// [`Error::Unsupported`].
+    pub fn pkcs12(&self, der: &[u8], password: &str) -> Result<LoadedIdentity, Error> {
+        let (chain, key) = pkcs12_contents(der, password)?;
+        let report = self.inspect(&chain, key.as_deref())?;
+        let identity = native_tls::Identity::from_pkcs12(der, password)?;
+        Ok(LoadedIdentity { identity, report })
+    }
+
+    /// Reads and loads a PEM certificate chain and key, as in [`IdentityLoader::pem`].
+    pub async fn read_pem<C, K>(&self, mut cert: C, mut key: K) -> Result<LoadedIdentity, Error>
+    where
+        C: AsyncRead + Unpin,
+        K: AsyncRead + Unpin,
+    {
+        let mut cert_pem = vec![];
+        cert.read_to_end(&mut cert_pem).await?;
+        let mut key_pem = vec![];
+        key.read_to_end(&mut key_pem).await?;
+        self.pem(&cert_pem, &key_pem)
+    }
+
+    /// Reads and loads a PKCS #12 archive, as in [`IdentityLoader::pkcs12`].
+    pub async fn read_pkcs12<R, S>(&self, mut file: R, password: S) -> Result<LoadedIdentity, Error>
+    where
+        R: AsyncRead + Unpin,
+        S: AsRef<str>,
+    {
+        let mut der = vec![];
+        file.read_to_end(&mut der).await?;
+        self.pkcs12(&der, password.as_ref())
+    }
+
+    fn inspect(&self, chain: &[Vec<u8>], key: Option<&[u8]>) -> Result<IdentityReport, Error> {
+        let leaf = chain.first().ok_or(Error::EmptyChain)?;
+        let (_, cert) = X509Certificate::from_der(leaf).map_err(|e| Error::Parse(e.to_string()))?;
+
+        let key_verified = match key.map(|key| key_matches(cert.public_key(), key)) {
+            Some(Some(false)) => return Err(Error::KeyMismatch),
+            Some(Some(true)) => true,
+            _ => false,
+        };
+
+        let info = CertificateInfo::from_x509(&cert, leaf);
+        let (not_before, not_after) = (info.not_before, info.not_after);
+        let now = self.now.unwrap_or_else(SystemTime::now);
+        let mut warnings = vec![];
+        if now < not_before {
+            return Err(Error::NotYetValid(not_before));
+        }
+        if now > not_after {
+            return Err(Error::Expired(not_after));
+        }
+        // a window reaching beyond the representable times covers any expiry
+        let expires_soon = match now.checked_add(self.expiry_window) {
+            Some(end) => end > not_after,
+            None => true,
+        };
+        if expires_soon {
+            if self.reject_expiring {
+                return Err(Error::ExpiresSoon(not_after));
+            }
+            warnings.push(Warning::ExpiresSoon(not_after));
+        }
+
+        Ok(IdentityReport {
+            subject: info.subject,
+            subject_alt_names: info.subject_alt_names,
+            not_before,
+            not_after,
+            key_type: info.key_type,
+            chain_len: chain.len(),
+            key_verified,
+            warnings,
+        })
+    }
+}
+
+/// Compares the public half stored in a PKCS #8 private key with a certificate's public key.
+///
+/// Returns `None` if the key does not carry its public half in a form that can be compared.
+fn key_matches(spki: &SubjectPublicKeyInfo<'_>, pkcs8: &[u8]) -> Option<bool> {
+    let (_, info) = parse_der(pkcs8).ok()?;
+    let info = info.as_sequence().ok()?;
+    let alg = info.get(1)?.as_sequence().ok()?;
+    if alg.first()?.as_oid().ok()? != &spki.algorithm.algorithm {
+        return Some(false);
+    }
+    let private_key = info.get(2)?.as_slice().ok()?;
+    let public_key = spki.subject_public_key.data.as_ref();
+
+    let alg = &spki.algorithm.algorithm;
+    if *alg == OID_PKCS1_RSAENCRYPTION {
+        // RSAPrivateKey ::= SEQUENCE { version, modulus, publicExponent, ... }
+        let (_, private_key) = parse_der(private_key).ok()?;
+        let private_key = private_key.as_sequence().ok()?;
+        let (_, public_key) = parse_der(public_key).ok()?;
+        let public_key = public_key.as_sequence().ok()?;
+        Some(
+            integer_eq(private_key.get(1)?, public_key.first()?)?
+                && integer_eq(private_key.get(2)?, public_key.get(1)?)?,
+        )
+    } else if *alg == OID_KEY_TYPE_EC_PUBLIC_KEY {
+        // ECPrivateKey ::= SEQUENCE { version, privateKey, [0] parameters, [1] publicKey }
+        let (_, private_key) = parse_der(private_key).ok()?;
+        let embedded =
+            private_key
+                .as_sequence()
+                .ok()?
+                .iter()
+                .find_map(|field| match field.as_tagged() {
+                    Ok((_, tag, inner)) if tag.0 == 1 => inner.as_bitstring().ok(),
+                    _ => tagged_bitstring(field, 1),
+                })?;
+        Some(embedded.data == public_key)
+    } else {
+        // OneAsymmetricKey ::= SEQUENCE { ..., [1] publicKey OPTIONAL }
+        let embedded = info.iter().skip(3).find_map(|field| tagged_raw(field, 1))?;
+        let embedded = embedded.get(1..)?;
+        Some(embedded == public_key)
+    }
+}
+
+fn integer_eq(a: &BerObject<'_>, b: &BerObject<'_>) -> Option<bool> {
+    let strip =
+        |bytes: &[u8]| -> Vec<u8> { bytes.iter().skip_while(|b| **b == 0).copied().collect() };
+    Some(strip(a.as_slice().ok()?) == strip(b.as_slice().ok()?))
+}
+
+fn tagged_raw<'a>(field: &BerObject<'a>, tag: u32) -> Option<&'a [u8]> {
+    if field.header.tag().0 == tag && !field.header.is_universal() {
+        field.as_slice().ok()
+    } else {
+        None
+    }
+}
+
+fn tagged_bitstring<'a>(
+    field: &BerObject<'a>,
+    tag: u32,
+) -> Option<x509_parser::der_parser::ber::BitStringObject<'a>> {
+    let raw = tagged_raw(field, tag)?;
+    let (_, inner) = parse_der(raw).ok()?;
+    inner.as_bitstring().ok()
+}
+
+/// The DER certificate chain, leaf first, and the DER PKCS #8 private key of an archive.
+type Pkcs12Contents = (Vec<Vec<u8>>, Option<Vec<u8>>);
+
+#[cfg(not(any(target_os = "windows", target_vendor = "apple")))]
+fn pkcs12_contents(der: &[u8], password: &str) -> Result<Pkcs12Contents, Error> {
+    use openssl::pkcs12::Pkcs12;
+
+    let parsed = Pkcs12::from_der(der)
+        .and_then(|pkcs12| pkcs12.parse2(password))
+        .map_err(|e| Error::Parse(e.to_string()))?;
+    let mut chain = vec![];
+    if let Some(cert) = parsed.cert {
+        chain.push(cert.to_der().map_err(|e| Error::Parse(e.to_string()))?);
+    }
+    for cert in parsed.ca.into_iter().flatten() {
+        chain.push(cert.to_der().map_err(|e| Error::Parse(e.to_string()))?);
+    }
+    let key = match parsed.pkey {
+        Some(pkey) => Some(
+            pkey.private_key_to_pkcs8()
+                .map_err(|e| Error::Parse(e.to_string()))?,
+        ),
+        None => None,
+    };
+    Ok((chain, key))
+}
+
+#[cfg(any(target_os = "windows", target_vendor = "apple"))]
+fn pkcs12_contents(_der: &[u8], _password: &str) -> Result<Pkcs12Contents, Error> {
+    Err(Error::Unsupported)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn read(path: &str) -> Vec<u8> {
+        fs::read(path).unwrap()
+    }
+
+    #[test]
+    fn loads_pem_identity() {
+        let loaded = IdentityLoader::new()
+            .pem(
+                &read("tests/localhost.pem"),
+                &read("tests/localhost-key.pem"),
+            )
+            .unwrap();
+        let report = loaded.report;
+        assert_eq!(report.subject, "O=async-native-tls, CN=localhost");
+        assert_eq!(
+            report.subject_alt_names,
+            [
+                SubjectAltName::Dns("localhost".into()),
+                SubjectAltName::Ip([127, 0, 0, 1].into()),
+            ]
+        );
+        assert_eq!(report.key_type, KeyType::Ec("P-256".into()));
+        assert_eq!(report.chain_len, 1);
+        assert!(report.key_verified);
+        assert!(report.warnings.is_empty());
+    }
+
+    #[test]
+    fn rejects_mismatched_key() {
+        let res =
+            IdentityLoader::new().pem(&read("tests/localhost.pem"), &read("tests/ca-key.pem"));
+        assert!(matches!(res, Err(Error::KeyMismatch)));
+    }
+
+    #[test]
+    fn expiry_window() {
+        let (cert, key) = (read("tests/localhost.pem"), read("tests/localhost-key.pem"));
+        let century = Duration::from_secs(100 * 365 * 24 * 60 * 60);
+
+        let loaded = IdentityLoader::new()
+            .expiry_window(century)
+            .pem(&cert, &key)
+            .unwrap();
+        assert!(matches!(
+            loaded.report.warnings[..],
+            [Warning::ExpiresSoon(_)]
+        ));
+
+        let res = IdentityLoader::new()
+            .expiry_window(century)
+            .reject_expiring(true)
+            .pem(&cert, &key);
+        assert!(matches!(res, Err(Error::ExpiresSoon(_))));
+
+        let loaded = IdentityLoader::new()
+            .expiry_window(Duration::MAX)
+            .pem(&cert, &key)
+            .unwrap();
+        assert!(matches!(
+            loaded.report.warnings[..],
+            [Warning::ExpiresSoon(_)]
+        ));
+
+        let res = IdentityLoader::new()
+            .now(SystemTime::now() + century * 2)
+            .pem(&cert, &key);
+        assert!(matches!(res, Err(Error::Expired(_))));
+    }
+
+    #[cfg(not(any(target_os = "windows", target_vendor = "apple")))]
+    #[test]
+    fn rejects_expired_pkcs12() {
+        let res = IdentityLoader::new().pkcs12(&read("tests/identity.pfx"), "hello");
+        assert!(matches!(res, Err(Error::Expired(_))));
+    }
+}
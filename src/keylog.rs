@@ -0,0 +1,176 @@
+use std::env;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex, Once};
+
+use openssl::ssl::SslContextBuilder;
+
+/// The environment variable naming the file [`KeyLog::from_env`] writes to.
+const SSLKEYLOGFILE: &str = "SSLKEYLOGFILE";
+
+/// A destination for TLS session secrets, in the NSS key log format understood by Wireshark.
+///
+/// Key logging is meant for debugging only: anyone with access to the secrets can decrypt the
+/// traffic of the logged sessions. A warning is logged with the [`log`] crate the first time key
+/// logging is enabled in a process.
+///
+/// Only supported on the OpenSSL backend; enable it with
+/// [`TlsConnectorExt::key_log`](crate::TlsConnectorExt::key_log) or
+/// [`TlsAcceptorBuilderExt::key_log`](crate::TlsAcceptorBuilderExt::key_log). Requires OpenSSL
+/// 1.1.1 or newer.
+///
+/// The `SSLKEYLOGFILE` environment variable is not honored on its own: connectors and acceptors
+/// only log keys when given a `KeyLog`, for example one from [`KeyLog::from_env`].
+///
+/// # Example
+///
+/// ```no_run
+/// # #[cfg(feature = "runtime-async-std")]
+/// # fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> { async_std::task::block_on(async {
+/// #
+/// use async_std::net::TcpStream;
+/// use async_native_tls::{KeyLog, TlsConnector, TlsConnectorExt};
+///
+/// let mut connector = TlsConnector::new();
+/// // logs keys only while debugging, with SSLKEYLOGFILE=/tmp/keys.log
+/// if let Some(key_log) = KeyLog::from_env()? {
+///     connector = connector.key_log(key_log);
+/// }
+/// let stream = TcpStream::connect("mail.example.com:465").await?;
+/// let stream = connector.connect("mail.example.com", stream).await?;
+/// #
+/// # Ok(()) }) }
+/// # #[cfg(feature = "runtime-tokio")]
+/// # fn main() {}
+/// ```
+#[derive(Clone)]
+pub struct KeyLog {
+    target: String,
+    sink: Arc<dyn Fn(&str) + Send + Sync>,
+}
+
+impl KeyLog {
+    /// Appends key log lines to the file at `path`, creating it if needed. On Unix, a new file
+    /// is only readable and writable by its owner.
+    pub fn file(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let mut options = OpenOptions::new();
+        options.create(true).append(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let file = options.open(path)?;
+        let file = Mutex::new(file);
+        Ok(Self {
+            target: path.display().to_string(),
+            sink: Arc::new(move |line| {
+                let mut file = file.lock().unwrap_or_else(|e| e.into_inner());
+                // losing a line only makes a session undecryptable
+                let _ = write_line(&mut file, line);
+            }),
+        })
+    }
+
+    /// Appends key log lines to the file named by the `SSLKEYLOGFILE` environment variable, if
+    /// it is set.
+    pub fn from_env() -> io::Result<Option<Self>> {
+        match env::var_os(SSLKEYLOGFILE) {
+            Some(path) if !path.is_empty() => Self::file(path).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    /// Passes key log lines, without line terminator, to `sink`.
+    pub fn sink<F>(sink: F) -> Self
+    where
+        F: Fn(&str) + Send + Sync + 'static,
+    {
+        Self {
+            target: "a callback".to_string(),
+            sink: Arc::new(sink),
+        }
+    }
+
+    /// Logs the secrets of every session of `ctx`.
+    pub(crate) fn install(&self, ctx: &mut SslContextBuilder) {
+        static WARNING: Once = Once::new();
+        WARNING.call_once(|| {
+            log::warn!(
+                "logging TLS session secrets to {}. Anyone with access to them can decrypt the \
+                 traffic. Only enable key logging for debugging.",
+                self.target
+            )
+        });
+        let sink = self.sink.clone();
+        ctx.set_keylog_callback(move |_, line| sink(line));
+    }
+}
+
+fn write_line(file: &mut File, line: &str) -> io::Result<()> {
+    // a single write keeps lines of concurrent sessions intact
+    file.write_all(format!("{}\n", line).as_bytes())
+}
+
+impl fmt::Debug for KeyLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyLog")
+            .field("target", &self.target)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(all(test, feature = "runtime-async-std"))]
+mod tests {
+    use super::*;
+    use crate::runtime::{AsyncReadExt, AsyncWriteExt};
+    use crate::test_util::{localhost, serve, tcp};
+    use crate::{TlsAcceptorBuilderExt, TlsConnector, TlsConnectorExt};
+
+    #[async_std::test]
+    async fn logs_both_ends() {
+        let path = env::temp_dir().join(format!("async-native-tls-keylog-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let acceptor = localhost()
+            .key_log(KeyLog::file(&path).unwrap())
+            .build()
+            .unwrap();
+        let (addr, server) = serve(acceptor, |mut stream| async move {
+            stream.close().await.unwrap();
+        })
+        .await;
+
+        let lines = Arc::new(Mutex::new(vec![]));
+        let sink = lines.clone();
+        let mut stream = TlsConnector::new()
+            .danger_accept_invalid_certs(true)
+            .key_log(KeyLog::sink(move |line| {
+                sink.lock().unwrap().push(line.to_string())
+            }))
+            .connect("localhost", tcp(addr).await)
+            .await
+            .unwrap();
+        stream.read_to_end(&mut vec![]).await.unwrap();
+        server.await.unwrap();
+
+        let client = lines.lock().unwrap().clone();
+        // the acceptor negotiates TLS 1.2, like native-tls does
+        assert!(client.iter().any(|line| line.starts_with("CLIENT_RANDOM ")));
+        let server = std::fs::read_to_string(&path).unwrap();
+        let server: Vec<_> = server.lines().collect();
+        assert_eq!(server.len(), client.len());
+        for line in client {
+            assert!(server.contains(&line.as_str()));
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -38,21 +38,124 @@ compile_error!("one of 'runtime-async-std' or 'runtime-tokio' features must be e
 compile_error!("only one of 'runtime-async-std' or 'runtime-tokio' features must be enabled");
 
 mod acceptor;
+#[cfg(feature = "x509")]
+mod certificate;
+#[cfg(feature = "serde")]
+mod config;
 mod connector;
+#[cfg(feature = "x509")]
+mod dane;
 mod handshake;
+#[cfg(feature = "x509")]
+mod identity;
+#[cfg(all(
+    feature = "openssl",
+    not(any(target_os = "windows", target_vendor = "apple"))
+))]
+mod keylog;
+mod mta_sts;
+#[cfg(feature = "x509")]
+mod ocsp;
+#[cfg(all(
+    feature = "openssl",
+    not(any(target_os = "windows", target_vendor = "apple"))
+))]
+mod openssl_ext;
+mod opportunistic;
+#[cfg(all(
+    feature = "openssl",
+    not(any(target_os = "windows", target_vendor = "apple"))
+))]
+mod ossl;
+#[cfg(feature = "pkcs8")]
+mod pem_identity;
+#[cfg(feature = "x509")]
+mod pinning;
+mod policy;
+#[cfg(feature = "x509")]
+mod principal;
+mod reload;
+#[cfg(feature = "x509")]
+mod revocation;
+mod roots;
 mod runtime;
+mod shutdown;
 mod std_adapter;
+#[cfg(all(test, feature = "runtime-async-std"))]
+mod test_util;
+mod tls_rpt;
 mod tls_stream;
+#[cfg(feature = "x509")]
+mod tofu;
 
 pub use accept::accept;
-pub use acceptor::{Error as AcceptError, TlsAcceptor};
-pub use connect::{connect, TlsConnector};
+pub use acceptor::{ClientAuth, Error as AcceptError, TlsAcceptor, TlsAcceptorBuilder};
+#[cfg(feature = "x509")]
+pub use certificate::{CertificateInfo, Error as CertificateError, KeyType, SubjectAltName};
+#[cfg(feature = "serde")]
+pub use config::{
+    AcceptorConfig, ConnectorConfig, Error as ConfigError, HostConfig, IdentityConfig,
+    PasswordSource, PolicyConfig,
+};
+pub use connect::{connect, Error as ConnectError, TlsConnector};
+#[cfg(feature = "x509")]
+pub use dane::{DaneMatch, DaneVerifier, Error as DaneError, TlsaRecord};
 pub use host::Host;
-pub use tls_stream::TlsStream;
+#[cfg(feature = "x509")]
+pub use identity::{
+    Error as IdentityError, IdentityLoader, IdentityReport, LoadedIdentity,
+    Warning as IdentityWarning,
+};
+#[cfg(all(
+    feature = "openssl",
+    not(any(target_os = "windows", target_vendor = "apple"))
+))]
+pub use keylog::KeyLog;
+pub use mta_sts::{Error as MtaStsError, MtaSts, MtaStsMode, MtaStsPolicy, PolicyFetcher};
+#[cfg(feature = "x509")]
+pub use ocsp::{Error as OcspError, OcspChecker, OcspTransport};
+#[cfg(all(
+    feature = "openssl",
+    not(any(target_os = "windows", target_vendor = "apple"))
+))]
+pub use openssl_ext::{TlsAcceptorBuilderExt, TlsConnectorExt, TlsStreamExt};
+pub use opportunistic::{
+    Attempt as OpportunisticAttempt, Error as OpportunisticError, MaybeTlsStream, Opportunistic,
+    Outcome as OpportunisticOutcome, SecurityLevel,
+};
+#[cfg(feature = "pkcs8")]
+pub use pem_identity::Error as PemIdentityError;
+#[cfg(feature = "x509")]
+pub use pinning::PinningError;
+pub use policy::{ConnectorPolicy, HostSettings};
+#[cfg(feature = "x509")]
+pub use principal::{
+    AuditEvent, AuditOutcome, AuthorizingAcceptor, Error as AuthorizationError, PrincipalMap,
+    PrincipalRule,
+};
+pub use reload::{IdentitySource, IdentityWatcher, ReloadableAcceptor};
+#[cfg(feature = "x509")]
+pub use revocation::{CrlStore, Error as RevocationError, RevocationPolicy, RevocationReason};
+pub use roots::{RootCertificates, SkippedCertificate};
+pub use shutdown::{Connection, DrainReport, Error as ShutdownError, Shutdown};
+pub use tls_rpt::{
+    DateRange, FailureDetails, FailureReport, PolicyReport, SessionSummary, TlsRptCollector,
+    TlsRptPolicy, TlsRptPolicyType, TlsRptReport, TlsRptResultType,
+};
+pub use tls_stream::{ChannelBindingType, Error as SessionError, TlsStream};
+#[cfg(feature = "x509")]
+pub use tofu::{Error as TofuError, TofuStore, TofuTrust};
 
 #[doc(inline)]
 pub use native_tls::{Certificate, Error, Identity, Protocol, Result};
 
+/// The OpenSSL bindings used by [`TlsConnectorExt`] and the other OpenSSL extension traits.
+#[cfg(all(
+    feature = "openssl",
+    not(any(target_os = "windows", target_vendor = "apple"))
+))]
+pub use openssl;
+
 mod accept {
     use crate::runtime::{AsyncRead, AsyncWrite};
 
@@ -114,6 +217,11 @@ mod host {
         pub fn as_string(self) -> String {
             self.0
         }
+
+        /// The host as string slice.
+        pub fn as_str(&self) -> &str {
+            &self.0
+        }
     }
 
     impl From<&str> for Host {
@@ -153,16 +261,94 @@ mod host {
             )
         }
     }
+
+    /// Matches a name pattern, which may start with a `*.` wildcard for exactly one label, against
+    /// a host name. Both are expected in lowercase.
+    pub(crate) fn matches_name(pattern: &str, name: &str) -> bool {
+        match pattern.strip_prefix("*.") {
+            Some(suffix) => match name.split_once('.') {
+                Some((label, rest)) => !label.is_empty() && rest == suffix,
+                None => false,
+            },
+            None => pattern == name,
+        }
+    }
 }
 
 mod connect {
     use std::fmt::{self, Debug};
+    use std::sync::Arc;
 
     use crate::host::Host;
     use crate::runtime::{AsyncRead, AsyncWrite};
     use crate::TlsStream;
     use crate::{Certificate, Identity, Protocol};
 
+    /// An error returned from connecting with a [`TlsConnector`].
+    ///
+    /// Before 0.6, connecting failed with a [`native_tls::Error`], which is now wrapped in
+    /// [`Error::NativeTls`]. Further variants depend on the enabled features.
+    #[derive(thiserror::Error, Debug)]
+    #[non_exhaustive]
+    pub enum Error {
+        /// NativeTls error.
+        #[error("NativeTls({0})")]
+        NativeTls(#[from] native_tls::Error),
+        /// The peer certificate did not match the pins of the host.
+        #[cfg(feature = "x509")]
+        #[error("Pinning({0})")]
+        Pinning(#[from] crate::PinningError),
+        /// The peer certificate is revoked, or its revocation status is unknown.
+        #[cfg(feature = "x509")]
+        #[error("Revocation({0})")]
+        Revocation(#[from] crate::RevocationError),
+        /// OpenSsl error, for connectors using the OpenSSL backend.
+        #[cfg(all(
+            feature = "openssl",
+            not(any(target_os = "windows", target_vendor = "apple"))
+        ))]
+        #[error("OpenSsl({0})")]
+        OpenSsl(#[from] openssl::ssl::Error),
+        /// The server certificate failed validation, for connectors using the OpenSSL backend.
+        #[cfg(all(
+            feature = "openssl",
+            not(any(target_os = "windows", target_vendor = "apple"))
+        ))]
+        #[error("certificate verification failed: {code}")]
+        InvalidCertificate {
+            /// Why validation failed.
+            code: openssl::x509::X509VerifyResult,
+            /// The failed handshake.
+            source: openssl::ssl::Error,
+        },
+        /// A setting of the connector is not supported by the OpenSSL backend.
+        #[cfg(all(
+            feature = "openssl",
+            not(any(target_os = "windows", target_vendor = "apple"))
+        ))]
+        #[error("{0} are not supported with OpenSSL settings")]
+        Unsupported(&'static str),
+        /// The peer certificate was rejected by the callback set with
+        /// [`TlsConnector::verify_with`].
+        #[error("Verification({0})")]
+        Verification(Box<dyn std::error::Error + Send + Sync>),
+    }
+
+    #[cfg(all(
+        feature = "openssl",
+        not(any(target_os = "windows", target_vendor = "apple"))
+    ))]
+    impl From<openssl::error::ErrorStack> for Error {
+        fn from(err: openssl::error::ErrorStack) -> Self {
+            Error::OpenSsl(err.into())
+        }
+    }
+
+    /// A certificate verification callback, set with [`TlsConnector::verify_with`].
+    pub(crate) type Verify = dyn Fn(&[Certificate], &Host) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
+        + Send
+        + Sync;
+
     /// Connect a client to a remote server.
     ///
     /// # Examples
@@ -190,7 +376,9 @@ mod connect {
     where
         S: AsyncRead + AsyncWrite + Unpin,
     {
-        let stream = TlsConnector::new().connect(host, stream).await?;
+        let domain = host.into().as_string();
+        let connector = crate::connector::TlsConnector::from(native_tls::TlsConnector::new()?);
+        let stream = connector.connect(&domain, stream).await?;
         Ok(stream)
     }
 
@@ -223,6 +411,16 @@ mod connect {
     /// ```
     pub struct TlsConnector {
         builder: native_tls::TlsConnectorBuilder,
+        #[cfg(feature = "x509")]
+        pins: crate::pinning::Pins,
+        #[cfg(feature = "x509")]
+        crls: Option<std::sync::Arc<crate::CrlStore>>,
+        verify: Option<Arc<Verify>>,
+        #[cfg(all(
+            feature = "openssl",
+            not(any(target_os = "windows", target_vendor = "apple"))
+        ))]
+        pub(crate) openssl: crate::ossl::ConnectorSettings,
     }
 
     impl Default for TlsConnector {
@@ -235,22 +433,100 @@ mod connect {
         /// Create a new instance.
         pub fn new() -> Self {
             Self {
-                builder: native_tls::TlsConnector::builder(),
+                #[cfg(all(
+                    feature = "openssl",
+                    not(any(target_os = "windows", target_vendor = "apple"))
+                ))]
+                openssl: Default::default(),
+                ..native_tls::TlsConnector::builder().into()
             }
         }
 
         /// Sets the identity to be used for client certificate authentication.
         pub fn identity(mut self, identity: Identity) -> Self {
             self.builder.identity(identity);
+            #[cfg(all(
+                feature = "openssl",
+                not(any(target_os = "windows", target_vendor = "apple"))
+            ))]
+            {
+                let settings = self.openssl_mut();
+                settings.identity = None;
+                settings.opaque_identity = true;
+            }
             self
         }
 
+        /// Sets the identity to be used for client certificate authentication, reading a
+        /// PEM-formatted certificate chain, leaf first, and a PEM-formatted PKCS #8 private key.
+        ///
+        /// Encrypted keys are decrypted with `passphrase`, which is zeroized after use, as is the
+        /// decrypted key.
+        ///
+        /// # Example
+        ///
+        /// ```no_run
+        /// # #[cfg(feature = "runtime-async-std")]
+        /// # fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> { async_std::task::block_on(async {
+        /// #
+        /// use async_std::fs::File;
+        /// use async_native_tls::TlsConnector;
+        ///
+        /// let cert = File::open("client.pem").await?;
+        /// let key = File::open("client-key.pem").await?;
+        /// let passphrase = std::env::var("CLIENT_KEY_PASSPHRASE").ok();
+        /// let connector = TlsConnector::new()
+        ///     .identity_from_pem(cert, key, passphrase)
+        ///     .await?;
+        /// #
+        /// # Ok(()) }) }
+        /// # #[cfg(feature = "runtime-tokio")]
+        /// # fn main() {}
+        /// ```
+        #[cfg(feature = "pkcs8")]
+        pub async fn identity_from_pem<C, K>(
+            self,
+            cert: C,
+            key: K,
+            passphrase: Option<String>,
+        ) -> Result<Self, crate::PemIdentityError>
+        where
+            C: AsyncRead + Unpin,
+            K: AsyncRead + Unpin,
+        {
+            let (cert, key) = crate::pem_identity::read(cert, key, passphrase).await?;
+            let connector = self.identity(Identity::from_pkcs8(&cert, key.as_bytes())?);
+            // keep the key for the OpenSSL backend, which cannot load native-tls identities
+            #[cfg(all(
+                feature = "openssl",
+                not(any(target_os = "windows", target_vendor = "apple"))
+            ))]
+            let connector = {
+                let mut connector = connector;
+                let settings = connector.openssl_mut();
+                settings.identity = Some(crate::acceptor::IdentityData::Pkcs8 {
+                    cert,
+                    key: zeroize::Zeroizing::new(key.as_bytes().to_vec()),
+                });
+                settings.opaque_identity = false;
+                connector
+            };
+            Ok(connector)
+        }
+
         /// Sets the minimum supported protocol version.
         ///
         /// A value of `None` enables support for the oldest protocols supported by the
         /// implementation. Defaults to `Some(Protocol::Tlsv10)`.
         pub fn min_protocol_version(mut self, protocol: Option<Protocol>) -> Self {
             self.builder.min_protocol_version(protocol);
+            #[cfg(all(
+                feature = "openssl",
+                not(any(target_os = "windows", target_vendor = "apple"))
+            ))]
+            {
+                self.openssl_mut().min_protocol = protocol;
+            }
             self
         }
 
@@ -260,6 +536,13 @@ mod connect {
         /// implementation. Defaults to `None`.
         pub fn max_protocol_version(mut self, protocol: Option<Protocol>) -> Self {
             self.builder.max_protocol_version(protocol);
+            #[cfg(all(
+                feature = "openssl",
+                not(any(target_os = "windows", target_vendor = "apple"))
+            ))]
+            {
+                self.openssl_mut().max_protocol = protocol;
+            }
             self
         }
 
@@ -269,15 +552,59 @@ mod connect {
         /// add to that set when communicating with servers not trusted by the system. Defaults to
         /// an empty set.
         pub fn add_root_certificate(mut self, cert: Certificate) -> Self {
+            #[cfg(all(
+                feature = "openssl",
+                not(any(target_os = "windows", target_vendor = "apple"))
+            ))]
+            self.openssl_mut().roots.push(cert.clone());
             self.builder.add_root_certificate(cert);
             self
         }
 
+        /// Adds several certificates to the set of roots that the connector will trust, e.g. the
+        /// contents of a [`RootCertificates`](crate::RootCertificates).
+        pub fn add_root_certificates(
+            mut self,
+            certs: impl IntoIterator<Item = Certificate>,
+        ) -> Self {
+            for cert in certs {
+                #[cfg(all(
+                    feature = "openssl",
+                    not(any(target_os = "windows", target_vendor = "apple"))
+                ))]
+                self.openssl_mut().roots.push(cert.clone());
+                self.builder.add_root_certificate(cert);
+            }
+            self
+        }
+
+        /// Controls the use of built-in system certificates during certificate validation.
+        ///
+        /// Defaults to `false` -- built-in system certs will be used.
+        pub fn disable_built_in_roots(mut self, disable: bool) -> Self {
+            self.builder.disable_built_in_roots(disable);
+            #[cfg(all(
+                feature = "openssl",
+                not(any(target_os = "windows", target_vendor = "apple"))
+            ))]
+            {
+                self.openssl_mut().disable_built_in_roots = disable;
+            }
+            self
+        }
+
         /// Request specific protocols through ALPN (Application-Layer Protocol Negotiation).
         ///
         /// Defaults to none
         pub fn request_alpns(mut self, protocols: &[&str]) -> Self {
             self.builder.request_alpns(protocols);
+            #[cfg(all(
+                feature = "openssl",
+                not(any(target_os = "windows", target_vendor = "apple"))
+            ))]
+            {
+                self.openssl_mut().alpn = protocols.iter().map(|p| p.to_string()).collect();
+            }
             self
         }
 
@@ -294,6 +621,13 @@ mod connect {
         pub fn danger_accept_invalid_certs(mut self, accept_invalid_certs: bool) -> Self {
             self.builder
                 .danger_accept_invalid_certs(accept_invalid_certs);
+            #[cfg(all(
+                feature = "openssl",
+                not(any(target_os = "windows", target_vendor = "apple"))
+            ))]
+            {
+                self.openssl_mut().accept_invalid_certs = accept_invalid_certs;
+            }
             self
         }
 
@@ -302,6 +636,13 @@ mod connect {
         /// Defaults to `true`.
         pub fn use_sni(mut self, use_sni: bool) -> Self {
             self.builder.use_sni(use_sni);
+            #[cfg(all(
+                feature = "openssl",
+                not(any(target_os = "windows", target_vendor = "apple"))
+            ))]
+            {
+                self.openssl_mut().use_sni = use_sni;
+            }
             self
         }
 
@@ -317,6 +658,141 @@ mod connect {
         pub fn danger_accept_invalid_hostnames(mut self, accept_invalid_hostnames: bool) -> Self {
             self.builder
                 .danger_accept_invalid_hostnames(accept_invalid_hostnames);
+            #[cfg(all(
+                feature = "openssl",
+                not(any(target_os = "windows", target_vendor = "apple"))
+            ))]
+            {
+                self.openssl_mut().accept_invalid_hostnames = accept_invalid_hostnames;
+            }
+            self
+        }
+
+        /// Pins the subject public key info of `host` to the given SHA-256 digests.
+        ///
+        /// After the handshake with `host`, the connection fails with [`Error::Pinning`] unless
+        /// the peer certificate matches one of the pins of the host. Pins are checked in addition
+        /// to the usual certificate validation; combine them with
+        /// [`danger_accept_invalid_certs`](TlsConnector::danger_accept_invalid_certs) to rely on
+        /// the pins alone. Can be called repeatedly to add pins.
+        ///
+        /// Only the leaf certificate of the server is checked, not its issuers, so pinning the
+        /// key of an intermediate or root CA never matches. Pin the keys of the server itself,
+        /// including a backup key for the next rotation.
+        #[cfg(feature = "x509")]
+        pub fn pin_spki_sha256(
+            mut self,
+            host: impl Into<Host>,
+            hashes: impl IntoIterator<Item = [u8; 32]>,
+        ) -> Self {
+            self.pins.add_spki_sha256(&host.into().as_string(), hashes);
+            self
+        }
+
+        /// Pins the certificate of `host` to the given SHA-256 fingerprints.
+        ///
+        /// Behaves like [`pin_spki_sha256`](TlsConnector::pin_spki_sha256), but matches the
+        /// digest of the whole DER-encoded certificate.
+        #[cfg(feature = "x509")]
+        pub fn pin_certificate_sha256(
+            mut self,
+            host: impl Into<Host>,
+            fingerprints: impl IntoIterator<Item = [u8; 32]>,
+        ) -> Self {
+            self.pins
+                .add_certificate_sha256(&host.into().as_string(), fingerprints);
+            self
+        }
+
+        /// Controls whether pin mismatches fail the connection.
+        ///
+        /// In report-only mode, mismatches are only passed to the callback set with
+        /// [`on_pinning_failure`](TlsConnector::on_pinning_failure). Defaults to `false`.
+        #[cfg(feature = "x509")]
+        pub fn pinning_report_only(mut self, report_only: bool) -> Self {
+            self.pins.set_report_only(report_only);
+            self
+        }
+
+        /// Sets a callback invoked for every pin mismatch, whether or not it fails the connection.
+        #[cfg(feature = "x509")]
+        pub fn on_pinning_failure<F>(mut self, callback: F) -> Self
+        where
+            F: Fn(&crate::PinningError) + Send + Sync + 'static,
+        {
+            self.pins.set_reporter(std::sync::Arc::new(callback));
+            self
+        }
+
+        /// Sets a callback deciding whether to accept the certificate chain of the server, leaf
+        /// first, for the host connected to. Rejections fail the connection with
+        /// [`Error::Verification`].
+        ///
+        /// The callback supplements the usual certificate validation, which it does not replace,
+        /// and is meant for extra checks such as a required policy OID. With the `openssl`
+        /// feature, it runs during the handshake, only for chains that passed validation, and
+        /// receives the verified chain. Otherwise it runs right after the handshake, and only
+        /// receives the leaf certificate, as native-tls does not expose the chain. It also runs
+        /// after the handshake for connectors that
+        /// [accept invalid certificates](TlsConnector::danger_accept_invalid_certs) or whose
+        /// settings cannot be carried over to OpenSSL, such as an [`Identity`], and for resumed
+        /// sessions, which skip certificate validation; it then receives the chain verified when
+        /// the session was established. Sessions cached before the callback was set are dropped.
+        ///
+        /// # Example
+        ///
+        /// ```no_run
+        /// # #[cfg(feature = "runtime-async-std")]
+        /// # fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> { async_std::task::block_on(async {
+        /// #
+        /// use async_std::net::TcpStream;
+        /// use async_native_tls::{Certificate, TlsConnector};
+        ///
+        /// let issuing_ca = Certificate::from_pem(&std::fs::read("issuing-ca.pem")?)?.to_der()?;
+        /// let connector = TlsConnector::new().verify_with(move |chain, host| {
+        ///     match chain.get(1).map(Certificate::to_der).transpose()? {
+        ///         Some(issuer) if issuer == issuing_ca => Ok(()),
+        ///         _ => Err(format!("{} is not issued by the internal CA", host.as_str()).into()),
+        ///     }
+        /// });
+        /// let stream = TcpStream::connect("service.internal:443").await?;
+        /// let stream = connector.connect("service.internal", stream).await?;
+        /// #
+        /// # Ok(()) }) }
+        /// # #[cfg(feature = "runtime-tokio")]
+        /// # fn main() {}
+        /// ```
+        pub fn verify_with<F>(mut self, callback: F) -> Self
+        where
+            F: Fn(&[Certificate], &Host) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
+                + Send
+                + Sync
+                + 'static,
+        {
+            #[cfg(all(
+                feature = "openssl",
+                not(any(target_os = "windows", target_vendor = "apple"))
+            ))]
+            {
+                // cached sessions were verified without this callback
+                self.openssl_mut();
+            }
+            self.verify = Some(Arc::new(callback));
+            self
+        }
+
+        /// Checks the server certificate chain against `crls` after the handshake.
+        #[cfg(feature = "x509")]
+        pub fn check_revocation(mut self, crls: std::sync::Arc<crate::CrlStore>) -> Self {
+            #[cfg(all(
+                feature = "openssl",
+                not(any(target_os = "windows", target_vendor = "apple"))
+            ))]
+            {
+                // native-tls only exposes the leaf certificate
+                self.openssl_mut().peer_chain = true;
+            }
+            self.crls = Some(crls);
             self
         }
 
@@ -351,19 +827,80 @@ mod connect {
             &self,
             host: impl Into<Host>,
             stream: S,
-        ) -> native_tls::Result<TlsStream<S>>
+        ) -> Result<TlsStream<S>, Error>
         where
             S: AsyncRead + AsyncWrite + Unpin,
         {
             let host: Host = host.into();
             let domain = host.as_string();
+            #[cfg(all(
+                feature = "openssl",
+                not(any(target_os = "windows", target_vendor = "apple"))
+            ))]
+            let stream = if self.openssl.required() {
+                if let Some(setting) = self.openssl.unsupported() {
+                    return Err(Error::Unsupported(setting));
+                }
+                crate::ossl::connect(&self.openssl, &domain, stream, self.verify.as_ref()).await?
+            } else if (self.verify.is_some() || self.openssl.peer_chain)
+                && self.openssl.unsupported().is_none()
+            {
+                crate::ossl::connect(&self.openssl, &domain, stream, self.verify.as_ref()).await?
+            } else {
+                self.connect_native(&domain, stream).await?
+            };
+            #[cfg(not(all(
+                feature = "openssl",
+                not(any(target_os = "windows", target_vendor = "apple"))
+            )))]
+            let stream = self.connect_native(&domain, stream).await?;
+            #[cfg(feature = "x509")]
+            self.pins.verify(&domain, &stream)?;
+            #[cfg(feature = "x509")]
+            if let Some(crls) = &self.crls {
+                crls.verify(&stream)?;
+            }
+            Ok(stream)
+        }
+
+        /// The OpenSSL settings, for changing them. Cached sessions are dropped, as they cannot be
+        /// resumed with different settings.
+        #[cfg(all(
+            feature = "openssl",
+            not(any(target_os = "windows", target_vendor = "apple"))
+        ))]
+        pub(crate) fn openssl_mut(&mut self) -> &mut crate::ossl::ConnectorSettings {
+            self.openssl.reset_sessions();
+            &mut self.openssl
+        }
+
+        async fn connect_native<S>(&self, domain: &str, stream: S) -> Result<TlsStream<S>, Error>
+        where
+            S: AsyncRead + AsyncWrite + Unpin,
+        {
             let connector = self.builder.build()?;
             let connector = crate::connector::TlsConnector::from(connector);
-            let stream = connector.connect(&domain, stream).await?;
+            let stream = connector.connect(domain, stream).await?;
+            if let Some(verify) = &self.verify {
+                verify_after_handshake(verify.as_ref(), domain, &stream)?;
+            }
             Ok(stream)
         }
     }
 
+    /// Runs `verify` on the chain the peer of `stream` presented.
+    pub(crate) fn verify_after_handshake<S>(
+        verify: &Verify,
+        domain: &str,
+        stream: &TlsStream<S>,
+    ) -> Result<(), Error>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let chain = stream.peer_certificate_chain()?.unwrap_or_default();
+        verify(&chain, &Host::from(domain)).map_err(Error::Verification)
+    }
+
     impl Debug for TlsConnector {
         fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
             f.debug_struct("TlsConnector").finish()
@@ -372,7 +909,65 @@ mod connect {
 
     impl From<native_tls::TlsConnectorBuilder> for TlsConnector {
         fn from(builder: native_tls::TlsConnectorBuilder) -> Self {
-            Self { builder }
+            Self {
+                builder,
+                #[cfg(feature = "x509")]
+                pins: Default::default(),
+                #[cfg(feature = "x509")]
+                crls: None,
+                verify: None,
+                #[cfg(all(
+                    feature = "openssl",
+                    not(any(target_os = "windows", target_vendor = "apple"))
+                ))]
+                openssl: crate::ossl::ConnectorSettings {
+                    from_native: true,
+                    ..Default::default()
+                },
+            }
+        }
+    }
+    #[cfg(all(test, feature = "runtime-async-std"))]
+    mod tests {
+        use super::*;
+        use crate::test_util::{ca, hello, localhost, tcp};
+        use async_std::net::TcpStream;
+        use std::sync::Mutex;
+
+        async fn connect(connector: TlsConnector) -> Result<TlsStream<TcpStream>, Error> {
+            let addr = hello(localhost().build().unwrap()).await;
+            connector
+                .add_root_certificate(ca())
+                .connect("localhost", tcp(addr).await)
+                .await
+        }
+
+        #[async_std::test]
+        async fn verify_with() {
+            let seen = Arc::new(Mutex::new(None));
+            let record = seen.clone();
+            connect(TlsConnector::new().verify_with(move |chain, host| {
+                *record.lock().unwrap() = Some((chain.len(), host.as_str().to_string()));
+                Ok(())
+            }))
+            .await
+            .unwrap();
+            // only OpenSSL exposes the chain beyond the leaf
+            let len = if cfg!(all(
+                feature = "openssl",
+                not(any(target_os = "windows", target_vendor = "apple"))
+            )) {
+                2
+            } else {
+                1
+            };
+            assert_eq!(*seen.lock().unwrap(), Some((len, "localhost".to_string())));
+
+            let res = connect(TlsConnector::new().verify_with(|_, _| Err("policy missing".into())));
+            match res.await {
+                Err(Error::Verification(err)) => assert_eq!(err.to_string(), "policy missing"),
+                res => panic!("unexpected result {:?}", res.map(|_| ())),
+            }
         }
     }
 }
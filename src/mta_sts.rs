@@ -0,0 +1,582 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use crate::host::{matches_name, Host};
+use crate::runtime::{AsyncRead, AsyncWrite};
+use crate::{ConnectError, TlsConnector, TlsStream};
+
+/// The largest `max_age` allowed by RFC 8461, about one year.
+const MAX_AGE_LIMIT: u64 = 31_557_600;
+
+/// An MTA-STS policy, as defined in [RFC 8461](https://tools.ietf.org/html/rfc8461).
+///
+/// Policies are parsed from the text served at
+/// `https://mta-sts.<domain>/.well-known/mta-sts.txt`.
+///
+/// ```
+/// use async_native_tls::{MtaStsMode, MtaStsPolicy};
+///
+/// let policy: MtaStsPolicy = "version: STSv1\r\n\
+///     mode: enforce\r\n\
+///     mx: mail.example.com\r\n\
+///     mx: *.example.net\r\n\
+///     max_age: 86400\r\n"
+///     .parse()
+///     .unwrap();
+/// assert_eq!(policy.mode, MtaStsMode::Enforce);
+/// assert!(policy.matches_mx("mx1.example.net"));
+/// assert!(!policy.matches_mx("example.net"));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MtaStsPolicy {
+    /// What sending MTAs should do with the policy.
+    pub mode: MtaStsMode,
+    /// The MX host patterns allowed to receive mail for the domain.
+    pub mx: Vec<String>,
+    /// How long the policy may be cached.
+    pub max_age: Duration,
+}
+
+/// The mode of an [`MtaStsPolicy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MtaStsMode {
+    /// Deliver only to matching MX hosts with valid certificates.
+    Enforce,
+    /// Report failures, but deliver anyway.
+    Testing,
+    /// The domain has no active policy.
+    None,
+}
+
+/// An error returned from fetching or applying an MTA-STS policy.
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    /// Connecting failed. Under an `enforce` policy this includes invalid certificates.
+    #[error("Connect({0})")]
+    Connect(#[from] ConnectError),
+    /// The policy could not be fetched.
+    #[error("failed to fetch policy: {0}")]
+    Fetch(io::Error),
+    /// The connection to the MX host could not be opened.
+    #[error("failed to open connection: {0}")]
+    Open(io::Error),
+    /// The policy text is malformed.
+    #[error("invalid policy: {0}")]
+    InvalidPolicy(String),
+    /// The MX host is not listed in the policy.
+    #[error("MX host {mx} matches none of {patterns:?}")]
+    MxMismatch {
+        /// The MX host connected to.
+        mx: String,
+        /// The MX patterns of the policy.
+        patterns: Vec<String>,
+    },
+}
+
+impl MtaStsPolicy {
+    /// Whether `host` matches one of the MX patterns of the policy.
+    ///
+    /// A pattern starting with `*.` matches exactly one additional leftmost label.
+    pub fn matches_mx(&self, host: &str) -> bool {
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        self.mx
+            .iter()
+            .any(|pattern| matches_name(&pattern.to_ascii_lowercase(), &host))
+    }
+}
+
+impl FromStr for MtaStsPolicy {
+    type Err = Error;
+
+    fn from_str(text: &str) -> Result<Self, Error> {
+        let invalid = |msg: &str| Error::InvalidPolicy(msg.to_string());
+
+        let fields = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                let (key, value) = line.split_once(':').ok_or_else(|| invalid(line))?;
+                Ok::<_, Error>((key.trim(), value.trim()))
+            });
+
+        // RFC 8461, section 3.2 does not fix the order of the fields
+        let (mut version, mut mode, mut max_age, mut mx) = (false, None, None, vec![]);
+        for field in fields {
+            match field? {
+                ("version", "STSv1") => version = true,
+                ("version", other) => return Err(invalid(&format!("unknown version {}", other))),
+                ("mode", "enforce") => mode = Some(MtaStsMode::Enforce),
+                ("mode", "testing") => mode = Some(MtaStsMode::Testing),
+                ("mode", "none") => mode = Some(MtaStsMode::None),
+                ("mode", other) => return Err(invalid(&format!("unknown mode {}", other))),
+                ("max_age", value) => {
+                    let secs = value
+                        .parse::<u64>()
+                        .map_err(|_| invalid(&format!("invalid max_age {}", value)))?;
+                    max_age = Some(Duration::from_secs(secs.min(MAX_AGE_LIMIT)));
+                }
+                ("mx", value) => mx.push(value.to_string()),
+                // unknown fields are ignored, as required for extensibility
+                _ => {}
+            }
+        }
+
+        if !version {
+            return Err(invalid("missing version: STSv1"));
+        }
+        let mode = mode.ok_or_else(|| invalid("missing mode"))?;
+        let max_age = max_age.ok_or_else(|| invalid("missing max_age"))?;
+        if mx.is_empty() && mode != MtaStsMode::None {
+            return Err(invalid("missing mx"));
+        }
+        Ok(Self { mode, mx, max_age })
+    }
+}
+
+/// Retrieves the text of MTA-STS policies.
+///
+/// Implementations fetch `https://mta-sts.<domain>/.well-known/mta-sts.txt` with an HTTP client
+/// of their choice, which validates the certificate of the policy host. Tests can return fixed
+/// policies instead.
+pub trait PolicyFetcher: Send + Sync {
+    /// Fetches the policy text of `domain`.
+    fn fetch<'a>(
+        &'a self,
+        domain: &'a str,
+    ) -> Pin<Box<dyn Future<Output = io::Result<String>> + Send + 'a>>;
+}
+
+/// Applies MTA-STS policies to connections, caching them for their `max_age`.
+///
+/// Looking up the `_mta-sts` TXT record is left to the caller, who passes its `id` along.
+///
+/// # Example
+///
+/// ```no_run
+/// # #[cfg(feature = "runtime-async-std")]
+/// # fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> { async_std::task::block_on(async {
+/// #
+/// use std::future::Future;
+/// use std::io;
+/// use std::pin::Pin;
+/// use async_std::net::TcpStream;
+/// use async_native_tls::{MtaSts, PolicyFetcher, TlsConnector};
+///
+/// struct Https;
+///
+/// impl PolicyFetcher for Https {
+///     fn fetch<'a>(
+///         &'a self,
+///         domain: &'a str,
+///     ) -> Pin<Box<dyn Future<Output = io::Result<String>> + Send + 'a>> {
+///         Box::pin(async move {
+///             // GET https://mta-sts.{domain}/.well-known/mta-sts.txt with an HTTP client
+///             let message = format!("no HTTP client to fetch the policy of {}", domain);
+///             Err(io::Error::new(io::ErrorKind::Other, message))
+///         })
+///     }
+/// }
+///
+/// let mta_sts = MtaSts::new(Https);
+/// let open = || async {
+///     let stream = TcpStream::connect("mx1.example.com:25").await?;
+///     // issue STARTTLS here
+///     Ok(stream)
+/// };
+/// let (stream, failure) = mta_sts
+///     .connect(TlsConnector::new(), "example.com", Some("20160831085700Z"), "mx1.example.com", open)
+///     .await?;
+/// if let Some(failure) = failure {
+///     println!("delivering despite {}", failure);
+/// }
+/// #
+/// # Ok(()) }) }
+/// # #[cfg(feature = "runtime-tokio")]
+/// # fn main() {}
+/// ```
+pub struct MtaSts<F> {
+    fetcher: F,
+    cache: Mutex<HashMap<String, CachedPolicy>>,
+    reporter: Option<Reporter>,
+}
+
+type Reporter = Arc<dyn Fn(&str, &Error) + Send + Sync>;
+
+struct CachedPolicy {
+    id: Option<String>,
+    policy: MtaStsPolicy,
+    expires: SystemTime,
+}
+
+impl<F: PolicyFetcher> MtaSts<F> {
+    /// Create a new instance fetching policies with `fetcher`.
+    pub fn new(fetcher: F) -> Self {
+        Self {
+            fetcher,
+            cache: Mutex::new(HashMap::new()),
+            reporter: None,
+        }
+    }
+
+    /// Sets a callback invoked with the policy domain for failures that do not fail the
+    /// connection, such as validation failures under a `testing` policy.
+    pub fn on_testing_failure<C>(mut self, callback: C) -> Self
+    where
+        C: Fn(&str, &Error) + Send + Sync + 'static,
+    {
+        self.reporter = Some(Arc::new(callback));
+        self
+    }
+
+    /// Returns the policy of `domain`, fetching it if needed.
+    ///
+    /// `id` is the policy id from the `_mta-sts` TXT record of the domain, or `None` if there is
+    /// no such record. A cached policy is used until it expires or the id changes. If refreshing
+    /// fails, the cached policy stays in use until it expires.
+    pub async fn policy(
+        &self,
+        domain: &str,
+        id: Option<&str>,
+    ) -> Result<Option<MtaStsPolicy>, Error> {
+        let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+        let now = SystemTime::now();
+        let cached = {
+            let cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+            cache
+                .get(&domain)
+                .filter(|cached| now < cached.expires)
+                .map(|cached| (cached.id.clone(), cached.policy.clone()))
+        };
+
+        let id = match (id, cached) {
+            (None, cached) => return Ok(cached.map(|(_, policy)| policy)),
+            (Some(id), Some((cached_id, policy))) if cached_id.as_deref() == Some(id) => {
+                return Ok(Some(policy))
+            }
+            (Some(id), _) => id,
+        };
+
+        let fetched = match self.fetcher.fetch(&domain).await {
+            Ok(text) => text.parse::<MtaStsPolicy>(),
+            Err(err) => Err(Error::Fetch(err)),
+        };
+        let mut cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+        match fetched {
+            Ok(policy) => {
+                cache.insert(
+                    domain,
+                    CachedPolicy {
+                        id: Some(id.to_string()),
+                        policy: policy.clone(),
+                        expires: now + policy.max_age,
+                    },
+                );
+                Ok(Some(policy))
+            }
+            Err(err) => match cache.get(&domain).filter(|cached| now < cached.expires) {
+                Some(cached) => Ok(Some(cached.policy.clone())),
+                None => Err(err),
+            },
+        }
+    }
+
+    /// Connects to the MX host `mx` of `domain`, applying its policy.
+    ///
+    /// `open` opens the connection to `mx`, including any STARTTLS exchange. Under an `enforce`
+    /// policy, `mx` has to match the policy and the server certificate has to be valid for `mx`;
+    /// the `danger_*` settings of `connector` are overridden. Under a `testing` policy, the
+    /// certificate is validated as well, but if that fails the connection is opened again with
+    /// validation disabled, as described in [RFC 8461, section
+    /// 5](https://tools.ietf.org/html/rfc8461#section-5). An MX mismatch under a `testing`
+    /// policy does not fail the connection either, and `connector` is then used as is.
+    ///
+    /// If the policy cannot be fetched and none is cached, the connection proceeds as if the
+    /// domain had no policy.
+    ///
+    /// Along with the stream, the failure that did not fail the connection is returned, to be
+    /// reported with TLS-RPT. It is also passed to the callback set with
+    /// [`on_testing_failure`](MtaSts::on_testing_failure).
+    pub async fn connect<O, Fut, S>(
+        &self,
+        connector: TlsConnector,
+        domain: &str,
+        id: Option<&str>,
+        mx: impl Into<Host>,
+        mut open: O,
+    ) -> Result<(TlsStream<S>, Option<Error>), Error>
+    where
+        O: FnMut() -> Fut,
+        Fut: Future<Output = io::Result<S>>,
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let mx = mx.into().as_string();
+        let policy = match self.policy(domain, id).await {
+            Ok(policy) => policy,
+            Err(err) => {
+                self.report(domain, &err);
+                let stream = open().await.map_err(Error::Open)?;
+                return Ok((connector.connect(mx, stream).await?, Some(err)));
+            }
+        };
+
+        let mut connector = connector;
+        if let Some(policy) = policy {
+            let mismatch = Error::MxMismatch {
+                mx: mx.clone(),
+                patterns: policy.mx.clone(),
+            };
+            match policy.mode {
+                MtaStsMode::Enforce => {
+                    if !policy.matches_mx(&mx) {
+                        return Err(mismatch);
+                    }
+                    connector = connector
+                        .danger_accept_invalid_certs(false)
+                        .danger_accept_invalid_hostnames(false)
+                        .use_sni(true);
+                }
+                MtaStsMode::Testing if !policy.matches_mx(&mx) => {
+                    self.report(domain, &mismatch);
+                    let stream = open().await.map_err(Error::Open)?;
+                    return Ok((connector.connect(mx, stream).await?, Some(mismatch)));
+                }
+                MtaStsMode::Testing => {
+                    connector = connector
+                        .danger_accept_invalid_certs(false)
+                        .danger_accept_invalid_hostnames(false)
+                        .use_sni(true);
+                    let stream = open().await.map_err(Error::Open)?;
+                    let err = match connector.connect(mx.as_str(), stream).await {
+                        Ok(stream) => return Ok((stream, None)),
+                        Err(err) => Error::Connect(err),
+                    };
+                    self.report(domain, &err);
+                    connector = connector
+                        .danger_accept_invalid_certs(true)
+                        .danger_accept_invalid_hostnames(true);
+                    let stream = open().await.map_err(Error::Open)?;
+                    return Ok((connector.connect(mx, stream).await?, Some(err)));
+                }
+                MtaStsMode::None => {}
+            }
+        }
+
+        let stream = open().await.map_err(Error::Open)?;
+        Ok((connector.connect(mx, stream).await?, None))
+    }
+
+    fn report(&self, domain: &str, err: &Error) {
+        if let Some(reporter) = &self.reporter {
+            reporter(domain, err);
+        }
+    }
+}
+
+impl<F> fmt::Debug for MtaSts<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+        f.debug_struct("MtaSts")
+            .field("cached", &cache.keys())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const POLICY: &str =
+        "version: STSv1\nmode: enforce\nmx: localhost\nmx: *.example.org\nmax_age: 600\n";
+
+    struct Stub {
+        text: Mutex<io::Result<String>>,
+        fetches: AtomicUsize,
+    }
+
+    impl Stub {
+        fn new(text: &str) -> Self {
+            Self {
+                text: Mutex::new(Ok(text.to_string())),
+                fetches: AtomicUsize::new(0),
+            }
+        }
+
+        fn fail(&self) {
+            *self.text.lock().unwrap() = Err(io::ErrorKind::NotFound.into());
+        }
+    }
+
+    impl PolicyFetcher for Stub {
+        fn fetch<'a>(
+            &'a self,
+            _domain: &'a str,
+        ) -> Pin<Box<dyn Future<Output = io::Result<String>> + Send + 'a>> {
+            self.fetches.fetch_add(1, Ordering::SeqCst);
+            let text = match &*self.text.lock().unwrap() {
+                Ok(text) => Ok(text.clone()),
+                Err(err) => Err(err.kind().into()),
+            };
+            Box::pin(async move { text })
+        }
+    }
+
+    #[test]
+    fn parse_policy() {
+        let policy: MtaStsPolicy = POLICY.replace('\n', "\r\n").parse().unwrap();
+        assert_eq!(policy.mode, MtaStsMode::Enforce);
+        assert_eq!(policy.mx, ["localhost", "*.example.org"]);
+        assert_eq!(policy.max_age, Duration::from_secs(600));
+
+        let policy: MtaStsPolicy = "version: STSv1\nmode: none\nmax_age: 99999999999\nfoo: bar\n"
+            .parse()
+            .unwrap();
+        assert_eq!(policy.max_age, Duration::from_secs(MAX_AGE_LIMIT));
+
+        let policy: MtaStsPolicy = "mode: testing\nmx: a\nmax_age: 1\nversion: STSv1"
+            .parse()
+            .unwrap();
+        assert_eq!(policy.mode, MtaStsMode::Testing);
+
+        for text in &[
+            "mode: enforce\nmx: a\nmax_age: 1",
+            "version: STSv2\nmode: enforce\nmx: a\nmax_age: 1",
+            "version: STSv1\nmode: strict\nmx: a\nmax_age: 1",
+            "version: STSv1\nmode: enforce\nmax_age: 1",
+            "version: STSv1\nmode: testing\nmx: a",
+        ] {
+            assert!(matches!(
+                text.parse::<MtaStsPolicy>(),
+                Err(Error::InvalidPolicy(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn mx_patterns() {
+        let policy: MtaStsPolicy = POLICY.parse().unwrap();
+        assert!(policy.matches_mx("localhost"));
+        assert!(policy.matches_mx("MX1.example.org."));
+        assert!(!policy.matches_mx("example.org"));
+        assert!(!policy.matches_mx("a.mx1.example.org"));
+        assert!(!policy.matches_mx("mx.example.com"));
+    }
+
+    #[async_std::test]
+    async fn cache() {
+        let mta_sts = MtaSts::new(Stub::new(POLICY));
+        assert_eq!(mta_sts.policy("example.org", None).await.unwrap(), None);
+        assert!(mta_sts
+            .policy("example.org", Some("1"))
+            .await
+            .unwrap()
+            .is_some());
+        assert!(mta_sts
+            .policy("Example.org.", Some("1"))
+            .await
+            .unwrap()
+            .is_some());
+        assert!(mta_sts.policy("example.org", None).await.unwrap().is_some());
+        assert_eq!(mta_sts.fetcher.fetches.load(Ordering::SeqCst), 1);
+
+        mta_sts.fetcher.fail();
+        assert!(mta_sts
+            .policy("example.org", Some("2"))
+            .await
+            .unwrap()
+            .is_some());
+        assert_eq!(mta_sts.fetcher.fetches.load(Ordering::SeqCst), 2);
+        assert!(matches!(
+            mta_sts.policy("example.com", Some("1")).await,
+            Err(Error::Fetch(_))
+        ));
+    }
+
+    #[cfg(feature = "runtime-async-std")]
+    #[async_std::test]
+    async fn enforce() {
+        use crate::test_util::{ca, hello_all, localhost, tcp};
+
+        let addr = hello_all(localhost().build().unwrap()).await;
+        let open = || async move { Ok(tcp(addr).await) };
+        let mta_sts = MtaSts::new(Stub::new(POLICY));
+
+        let connector = TlsConnector::new().add_root_certificate(ca());
+        let res = mta_sts
+            .connect(connector, "example.org", Some("1"), "localhost", open)
+            .await;
+        assert!(matches!(res, Ok((_, None))));
+
+        let connector = TlsConnector::new().danger_accept_invalid_certs(true);
+        let res = mta_sts
+            .connect(connector, "example.org", Some("1"), "localhost", open)
+            .await;
+        assert!(matches!(res, Err(Error::Connect(_))));
+
+        let connector = TlsConnector::new().add_root_certificate(ca());
+        let res = mta_sts
+            .connect(connector, "example.org", Some("1"), "mx.example.com", open)
+            .await;
+        assert!(matches!(res, Err(Error::MxMismatch { .. })));
+    }
+
+    #[cfg(feature = "runtime-async-std")]
+    #[async_std::test]
+    async fn testing() {
+        use crate::test_util::{ca, hello_all, localhost, tcp};
+
+        let addr = hello_all(localhost().build().unwrap()).await;
+        let opened = Arc::new(AtomicUsize::new(0));
+        let open = || {
+            let opened = opened.clone();
+            async move {
+                opened.fetch_add(1, Ordering::SeqCst);
+                Ok(tcp(addr).await)
+            }
+        };
+        let reported = Arc::new(AtomicUsize::new(0));
+        let mta_sts = MtaSts::new(Stub::new(&POLICY.replace("enforce", "testing")))
+            .on_testing_failure({
+                let reported = reported.clone();
+                move |_, _| {
+                    reported.fetch_add(1, Ordering::SeqCst);
+                }
+            });
+
+        let connector = TlsConnector::new().add_root_certificate(ca());
+        let res = mta_sts
+            .connect(connector, "example.org", Some("1"), "localhost", open)
+            .await;
+        assert!(matches!(res, Ok((_, None))));
+        assert_eq!(opened.load(Ordering::SeqCst), 1);
+
+        // the certificate is not trusted, so the connection is retried without validation
+        let res = mta_sts
+            .connect(
+                TlsConnector::new(),
+                "example.org",
+                Some("1"),
+                "localhost",
+                open,
+            )
+            .await;
+        assert!(matches!(res, Ok((_, Some(Error::Connect(_))))));
+        assert_eq!(opened.load(Ordering::SeqCst), 3);
+        assert_eq!(reported.load(Ordering::SeqCst), 1);
+
+        let connector = TlsConnector::new().danger_accept_invalid_hostnames(true);
+        let connector = connector.add_root_certificate(ca());
+        let res = mta_sts
+            .connect(connector, "example.org", Some("1"), "mx.example.com", open)
+            .await;
+        assert!(matches!(res, Ok((_, Some(Error::MxMismatch { .. })))));
+        assert_eq!(reported.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,681 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use x509_parser::prelude::{FromDer, GeneralName, ParsedExtension, X509Certificate};
+
+use crate::certificate::{system_time, CertificateInfo};
+use crate::host::Host;
+use crate::revocation::reason;
+use crate::runtime::{AsyncRead, AsyncWrite};
+use crate::{
+    Certificate, CertificateError, ConnectError, RevocationPolicy, RevocationReason, TlsConnector,
+    TlsStream,
+};
+
+/// Allowed difference between the clock of the responder and ours.
+const CLOCK_SKEW: Duration = Duration::from_secs(5 * 60);
+
+/// How long a response without `nextUpdate` is current by default.
+const MAX_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// id-ad-ocsp
+const ACCESS_OCSP: &str = "1.3.6.1.5.5.7.48.1";
+
+/// Sends OCSP requests to responders for an [`OcspChecker`].
+///
+/// Implementations `POST` the request with the content type `application/ocsp-request` using
+/// an HTTP client of their choice. Tests can return fixed responses instead.
+pub trait OcspTransport: Send + Sync {
+    /// Sends the DER-encoded `request` to the responder at `url`, returning the body of the
+    /// response.
+    fn send<'a>(
+        &'a self,
+        url: &'a str,
+        request: &'a [u8],
+    ) -> Pin<Box<dyn Future<Output = io::Result<Vec<u8>>> + Send + 'a>>;
+}
+
+/// Checks the revocation status of peer certificates with OCSP, caching responses until their
+/// `nextUpdate`.
+///
+/// The responder is taken from the authority information access extension of the certificate,
+/// unless one is set with [`responder`](OcspChecker::responder). Responses must be signed by
+/// the issuer of the certificate or a responder it delegated to, and must be current.
+///
+/// The TLS backends only expose the leaf certificate of the peer, so its issuer has to be added
+/// with [`add_issuer`](OcspChecker::add_issuer), and intermediate certificates are not checked.
+/// Verifying responses is only supported with the OpenSSL backend; elsewhere every check fails
+/// with [`Error::Unsupported`].
+///
+/// If no usable response can be obtained, the [`RevocationPolicy`] decides whether the
+/// certificate is accepted. Revoked certificates and forged responses are always rejected.
+///
+/// # Example
+///
+/// ```no_run
+/// # #[cfg(feature = "runtime-async-std")]
+/// # fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> { async_std::task::block_on(async {
+/// #
+/// use std::future::Future;
+/// use std::io;
+/// use std::pin::Pin;
+/// use async_std::net::TcpStream;
+/// use async_native_tls::{Certificate, OcspChecker, OcspTransport, TlsConnector};
+///
+/// struct Http;
+///
+/// impl OcspTransport for Http {
+///     fn send<'a>(
+///         &'a self,
+///         url: &'a str,
+///         request: &'a [u8],
+///     ) -> Pin<Box<dyn Future<Output = io::Result<Vec<u8>>> + Send + 'a>> {
+///         Box::pin(async move {
+///             Err(io::Error::new(
+///                 io::ErrorKind::Other,
+///                 format!("no HTTP client to POST {} bytes to {}", request.len(), url),
+///             ))
+///         })
+///     }
+/// }
+///
+/// let issuer = Certificate::from_pem(&std::fs::read("internal-ca.pem")?)?;
+/// let mut ocsp = OcspChecker::new(Http);
+/// ocsp.add_issuer(&issuer)?;
+///
+/// let stream = TcpStream::connect("db.internal:5432").await?;
+/// let stream = ocsp
+///     .connect(TlsConnector::new(), "db.internal", stream)
+///     .await?;
+/// #
+/// # Ok(()) }) }
+/// # #[cfg(feature = "runtime-tokio")]
+/// # fn main() {}
+/// ```
+pub struct OcspChecker<T> {
+    transport: T,
+    issuers: Vec<Issuer>,
+    responder: Option<String>,
+    policy: RevocationPolicy,
+    max_age: Duration,
+    cache: Mutex<HashMap<CertId, Cached>>,
+}
+
+/// An error returned from checking a certificate with OCSP.
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    /// Connecting failed.
+    #[error("Connect({0})")]
+    Connect(#[from] ConnectError),
+    /// The certificate has been revoked.
+    #[error("certificate {serial} of {issuer} was revoked")]
+    Revoked {
+        /// The issuer of the certificate.
+        issuer: String,
+        /// The serial number of the certificate, as colon-separated hex.
+        serial: String,
+        /// The reason given by the responder, if any.
+        reason: Option<RevocationReason>,
+        /// When the certificate was revoked.
+        revoked_at: SystemTime,
+    },
+    /// The responder does not know the certificate, under [`RevocationPolicy::FailClosed`].
+    #[error("responder does not know certificate {serial} of {issuer}")]
+    Unknown {
+        /// The issuer of the certificate.
+        issuer: String,
+        /// The serial number of the certificate, as colon-separated hex.
+        serial: String,
+    },
+    /// The issuer of the certificate was not added, under [`RevocationPolicy::FailClosed`].
+    #[error("no issuer certificate for {issuer}")]
+    NoIssuer {
+        /// The issuer of the certificate.
+        issuer: String,
+    },
+    /// The certificate names no responder and none is configured, under
+    /// [`RevocationPolicy::FailClosed`].
+    #[error("no OCSP responder for the certificate")]
+    NoResponder,
+    /// Sending the request failed, under [`RevocationPolicy::FailClosed`].
+    #[error("Transport({0})")]
+    Transport(io::Error),
+    /// The responder returned an error status, under [`RevocationPolicy::FailClosed`].
+    #[error("responder returned status {0}")]
+    Responder(u8),
+    /// The response could not be parsed or has no status for the certificate, under
+    /// [`RevocationPolicy::FailClosed`].
+    #[error("invalid OCSP response: {0}")]
+    Parse(String),
+    /// The response is not current, under [`RevocationPolicy::FailClosed`].
+    #[error("OCSP response is not current")]
+    Stale {
+        /// When the response was produced.
+        this_update: SystemTime,
+        /// When the response should have been replaced.
+        next_update: Option<SystemTime>,
+    },
+    /// The response is not signed by the issuer or a responder it delegated to.
+    #[error("OCSP response has an invalid signature")]
+    BadSignature,
+    /// The peer presented no certificate.
+    #[error("peer presented no certificate")]
+    NoPeerCertificate,
+    /// A certificate could not be parsed.
+    #[error("Certificate({0})")]
+    Certificate(#[from] CertificateError),
+    /// Verifying OCSP responses is not supported by the TLS backend.
+    #[error("verifying OCSP responses is not supported on this platform")]
+    Unsupported,
+}
+
+impl Error {
+    /// Whether the error only means the status could not be determined.
+    fn is_soft(&self) -> bool {
+        matches!(
+            self,
+            Error::Unknown { .. }
+                | Error::NoIssuer { .. }
+                | Error::NoResponder
+                | Error::Transport(_)
+                | Error::Responder(_)
+                | Error::Parse(_)
+                | Error::Stale { .. }
+        )
+    }
+}
+
+impl From<native_tls::Error> for Error {
+    fn from(err: native_tls::Error) -> Self {
+        Error::Certificate(err.into())
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Issuer {
+    subject: Vec<u8>,
+    der: Vec<u8>,
+}
+
+/// Identifies a certificate in the cache.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct CertId {
+    issuer: Vec<u8>,
+    serial: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Status {
+    Good,
+    Revoked {
+        revoked_at: SystemTime,
+        reason: Option<RevocationReason>,
+    },
+    Unknown,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Cached {
+    status: Status,
+    next_update: SystemTime,
+}
+
+impl<T: OcspTransport> OcspChecker<T> {
+    /// Create a new checker sending requests with `transport`.
+    pub fn new(transport: T) -> Self {
+        Self {
+            transport,
+            issuers: vec![],
+            responder: None,
+            policy: RevocationPolicy::default(),
+            max_age: MAX_AGE,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Sets what to do when the status of a certificate cannot be determined.
+    ///
+    /// Defaults to [`RevocationPolicy::FailOpen`].
+    pub fn policy(mut self, policy: RevocationPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Sends all requests to the responder at `url`, ignoring the responders named in
+    /// certificates.
+    pub fn responder(mut self, url: impl Into<String>) -> Self {
+        self.responder = Some(url.into());
+        self
+    }
+
+    /// Sets how long after its `thisUpdate` a response without `nextUpdate` is current.
+    ///
+    /// Such responses are never cached, as newer information is always available. Defaults to
+    /// one hour.
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = max_age;
+        self
+    }
+
+    /// Adds the certificate of an issuer whose certificates are checked.
+    pub fn add_issuer(&mut self, issuer: &Certificate) -> Result<(), Error> {
+        let der = issuer.to_der()?;
+        let (_, cert) =
+            X509Certificate::from_der(&der).map_err(|e| CertificateError::Parse(e.to_string()))?;
+        let subject = cert.subject().as_raw().to_vec();
+        self.issuers.push(Issuer { subject, der });
+        Ok(())
+    }
+
+    /// Connects to `host` and checks the certificate the server presents.
+    pub async fn connect<S>(
+        &self,
+        connector: TlsConnector,
+        host: impl Into<Host>,
+        stream: S,
+    ) -> Result<TlsStream<S>, Error>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let stream = connector.connect(host, stream).await?;
+        self.verify(&stream).await?;
+        Ok(stream)
+    }
+
+    /// Checks the peer certificate of `stream`, as presented by a server or a client.
+    pub async fn verify<S>(&self, stream: &TlsStream<S>) -> Result<(), Error>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let cert = stream.peer_certificate()?.ok_or(Error::NoPeerCertificate)?;
+        self.check(&cert).await
+    }
+
+    /// Checks the revocation status of a certificate.
+    pub async fn check(&self, cert: &Certificate) -> Result<(), Error> {
+        let der = cert.to_der()?;
+        let (_, x509) =
+            X509Certificate::from_der(&der).map_err(|e| CertificateError::Parse(e.to_string()))?;
+        let info = CertificateInfo::from_x509(&x509, &der);
+        let status = match self.status(&x509, &der, &info).await {
+            Ok(status) => status,
+            Err(err) if err.is_soft() && self.policy == RevocationPolicy::FailOpen => return Ok(()),
+            Err(err) => return Err(err),
+        };
+        match status {
+            Status::Good => Ok(()),
+            Status::Revoked { revoked_at, reason } => Err(Error::Revoked {
+                serial: info.serial_hex(),
+                issuer: info.issuer,
+                reason,
+                revoked_at,
+            }),
+            Status::Unknown if self.policy == RevocationPolicy::FailOpen => Ok(()),
+            Status::Unknown => Err(Error::Unknown {
+                serial: info.serial_hex(),
+                issuer: info.issuer,
+            }),
+        }
+    }
+
+    async fn status(
+        &self,
+        cert: &X509Certificate<'_>,
+        der: &[u8],
+        info: &CertificateInfo,
+    ) -> Result<Status, Error> {
+        let issuer = self
+            .issuers
+            .iter()
+            .find(|issuer| issuer.subject == cert.issuer().as_raw())
+            .ok_or_else(|| Error::NoIssuer {
+                issuer: info.issuer.clone(),
+            })?;
+        let id = CertId {
+            issuer: issuer.subject.clone(),
+            serial: info.serial.clone(),
+        };
+
+        if let Some(cached) = self.lock().get(&id) {
+            if SystemTime::now() <= cached.next_update {
+                return Ok(cached.status);
+            }
+        }
+
+        let url = match &self.responder {
+            Some(url) => url.clone(),
+            None => responder(cert).ok_or(Error::NoResponder)?,
+        };
+        let response = self
+            .transport
+            .send(&url, &request(der, issuer)?)
+            .await
+            .map_err(Error::Transport)?;
+        let single = parse_response(&response, der, issuer, self.max_age)?;
+        if let Some(next_update) = single.next_update {
+            self.lock().insert(
+                id,
+                Cached {
+                    status: single.status,
+                    next_update,
+                },
+            );
+        }
+        Ok(single.status)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<CertId, Cached>> {
+        self.cache.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<T> fmt::Debug for OcspChecker<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+        f.debug_struct("OcspChecker")
+            .field("issuers", &self.issuers.len())
+            .field("responder", &self.responder)
+            .field("policy", &self.policy)
+            .field("cached", &cache.len())
+            .finish()
+    }
+}
+
+/// Returns the first OCSP responder named in the authority information access extension.
+fn responder(cert: &X509Certificate<'_>) -> Option<String> {
+    cert.extensions()
+        .iter()
+        .filter_map(|ext| match ext.parsed_extension() {
+            ParsedExtension::AuthorityInfoAccess(aia) => Some(aia),
+            _ => None,
+        })
+        .flat_map(|aia| aia.iter())
+        .filter(|desc| desc.access_method.to_id_string() == ACCESS_OCSP)
+        .find_map(|desc| match desc.access_location {
+            GeneralName::URI(uri) => Some(uri.to_string()),
+            _ => None,
+        })
+}
+
+/// The status of a certificate in a verified response.
+struct SingleResponse {
+    status: Status,
+    next_update: Option<SystemTime>,
+}
+
+/// Encodes an `OCSPRequest` for `cert`, without extensions.
+#[cfg(not(any(target_os = "windows", target_vendor = "apple")))]
+fn request(cert: &[u8], issuer: &Issuer) -> Result<Vec<u8>, Error> {
+    use openssl::ocsp::OcspRequest;
+
+    let mut request = OcspRequest::new()?;
+    request.add_id(cert_id(cert, issuer)?)?;
+    Ok(request.to_der()?)
+}
+
+/// Verifies an `OCSPResponse` and returns the status of `cert`, if it is current.
+#[cfg(not(any(target_os = "windows", target_vendor = "apple")))]
+fn parse_response(
+    data: &[u8],
+    cert: &[u8],
+    issuer: &Issuer,
+    max_age: Duration,
+) -> Result<SingleResponse, Error> {
+    use std::convert::TryFrom;
+
+    use openssl::ocsp::{
+        OcspCertStatus, OcspFlag, OcspResponse, OcspResponseStatus, OcspRevokedStatus,
+    };
+    use openssl::stack::Stack;
+    use openssl::x509::store::X509StoreBuilder;
+    use openssl::x509::verify::X509VerifyFlags;
+    use openssl::x509::X509;
+
+    let response = OcspResponse::from_der(data)?;
+    if response.status() != OcspResponseStatus::SUCCESSFUL {
+        return Err(Error::Responder(response.status().as_raw() as u8));
+    }
+    let basic = response.basic()?;
+
+    // the issuer may sign itself; delegated responders must chain to it
+    let signer = X509::from_der(&issuer.der)?;
+    let mut certs = Stack::new()?;
+    certs.push(signer.clone())?;
+    let mut store = X509StoreBuilder::new()?;
+    store.add_cert(signer)?;
+    store.set_flags(X509VerifyFlags::PARTIAL_CHAIN)?;
+    if basic
+        .verify(&certs, &store.build(), OcspFlag::TRUST_OTHER)
+        .is_err()
+    {
+        return Err(Error::BadSignature);
+    }
+
+    let id = cert_id(cert, issuer)?;
+    let single = basic
+        .find_status(&id)
+        .ok_or_else(|| Error::Parse("no status for the certificate".to_string()))?;
+    let status = match single.status {
+        OcspCertStatus::GOOD => Status::Good,
+        OcspCertStatus::REVOKED => Status::Revoked {
+            revoked_at: match single.revocation_time {
+                Some(time) => generalized_time(time)?,
+                None => return Err(Error::Parse("missing revocation time".to_string())),
+            },
+            reason: match single.reason {
+                OcspRevokedStatus::NO_STATUS => None,
+                code => Some(reason(code.as_raw() as u8)),
+            },
+        },
+        _ => Status::Unknown,
+    };
+    let next_update = single.next_update().map(generalized_time).transpose()?;
+    let this_update = generalized_time(single.this_update)?;
+
+    // a response without nextUpdate is only current for max_age
+    let max_age = next_update
+        .is_none()
+        .then(|| u32::try_from(max_age.as_secs()).unwrap_or(u32::MAX));
+    if single
+        .check_validity(CLOCK_SKEW.as_secs() as u32, max_age)
+        .is_err()
+    {
+        return Err(Error::Stale {
+            this_update,
+            next_update,
+        });
+    }
+    Ok(SingleResponse {
+        status,
+        next_update,
+    })
+}
+
+#[cfg(not(any(target_os = "windows", target_vendor = "apple")))]
+fn cert_id(cert: &[u8], issuer: &Issuer) -> Result<openssl::ocsp::OcspCertId, Error> {
+    use openssl::hash::MessageDigest;
+    use openssl::ocsp::OcspCertId;
+    use openssl::x509::X509;
+
+    let cert = X509::from_der(cert)?;
+    let issuer = X509::from_der(&issuer.der)?;
+    Ok(OcspCertId::from_cert(
+        MessageDigest::sha1(),
+        &cert,
+        &issuer,
+    )?)
+}
+
+#[cfg(not(any(target_os = "windows", target_vendor = "apple")))]
+fn generalized_time(time: &openssl::asn1::Asn1GeneralizedTimeRef) -> Result<SystemTime, Error> {
+    use foreign_types::ForeignTypeRef;
+    use openssl::asn1::{Asn1Time, Asn1TimeRef};
+
+    // an ASN1_TIME holds either a UTCTime or a GeneralizedTime
+    let time = unsafe { Asn1TimeRef::from_ptr(time.as_ptr().cast()) };
+    let diff = Asn1Time::from_unix(0)?.diff(time)?;
+    Ok(system_time(
+        i64::from(diff.days) * 24 * 60 * 60 + i64::from(diff.secs),
+    ))
+}
+
+#[cfg(any(target_os = "windows", target_vendor = "apple"))]
+fn request(_cert: &[u8], _issuer: &Issuer) -> Result<Vec<u8>, Error> {
+    Err(Error::Unsupported)
+}
+
+#[cfg(any(target_os = "windows", target_vendor = "apple"))]
+fn parse_response(
+    _data: &[u8],
+    _cert: &[u8],
+    _issuer: &Issuer,
+    _max_age: Duration,
+) -> Result<SingleResponse, Error> {
+    Err(Error::Unsupported)
+}
+
+#[cfg(not(any(target_os = "windows", target_vendor = "apple")))]
+impl From<openssl::error::ErrorStack> for Error {
+    fn from(err: openssl::error::ErrorStack) -> Self {
+        Error::Parse(err.to_string())
+    }
+}
+
+#[cfg(all(
+    test,
+    feature = "runtime-async-std",
+    not(any(target_os = "windows", target_vendor = "apple"))
+))]
+mod tests {
+    use super::*;
+
+    /// A responder returning a fixed response, recording the requests it receives.
+    struct Stub {
+        response: Option<Vec<u8>>,
+        requests: Mutex<Vec<(String, Vec<u8>)>>,
+    }
+
+    impl OcspTransport for Stub {
+        fn send<'a>(
+            &'a self,
+            url: &'a str,
+            request: &'a [u8],
+        ) -> Pin<Box<dyn Future<Output = io::Result<Vec<u8>>> + Send + 'a>> {
+            self.requests
+                .lock()
+                .unwrap()
+                .push((url.to_string(), request.to_vec()));
+            let response = self
+                .response
+                .clone()
+                .ok_or_else(|| io::Error::from(io::ErrorKind::ConnectionRefused));
+            Box::pin(async move { response })
+        }
+    }
+
+    fn certificate(path: &str) -> Certificate {
+        Certificate::from_pem(&std::fs::read(path).unwrap()).unwrap()
+    }
+
+    fn checker(response: Option<Vec<u8>>) -> OcspChecker<Stub> {
+        let stub = Stub {
+            response,
+            requests: Mutex::new(vec![]),
+        };
+        let mut checker = OcspChecker::new(stub).responder("http://ocsp.test");
+        checker.add_issuer(&certificate("tests/ca.pem")).unwrap();
+        checker
+    }
+
+    #[async_std::test]
+    async fn good_and_cached() {
+        let checker = checker(Some(std::fs::read("tests/ocsp-good.der").unwrap()));
+        let leaf = certificate("tests/localhost.pem");
+        checker.check(&leaf).await.unwrap();
+        checker.check(&leaf).await.unwrap();
+
+        let requests = checker.transport.requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].0, "http://ocsp.test");
+        assert_eq!(
+            requests[0].1,
+            std::fs::read("tests/ocsp-request.der").unwrap()
+        );
+    }
+
+    #[async_std::test]
+    async fn revoked() {
+        let checker = checker(Some(std::fs::read("tests/ocsp-revoked.der").unwrap()));
+        match checker.check(&certificate("tests/localhost.pem")).await {
+            Err(Error::Revoked { serial, reason, .. }) => {
+                assert_eq!(serial, "10:01");
+                assert_eq!(reason, Some(RevocationReason::KeyCompromise));
+            }
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    #[async_std::test]
+    async fn failures() {
+        let leaf = certificate("tests/localhost.pem");
+
+        let mut forged = std::fs::read("tests/ocsp-good.der").unwrap();
+        *forged.last_mut().unwrap() ^= 1;
+        assert!(matches!(
+            checker(Some(forged)).check(&leaf).await,
+            Err(Error::BadSignature)
+        ));
+
+        assert!(checker(None).check(&leaf).await.is_ok());
+        assert!(matches!(
+            checker(None)
+                .policy(RevocationPolicy::FailClosed)
+                .check(&leaf)
+                .await,
+            Err(Error::Transport(_))
+        ));
+
+        // the response was current from Jan 1 to Jan 8 2020
+        let this_update = SystemTime::UNIX_EPOCH + Duration::from_secs(1577836800);
+        let stale = checker(Some(std::fs::read("tests/ocsp-stale.der").unwrap()))
+            .policy(RevocationPolicy::FailClosed);
+        match stale.check(&leaf).await {
+            Err(Error::Stale {
+                this_update: this,
+                next_update: Some(next),
+            }) => {
+                assert_eq!(this, this_update);
+                assert_eq!(next, this_update + Duration::from_secs(7 * 24 * 60 * 60));
+            }
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    #[async_std::test]
+    async fn max_age() {
+        let leaf = certificate("tests/localhost.pem");
+        // produced on Jan 1 2020
+        let response = std::fs::read("tests/ocsp-no-next-update.der").unwrap();
+
+        let stale = checker(Some(response.clone())).policy(RevocationPolicy::FailClosed);
+        assert!(matches!(
+            stale.check(&leaf).await,
+            Err(Error::Stale {
+                next_update: None,
+                ..
+            })
+        ));
+
+        let fresh = checker(Some(response))
+            .policy(RevocationPolicy::FailClosed)
+            .max_age(Duration::from_secs(100 * 365 * 24 * 60 * 60));
+        fresh.check(&leaf).await.unwrap();
+        fresh.check(&leaf).await.unwrap();
+        assert_eq!(fresh.transport.requests.lock().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,298 @@
+use std::sync::Arc;
+
+use openssl::error::ErrorStack;
+use openssl::ssl::{SslAcceptorBuilder, SslConnectorBuilder, SslRef};
+
+use crate::{KeyLog, TlsAcceptorBuilder, TlsConnector, TlsStream};
+
+mod sealed {
+    pub trait Sealed {}
+
+    impl Sealed for crate::TlsConnector {}
+    impl Sealed for crate::TlsAcceptorBuilder {}
+    impl<S> Sealed for crate::TlsStream<S> {}
+}
+
+/// Settings of the OpenSSL backend for a [`TlsConnector`].
+///
+/// # Example
+///
+/// ```no_run
+/// # #[cfg(feature = "runtime-async-std")]
+/// # fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> { async_std::task::block_on(async {
+/// #
+/// use async_std::net::TcpStream;
+/// use async_native_tls::{TlsConnector, TlsConnectorExt, TlsStreamExt};
+///
+/// let stream = TcpStream::connect("example.com:443").await?;
+/// let stream = TlsConnector::new()
+///     .configure_openssl(|builder| {
+///         builder.set_cipher_list("ECDHE+AESGCM")?;
+///         builder.set_groups_list("X25519:P-256")
+///     })
+///     .connect("example.com", stream)
+///     .await?;
+/// if let Some(ssl) = stream.ssl() {
+///     println!("negotiated {:?}", ssl.current_cipher().map(|cipher| cipher.name()));
+/// }
+/// #
+/// # Ok(()) }) }
+/// # #[cfg(feature = "runtime-tokio")]
+/// # fn main() {}
+/// ```
+pub trait TlsConnectorExt: sealed::Sealed + Sized {
+    /// Adds a callback customizing the OpenSSL context of the connector, such as cipher lists,
+    /// curves, session caching or verify callbacks.
+    ///
+    /// The callback runs when the OpenSSL context is built, after the portable settings were
+    /// applied. That is for every connection, except with
+    /// [`resume_sessions`](TlsConnectorExt::resume_sessions): sessions can only be resumed with
+    /// the context they were established with, so the context is built, and the callback runs,
+    /// once for the connector and again only after its settings changed.
+    /// Connectors with callbacks always connect through OpenSSL, and fail with
+    /// [`ConnectError::Unsupported`](crate::ConnectError::Unsupported) when they have settings
+    /// that cannot be carried over from native-tls: identities set with
+    /// [`TlsConnector::identity`], rather than `TlsConnector::identity_from_pem` of the `pkcs8`
+    /// feature, and connectors created from a `native_tls::TlsConnectorBuilder`.
+    fn configure_openssl<F>(self, callback: F) -> Self
+    where
+        F: Fn(&mut SslConnectorBuilder) -> Result<(), ErrorStack> + Send + Sync + 'static;
+
+    /// Caches the sessions of this connector by host, and resumes them on later connections to
+    /// the same host, saving the full handshake. Works with TLS 1.2 and 1.3.
+    ///
+    /// The cache is dropped when settings of the connector change, and OpenSSL does not resume
+    /// sessions of connections that were not closed cleanly. Whether a connection was resumed is
+    /// reported by [`TlsStream::session_resumed`]. Like
+    /// [`configure_openssl`](TlsConnectorExt::configure_openssl), this makes the connector use
+    /// OpenSSL. Its callbacks then customize the context shared by all connections of the
+    /// connector, rather than that of each connection.
+    fn resume_sessions(self) -> Self;
+
+    /// Logs the secrets of every session to `key_log`, so that captured traffic can be
+    /// decrypted.
+    ///
+    /// Like [`configure_openssl`](TlsConnectorExt::configure_openssl), this makes the connector
+    /// use OpenSSL.
+    fn key_log(self, key_log: KeyLog) -> Self {
+        self.configure_openssl(move |builder| {
+            key_log.install(builder);
+            Ok(())
+        })
+    }
+}
+
+impl TlsConnectorExt for TlsConnector {
+    fn configure_openssl<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&mut SslConnectorBuilder) -> Result<(), ErrorStack> + Send + Sync + 'static,
+    {
+        self.openssl_mut().configure.push(Box::new(callback));
+        self
+    }
+
+    fn resume_sessions(mut self) -> Self {
+        self.openssl_mut().sessions = Some(Default::default());
+        self
+    }
+}
+
+/// Settings of the OpenSSL backend for a [`TlsAcceptorBuilder`].
+pub trait TlsAcceptorBuilderExt: sealed::Sealed + Sized {
+    /// Adds a callback customizing the OpenSSL context of the acceptor, such as cipher lists,
+    /// curves, session caching or verify callbacks.
+    ///
+    /// The callback runs each time the acceptor is built, after the portable settings were
+    /// applied: once, or again on every reload of a
+    /// [`ReloadableAcceptor`](crate::ReloadableAcceptor) built from this builder.
+    fn configure_openssl<F>(self, callback: F) -> Self
+    where
+        F: Fn(&mut SslAcceptorBuilder) -> Result<(), ErrorStack> + Send + Sync + 'static;
+
+    /// Logs the secrets of every session to `key_log`, so that captured traffic can be
+    /// decrypted.
+    fn key_log(self, key_log: KeyLog) -> Self {
+        self.configure_openssl(move |builder| {
+            key_log.install(builder);
+            Ok(())
+        })
+    }
+}
+
+impl TlsAcceptorBuilderExt for TlsAcceptorBuilder {
+    fn configure_openssl<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&mut SslAcceptorBuilder) -> Result<(), ErrorStack> + Send + Sync + 'static,
+    {
+        self.configure.push(Arc::new(callback));
+        self
+    }
+}
+
+/// Access to the OpenSSL session of a [`TlsStream`].
+pub trait TlsStreamExt: sealed::Sealed {
+    /// Returns the OpenSSL session, if the stream was established through OpenSSL.
+    ///
+    /// This is the case for streams of connectors and acceptors with OpenSSL settings, such as
+    /// [`configure_openssl`](TlsConnectorExt::configure_openssl) or
+    /// [`client_auth`](TlsAcceptorBuilder::client_auth). Streams established through native-tls
+    /// do not expose their session.
+    fn ssl(&self) -> Option<&SslRef>;
+}
+
+impl<S> TlsStreamExt for TlsStream<S> {
+    fn ssl(&self) -> Option<&SslRef> {
+        self.ssl_ref()
+    }
+}
+
+#[cfg(all(test, feature = "runtime-async-std"))]
+mod tests {
+    use super::*;
+    use crate::runtime::{AsyncReadExt, AsyncWriteExt};
+    use crate::test_util::{ca, hello_all, localhost, serve, tcp};
+    use crate::{ConnectError, Protocol};
+    use async_std::net::TcpListener;
+    use openssl::ssl::SslOptions;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    const CIPHER: &str = "ECDHE-ECDSA-AES128-GCM-SHA256";
+
+    #[async_std::test]
+    async fn configures_both_ends() {
+        let acceptor = localhost()
+            .max_protocol_version(Some(Protocol::Tlsv12))
+            .configure_openssl(|builder| builder.set_cipher_list(CIPHER))
+            .build()
+            .unwrap();
+        let (addr, server) = serve(acceptor, |mut stream| async move {
+            stream.close().await.unwrap();
+            stream.ssl().unwrap().current_cipher().unwrap().name()
+        })
+        .await;
+
+        let mut stream = TlsConnector::new()
+            .add_root_certificate(ca())
+            .configure_openssl(|builder| builder.set_cipher_list(CIPHER))
+            .connect("localhost", tcp(addr).await)
+            .await
+            .unwrap();
+        let mut res = vec![];
+        stream.read_to_end(&mut res).await.unwrap();
+        assert_eq!(res, b"hello");
+        let ssl = stream.ssl().unwrap();
+        assert_eq!(ssl.current_cipher().unwrap().name(), CIPHER);
+        assert_eq!(server.await, Some(CIPHER));
+    }
+
+    async fn resumes(acceptor: TlsAcceptorBuilder) -> Vec<bool> {
+        let addr = hello_all(acceptor.build().unwrap()).await;
+        let connector = TlsConnector::new()
+            .add_root_certificate(ca())
+            .resume_sessions();
+        let mut resumed = vec![];
+        for _ in 0..3 {
+            let mut stream = connector
+                .connect("localhost", tcp(addr).await)
+                .await
+                .unwrap();
+            // TLS 1.3 tickets arrive after the handshake
+            stream.read_to_end(&mut vec![]).await.unwrap();
+            resumed.push(stream.session_resumed());
+            stream.close().await.unwrap();
+        }
+        resumed
+    }
+
+    #[async_std::test]
+    async fn resumes_sessions() {
+        assert_eq!(resumes(localhost()).await, [false, true, true]);
+        let tls13 = localhost().configure_openssl(|builder| {
+            builder.clear_options(SslOptions::NO_TLSV1_3);
+            Ok(())
+        });
+        assert_eq!(resumes(tls13).await, [false, true, true]);
+    }
+
+    #[async_std::test]
+    async fn verifies_resumed_sessions() {
+        let tls13 = localhost().configure_openssl(|builder| {
+            builder.clear_options(SslOptions::NO_TLSV1_3);
+            Ok(())
+        });
+        for acceptor in [localhost(), tls13] {
+            let addr = hello_all(acceptor.build().unwrap()).await;
+            let calls = Arc::new(Mutex::new(vec![]));
+            let record = calls.clone();
+            let reject = Arc::new(AtomicBool::new(false));
+            let rejecting = reject.clone();
+            let connector = TlsConnector::new()
+                .add_root_certificate(ca())
+                .resume_sessions()
+                .verify_with(move |chain, _| {
+                    record.lock().unwrap().push(chain.len());
+                    match rejecting.load(Ordering::SeqCst) {
+                        true => Err("revoked".into()),
+                        false => Ok(()),
+                    }
+                });
+            let mut resumed = vec![];
+            for _ in 0..3 {
+                let mut stream = connector
+                    .connect("localhost", tcp(addr).await)
+                    .await
+                    .unwrap();
+                stream.read_to_end(&mut vec![]).await.unwrap();
+                resumed.push(stream.session_resumed());
+                stream.close().await.unwrap();
+            }
+            // sessions resumed from resumed sessions keep the chain too
+            assert_eq!(resumed, [false, true, true]);
+            assert_eq!(*calls.lock().unwrap(), [2, 2, 2]);
+
+            reject.store(true, Ordering::SeqCst);
+            let res = connector.connect("localhost", tcp(addr).await).await;
+            match res {
+                Err(ConnectError::Verification(err)) => assert_eq!(err.to_string(), "revoked"),
+                res => panic!("unexpected result {:?}", res.map(|_| ())),
+            }
+        }
+    }
+
+    #[async_std::test]
+    async fn rejects_opaque_identity() {
+        let cert = std::fs::read("tests/client.pem").unwrap();
+        let key = std::fs::read("tests/client-key.pem").unwrap();
+        let identity = crate::Identity::from_pkcs8(&cert, &key).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let res = TlsConnector::new()
+            .identity(identity)
+            .configure_openssl(|_| Ok(()))
+            .connect("localhost", tcp(listener.local_addr().unwrap()).await)
+            .await;
+        assert!(matches!(res, Err(ConnectError::Unsupported(_))));
+    }
+
+    #[async_std::test]
+    async fn keeps_default_protocol_floor() {
+        use crate::test_util::offered_versions;
+
+        let connector = TlsConnector::new().configure_openssl(|_| Ok(()));
+        assert_eq!(offered_versions(connector).await, [0x0304, 0x0303]);
+    }
+
+    #[async_std::test]
+    async fn rejects_invalid_alpn() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let long = "h".repeat(256);
+        for protocol in ["", long.as_str()] {
+            let res = TlsConnector::new()
+                .request_alpns(&["h2", protocol])
+                .configure_openssl(|_| Ok(()))
+                .connect("localhost", tcp(listener.local_addr().unwrap()).await)
+                .await;
+            assert!(matches!(res, Err(ConnectError::Unsupported(_))));
+        }
+    }
+}
@@ -0,0 +1,445 @@
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use crate::host::Host;
+use crate::runtime::{AsyncRead, AsyncWrite};
+use crate::{ConnectError, TlsConnector, TlsStream};
+
+/// A stream that is either protected by TLS or plaintext.
+#[derive(Debug)]
+pub enum MaybeTlsStream<S> {
+    /// A TLS session over the stream.
+    Tls(TlsStream<S>),
+    /// The raw stream.
+    Plain(S),
+}
+
+impl<S> MaybeTlsStream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    /// Whether the stream is protected by TLS.
+    pub fn is_tls(&self) -> bool {
+        matches!(self, MaybeTlsStream::Tls(_))
+    }
+
+    /// Returns a shared reference to the inner stream.
+    pub fn get_ref(&self) -> &S {
+        match self {
+            MaybeTlsStream::Tls(stream) => stream.get_ref(),
+            MaybeTlsStream::Plain(stream) => stream,
+        }
+    }
+
+    /// Returns a mutable reference to the inner stream.
+    pub fn get_mut(&mut self) -> &mut S {
+        match self {
+            MaybeTlsStream::Tls(stream) => stream.get_mut(),
+            MaybeTlsStream::Plain(stream) => stream,
+        }
+    }
+}
+
+#[cfg(feature = "runtime-async-std")]
+impl<S> AsyncRead for MaybeTlsStream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            MaybeTlsStream::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+            MaybeTlsStream::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+#[cfg(feature = "runtime-tokio")]
+impl<S> AsyncRead for MaybeTlsStream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            MaybeTlsStream::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+            MaybeTlsStream::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl<S> AsyncWrite for MaybeTlsStream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            MaybeTlsStream::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+            MaybeTlsStream::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            MaybeTlsStream::Tls(stream) => Pin::new(stream).poll_flush(cx),
+            MaybeTlsStream::Plain(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    #[cfg(feature = "runtime-async-std")]
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            MaybeTlsStream::Tls(stream) => Pin::new(stream).poll_close(cx),
+            MaybeTlsStream::Plain(stream) => Pin::new(stream).poll_close(cx),
+        }
+    }
+
+    #[cfg(feature = "runtime-tokio")]
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            MaybeTlsStream::Tls(stream) => Pin::new(stream).poll_shutdown(cx),
+            MaybeTlsStream::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+/// The protection of a connection attempt, from strongest to weakest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SecurityLevel {
+    /// TLS with the certificate validated.
+    Validated,
+    /// TLS without validating the certificate.
+    Unvalidated,
+    /// No TLS.
+    Plaintext,
+}
+
+/// A single connection attempt of [`Opportunistic::connect`].
+#[derive(Debug)]
+pub struct Attempt {
+    /// The protection attempted.
+    pub level: SecurityLevel,
+    /// Why the attempt failed, or `None` if it succeeded.
+    pub error: Option<ConnectError>,
+}
+
+/// Every attempt made by [`Opportunistic::connect`], in order.
+#[derive(Debug, Default)]
+pub struct Outcome {
+    /// The attempts made. Only the last one can have succeeded.
+    pub attempts: Vec<Attempt>,
+}
+
+impl Outcome {
+    /// The protection of the established connection, if any.
+    pub fn level(&self) -> Option<SecurityLevel> {
+        self.attempts
+            .last()
+            .filter(|attempt| attempt.error.is_none())
+            .map(|attempt| attempt.level)
+    }
+
+    /// Whether the connection was established with less than a validated TLS session.
+    pub fn is_downgraded(&self) -> bool {
+        self.level()
+            .is_some_and(|level| level != SecurityLevel::Validated)
+    }
+}
+
+/// An error returned from [`Opportunistic::connect`].
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    /// Opening a stream for an attempt failed.
+    #[error("failed to open stream: {0}")]
+    Io(#[source] io::Error, Outcome),
+    /// Every attempt allowed by the policy failed.
+    #[error("all {} connection attempts failed", .0.attempts.len())]
+    Failed(Outcome),
+}
+
+impl Error {
+    /// The attempts made before the error.
+    pub fn outcome(&self) -> &Outcome {
+        match self {
+            Error::Io(_, outcome) | Error::Failed(outcome) => outcome,
+        }
+    }
+}
+
+/// Opportunistic TLS, as used between SMTP relays.
+///
+/// A validated TLS session is tried first. If that fails, a session without certificate
+/// validation is tried if [`allow_invalid_certs`](Opportunistic::allow_invalid_certs) is set, and
+/// finally a plaintext connection if [`allow_plaintext`](Opportunistic::allow_plaintext) is set.
+/// Every attempt is recorded in the returned [`Outcome`], so downgrades can be logged.
+///
+/// A failed handshake consumes its stream, so each attempt opens a new one. The callback
+/// receives the level of the attempt, e.g. to skip `STARTTLS` for plaintext.
+///
+/// # Example
+///
+/// ```no_run
+/// # #[cfg(feature = "runtime-async-std")]
+/// # fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> { async_std::task::block_on(async {
+/// #
+/// use async_std::net::TcpStream;
+/// use async_native_tls::{Opportunistic, TlsConnector};
+///
+/// let (stream, outcome) = Opportunistic::new(TlsConnector::new())
+///     .allow_invalid_certs(true)
+///     .allow_plaintext(true)
+///     .connect("mx1.example.com", |_level| async {
+///         let stream = TcpStream::connect("mx1.example.com:25").await?;
+///         // issue STARTTLS here, unless the level is plaintext
+///         Ok(stream)
+///     })
+///     .await?;
+/// if outcome.is_downgraded() {
+///     println!("downgraded to {:?}", outcome.level());
+/// }
+/// #
+/// # Ok(()) }) }
+/// # #[cfg(feature = "runtime-tokio")]
+/// # fn main() {}
+/// ```
+pub struct Opportunistic {
+    connector: TlsConnector,
+    allow_invalid_certs: bool,
+    allow_plaintext: bool,
+}
+
+impl Opportunistic {
+    /// Create a new instance attempting TLS with `connector`.
+    ///
+    /// The `danger_*` settings of `connector` are overridden for each attempt, so that a
+    /// [`SecurityLevel::Validated`] connection is always validated.
+    pub fn new(connector: TlsConnector) -> Self {
+        Self {
+            connector,
+            allow_invalid_certs: false,
+            allow_plaintext: false,
+        }
+    }
+
+    /// Retry without certificate validation if the validated handshake fails.
+    ///
+    /// Defaults to `false`.
+    pub fn allow_invalid_certs(mut self, allow: bool) -> Self {
+        self.allow_invalid_certs = allow;
+        self
+    }
+
+    /// Fall back to plaintext if no TLS session could be established.
+    ///
+    /// Defaults to `false`.
+    pub fn allow_plaintext(mut self, allow: bool) -> Self {
+        self.allow_plaintext = allow;
+        self
+    }
+
+    /// Connects to `host`, opening a stream for each attempt with `open`.
+    pub async fn connect<S, F, Fut>(
+        self,
+        host: impl Into<Host>,
+        mut open: F,
+    ) -> Result<(MaybeTlsStream<S>, Outcome), Error>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+        F: FnMut(SecurityLevel) -> Fut,
+        Fut: Future<Output = io::Result<S>>,
+    {
+        let host = host.into().as_string();
+        let mut outcome = Outcome::default();
+        let mut connector = self.connector;
+
+        let mut levels = vec![SecurityLevel::Validated];
+        if self.allow_invalid_certs {
+            levels.push(SecurityLevel::Unvalidated);
+        }
+        if self.allow_plaintext {
+            levels.push(SecurityLevel::Plaintext);
+        }
+
+        for level in levels {
+            let stream = match open(level).await {
+                Ok(stream) => stream,
+                Err(err) => return Err(Error::Io(err, outcome)),
+            };
+            let res = match level {
+                SecurityLevel::Validated => {
+                    connector = connector
+                        .danger_accept_invalid_certs(false)
+                        .danger_accept_invalid_hostnames(false);
+                    connector.connect(&host, stream).await
+                }
+                SecurityLevel::Unvalidated => {
+                    connector = connector
+                        .danger_accept_invalid_certs(true)
+                        .danger_accept_invalid_hostnames(true);
+                    connector.connect(&host, stream).await
+                }
+                SecurityLevel::Plaintext => {
+                    outcome.attempts.push(Attempt { level, error: None });
+                    return Ok((MaybeTlsStream::Plain(stream), outcome));
+                }
+            };
+            match res {
+                Ok(stream) => {
+                    outcome.attempts.push(Attempt { level, error: None });
+                    return Ok((MaybeTlsStream::Tls(stream), outcome));
+                }
+                Err(err) => outcome.attempts.push(Attempt {
+                    level,
+                    error: Some(err),
+                }),
+            }
+        }
+        Err(Error::Failed(outcome))
+    }
+}
+
+impl fmt::Debug for Opportunistic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Opportunistic")
+            .field("allow_invalid_certs", &self.allow_invalid_certs)
+            .field("allow_plaintext", &self.allow_plaintext)
+            .finish()
+    }
+}
+
+#[cfg(all(test, feature = "runtime-async-std"))]
+mod tests {
+    use super::*;
+    use crate::runtime::{AsyncReadExt, AsyncWriteExt};
+    use crate::test_util::{ca, hello_all, localhost};
+    use async_std::net::{TcpListener, TcpStream};
+    use std::net::SocketAddr;
+
+    /// Serves `hello` to every connection, over TLS if `tls` is set.
+    async fn server(tls: bool) -> SocketAddr {
+        if tls {
+            return hello_all(localhost().build().unwrap()).await;
+        }
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        async_std::task::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let _ = stream.write_all(b"hello").await;
+            }
+        });
+        addr
+    }
+
+    #[async_std::test]
+    async fn validated() {
+        let addr = server(true).await;
+        let (mut stream, outcome) =
+            Opportunistic::new(TlsConnector::new().add_root_certificate(ca()))
+                .allow_plaintext(true)
+                .connect("localhost", |_| TcpStream::connect(addr))
+                .await
+                .unwrap();
+        assert!(stream.is_tls());
+        assert_eq!(outcome.level(), Some(SecurityLevel::Validated));
+        assert!(!outcome.is_downgraded());
+
+        let mut buf = [0; 5];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+    }
+
+    #[async_std::test]
+    async fn validated_overrides_connector() {
+        let addr = server(true).await;
+        let connector = TlsConnector::new()
+            .danger_accept_invalid_certs(true)
+            .danger_accept_invalid_hostnames(true);
+        let res = Opportunistic::new(connector)
+            .connect("localhost", |_| TcpStream::connect(addr))
+            .await;
+        match res {
+            Err(Error::Failed(outcome)) => {
+                assert_eq!(outcome.attempts.len(), 1);
+                assert!(outcome.attempts[0].error.is_some());
+            }
+            res => panic!("unexpected result {:?}", res.map(|(_, outcome)| outcome)),
+        }
+
+        let (_, outcome) =
+            Opportunistic::new(TlsConnector::new().danger_accept_invalid_certs(true))
+                .allow_invalid_certs(true)
+                .connect("localhost", |_| TcpStream::connect(addr))
+                .await
+                .unwrap();
+        assert_eq!(outcome.level(), Some(SecurityLevel::Unvalidated));
+    }
+
+    #[async_std::test]
+    async fn downgrades() {
+        let addr = server(true).await;
+        let (stream, outcome) = Opportunistic::new(TlsConnector::new())
+            .allow_invalid_certs(true)
+            .connect("localhost", |_| TcpStream::connect(addr))
+            .await
+            .unwrap();
+        assert!(stream.is_tls());
+        assert_eq!(outcome.attempts.len(), 2);
+        assert!(outcome.attempts[0].error.is_some());
+        assert_eq!(outcome.level(), Some(SecurityLevel::Unvalidated));
+        assert!(outcome.is_downgraded());
+
+        let addr = server(false).await;
+        let mut levels = vec![];
+        let (mut stream, outcome) = Opportunistic::new(TlsConnector::new())
+            .allow_invalid_certs(true)
+            .allow_plaintext(true)
+            .connect("localhost", |level| {
+                levels.push(level);
+                TcpStream::connect(addr)
+            })
+            .await
+            .unwrap();
+        assert!(!stream.is_tls());
+        assert_eq!(
+            levels,
+            [
+                SecurityLevel::Validated,
+                SecurityLevel::Unvalidated,
+                SecurityLevel::Plaintext
+            ]
+        );
+        assert_eq!(outcome.level(), Some(SecurityLevel::Plaintext));
+        let mut buf = [0; 5];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+    }
+
+    #[async_std::test]
+    async fn plaintext_not_allowed() {
+        let addr = server(false).await;
+        let res = Opportunistic::new(TlsConnector::new())
+            .connect("localhost", |_| TcpStream::connect(addr))
+            .await;
+        match res {
+            Err(Error::Failed(outcome)) => {
+                assert_eq!(outcome.attempts.len(), 1);
+                assert_eq!(outcome.level(), None);
+            }
+            res => panic!("unexpected result {:?}", res.map(|(_, outcome)| outcome)),
+        }
+    }
+}
@@ -0,0 +1,501 @@
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::marker::Unpin;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use openssl::error::ErrorStack;
+use openssl::ex_data::Index;
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkcs12::Pkcs12;
+use openssl::pkey::{PKey, Private};
+use openssl::ssl::{
+    self, AlpnError, ConnectConfiguration, Ssl, SslAcceptor, SslConnector, SslConnectorBuilder,
+    SslContextBuilder, SslMethod, SslRef, SslSession, SslSessionCacheMode, SslStream,
+    SslVerifyMode, SslVersion,
+};
+use openssl::x509::store::X509StoreBuilder;
+use openssl::x509::{X509Ref, X509};
+
+use crate::acceptor::{ClientAuth, IdentityData, TlsAcceptorBuilder};
+use crate::connect::Verify;
+use crate::runtime::{AsyncRead, AsyncWrite};
+use crate::{
+    Certificate, ChannelBindingType, ConnectError, Host, Protocol, SessionError, TlsStream,
+};
+
+/// A callback customizing an OpenSSL context builder.
+pub(crate) type Configure<B> = dyn Fn(&mut B) -> Result<(), ErrorStack> + Send + Sync;
+
+/// The settings of a [`TlsConnector`](crate::TlsConnector), recorded alongside its native-tls
+/// builder so that the connector can be rebuilt with OpenSSL.
+pub(crate) struct ConnectorSettings {
+    pub(crate) identity: Option<IdentityData>,
+    /// An identity was set that OpenSSL cannot load, as native-tls does not expose its contents.
+    pub(crate) opaque_identity: bool,
+    /// The connector was created from a native-tls builder, whose settings are not exposed.
+    pub(crate) from_native: bool,
+    pub(crate) min_protocol: Option<Protocol>,
+    pub(crate) max_protocol: Option<Protocol>,
+    pub(crate) roots: Vec<Certificate>,
+    pub(crate) disable_built_in_roots: bool,
+    pub(crate) alpn: Vec<String>,
+    pub(crate) accept_invalid_certs: bool,
+    pub(crate) accept_invalid_hostnames: bool,
+    pub(crate) use_sni: bool,
+    pub(crate) configure: Vec<Box<Configure<SslConnectorBuilder>>>,
+    pub(crate) sessions: Option<Sessions>,
+    /// The chain presented by the server is needed, which native-tls does not expose.
+    pub(crate) peer_chain: bool,
+}
+
+impl Default for ConnectorSettings {
+    fn default() -> Self {
+        Self {
+            identity: None,
+            opaque_identity: false,
+            from_native: false,
+            min_protocol: Some(Protocol::Tlsv12),
+            max_protocol: None,
+            roots: vec![],
+            disable_built_in_roots: false,
+            alpn: vec![],
+            accept_invalid_certs: false,
+            accept_invalid_hostnames: false,
+            use_sni: true,
+            configure: vec![],
+            sessions: None,
+            peer_chain: false,
+        }
+    }
+}
+
+impl ConnectorSettings {
+    /// Whether connections have to be made with OpenSSL rather than native-tls.
+    pub(crate) fn required(&self) -> bool {
+        !self.configure.is_empty() || self.sessions.is_some()
+    }
+
+    /// Drops the cached sessions, which cannot be resumed once the settings changed.
+    pub(crate) fn reset_sessions(&mut self) {
+        if let Some(sessions) = &mut self.sessions {
+            *sessions = Sessions::default();
+        }
+    }
+
+    /// Describes the settings that cannot be reproduced with OpenSSL, if any.
+    pub(crate) fn unsupported(&self) -> Option<&'static str> {
+        if self.from_native {
+            Some("connectors created from a native-tls builder")
+        } else if self.opaque_identity {
+            Some("identities set with `TlsConnector::identity`")
+        } else if self.alpn.iter().any(|p| p.is_empty() || p.len() > 255) {
+            Some("ALPN protocol names that are empty or longer than 255 bytes")
+        } else {
+            None
+        }
+    }
+}
+
+/// Builds a connector with `settings`, like native-tls does.
+fn connector(settings: &ConnectorSettings) -> Result<SslConnector, ErrorStack> {
+    static PROBE: OnceLock<openssl_probe::ProbeResult> = OnceLock::new();
+
+    let mut connector = SslConnector::builder(SslMethod::tls())?;
+    let probe = PROBE.get_or_init(openssl_probe::probe);
+    // locations that fail to load are skipped, as native-tls does
+    if let Some(file) = &probe.cert_file {
+        let _ = connector.load_verify_locations(Some(file), None);
+    }
+    for dir in &probe.cert_dir {
+        let _ = connector.load_verify_locations(None, Some(dir));
+    }
+
+    if let Some(identity) = &settings.identity {
+        let (key, cert, chain) = self::identity(identity)?;
+        connector.set_certificate(&cert)?;
+        connector.set_private_key(&key)?;
+        for cert in chain {
+            connector.add_extra_chain_cert(cert)?;
+        }
+    }
+    supported_protocols(settings.min_protocol, settings.max_protocol, &mut connector)?;
+    if settings.disable_built_in_roots {
+        connector.set_cert_store(X509StoreBuilder::new()?.build());
+    }
+    for root in &settings.roots {
+        // duplicates fail to be added, which is harmless
+        let _ = connector.cert_store_mut().add_cert(x509(root)?);
+    }
+    if !settings.alpn.is_empty() {
+        // names that do not fit their length prefix are rejected by `unsupported`
+        let mut wire = vec![];
+        for protocol in &settings.alpn {
+            wire.push(protocol.len() as u8);
+            wire.extend_from_slice(protocol.as_bytes());
+        }
+        connector.set_alpn_protos(&wire)?;
+    }
+    for configure in &settings.configure {
+        configure(&mut connector)?;
+    }
+    if let Some(sessions) = &settings.sessions {
+        let by_host = sessions.by_host.clone();
+        let index = Sessions::index()?;
+        let chain_index = Sessions::chain_index()?;
+        connector.set_session_cache_mode(SslSessionCacheMode::CLIENT);
+        connector.set_new_session_callback(move |ssl, session| {
+            if let Some(host) = ssl.ex_data(index) {
+                let chain = match ssl.verified_chain() {
+                    Some(chain) => chain.iter().map(ToOwned::to_owned).collect(),
+                    // resumed sessions keep the chain they were established with
+                    None => ssl.ex_data(chain_index).cloned().unwrap_or_default(),
+                };
+                let mut by_host = by_host.lock().unwrap_or_else(|e| e.into_inner());
+                by_host.insert(host.clone(), (session, chain));
+            }
+        });
+    }
+    Ok(connector.build())
+}
+
+/// Client sessions by host, for resumption, along with the chains verified when they were
+/// established.
+///
+/// Sessions can only be resumed with the context they were established with, so the connector
+/// is built once and kept alongside them.
+#[derive(Default)]
+pub(crate) struct Sessions {
+    connector: Mutex<Option<SslConnector>>,
+    by_host: Arc<Mutex<HashMap<String, CachedSession>>>,
+}
+
+type CachedSession = (SslSession, Vec<X509>);
+
+impl Sessions {
+    /// The slot of a session for the host it is established with.
+    fn index() -> Result<Index<Ssl, String>, ErrorStack> {
+        static INDEX: OnceLock<Index<Ssl, String>> = OnceLock::new();
+        ex_index(&INDEX)
+    }
+
+    /// The slot of a session for the chain verified when the offered session was established.
+    fn chain_index() -> Result<Index<Ssl, Vec<X509>>, ErrorStack> {
+        static INDEX: OnceLock<Index<Ssl, Vec<X509>>> = OnceLock::new();
+        ex_index(&INDEX)
+    }
+
+    fn connector(&self, settings: &ConnectorSettings) -> Result<SslConnector, ErrorStack> {
+        let mut cached = self.connector.lock().unwrap_or_else(|e| e.into_inner());
+        match &*cached {
+            Some(connector) => Ok(connector.clone()),
+            None => Ok(cached.insert(connector(settings)?).clone()),
+        }
+    }
+
+    /// Offers the cached session for `host`, and caches the sessions the server issues.
+    fn prepare(&self, config: &mut ConnectConfiguration, host: &str) -> Result<(), ErrorStack> {
+        config.set_ex_data(Self::index()?, host.to_string());
+        let session = {
+            let by_host = self.by_host.lock().unwrap_or_else(|e| e.into_inner());
+            by_host.get(host).cloned()
+        };
+        if let Some((session, chain)) = session {
+            // SAFETY: sessions are only cached from the connector kept alongside them
+            unsafe { config.set_session(&session)? };
+            config.set_ex_data(Self::chain_index()?, chain);
+        }
+        Ok(())
+    }
+}
+
+fn ex_index<T>(cell: &OnceLock<Index<Ssl, T>>) -> Result<Index<Ssl, T>, ErrorStack>
+where
+    T: Send + Sync + 'static,
+{
+    match cell.get() {
+        Some(index) => Ok(*index),
+        None => {
+            let index = Ssl::new_ex_index()?;
+            Ok(*cell.get_or_init(|| index))
+        }
+    }
+}
+
+/// The chain verified when the session of `ssl` was established, for resumed sessions, which
+/// skip the verification.
+pub(crate) fn resumed_chain(ssl: &SslRef) -> Option<&[X509]> {
+    if !ssl.session_reused() {
+        return None;
+    }
+    ssl.ex_data(Sessions::chain_index().ok()?)
+        .map(Vec::as_slice)
+        .filter(|chain| !chain.is_empty())
+}
+
+/// Connects `stream` to `domain` with OpenSSL.
+///
+/// `verify` runs during the handshake, unless invalid certificates are accepted: OpenSSL then
+/// ignores rejections, so it runs after the handshake instead. It also runs after the handshake
+/// when a session was resumed, as no certificate is verified then.
+pub(crate) async fn connect<S>(
+    settings: &ConnectorSettings,
+    domain: &str,
+    stream: S,
+    verify: Option<&Arc<Verify>>,
+) -> Result<TlsStream<S>, ConnectError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let connector = match &settings.sessions {
+        Some(sessions) => sessions.connector(settings)?,
+        None => connector(settings)?,
+    };
+    let mut config = connector
+        .configure()?
+        .use_server_name_indication(settings.use_sni)
+        .verify_hostname(!settings.accept_invalid_hostnames);
+    if settings.accept_invalid_certs {
+        config.set_verify(SslVerifyMode::NONE);
+    }
+    if let Some(sessions) = &settings.sessions {
+        sessions.prepare(&mut config, domain)?;
+    }
+    let rejection = Arc::new(Mutex::new(None));
+    let invalid = Arc::new(Mutex::new(None));
+    let verify_after = if settings.accept_invalid_certs {
+        verify
+    } else {
+        let verify = verify.cloned();
+        let host = domain.to_string();
+        let rejection = rejection.clone();
+        let invalid = invalid.clone();
+        config.set_verify_callback(SslVerifyMode::PEER, move |preverified, ctx| {
+            if !preverified {
+                // keep the reason, which the handshake error does not carry
+                let mut invalid = invalid.lock().unwrap_or_else(|e| e.into_inner());
+                invalid.get_or_insert(ctx.error());
+                return false;
+            }
+            // the callback only sees the chain once validation reached the leaf
+            let verify = match &verify {
+                Some(verify) if ctx.error_depth() == 0 => verify,
+                _ => return true,
+            };
+            let chain = ctx
+                .chain()
+                .into_iter()
+                .flatten()
+                .map(certificate)
+                .collect::<crate::Result<Vec<_>>>();
+            let res = match chain {
+                Ok(chain) => verify(&chain, &Host::from(host.as_str())),
+                Err(err) => Err(err.into()),
+            };
+            res.map_err(|err| *rejection.lock().unwrap_or_else(|e| e.into_inner()) = Some(err))
+                .is_ok()
+        });
+        None
+    };
+    let ssl = config.into_ssl(domain)?;
+    let res = crate::handshake::openssl_handshake(move |s| ssl.connect(s), stream).await;
+    if let Some(err) = rejection.lock().unwrap_or_else(|e| e.into_inner()).take() {
+        return Err(ConnectError::Verification(err));
+    }
+    let res =
+        res.map_err(
+            |source| match invalid.lock().unwrap_or_else(|e| e.into_inner()).take() {
+                Some(code) => ConnectError::InvalidCertificate { code, source },
+                None => source.into(),
+            },
+        );
+    let stream = res?;
+    // resumed sessions skip the certificate verification, and with it the callback
+    let verify_after = match verify {
+        Some(verify) if stream.session_resumed() => Some(verify),
+        _ => verify_after,
+    };
+    if let Some(verify) = verify_after {
+        crate::connect::verify_after_handshake(verify.as_ref(), domain, &stream)?;
+    }
+    Ok(stream)
+}
+
+/// Builds an acceptor with the settings of `builder`, like native-tls does.
+pub(crate) fn acceptor(builder: &TlsAcceptorBuilder) -> Result<SslAcceptor, ErrorStack> {
+    let (key, cert, chain) = identity(&builder.identity)?;
+    let mut acceptor = SslAcceptor::mozilla_intermediate(SslMethod::tls())?;
+    acceptor.set_private_key(&key)?;
+    acceptor.set_certificate(&cert)?;
+    for cert in chain {
+        acceptor.add_extra_chain_cert(cert)?;
+    }
+    supported_protocols(builder.min_protocol, builder.max_protocol, &mut acceptor)?;
+
+    if let Some((mode, roots)) = &builder.client_auth {
+        let mut store = X509StoreBuilder::new()?;
+        for root in roots {
+            let root = x509(root)?;
+            acceptor.add_client_ca(&root)?;
+            store.add_cert(root)?;
+        }
+        acceptor.set_verify_cert_store(store.build())?;
+        // clients only resume sessions of the same context
+        acceptor.set_session_id_context(b"async-native-tls")?;
+        acceptor.set_verify(match mode {
+            ClientAuth::Request => SslVerifyMode::PEER,
+            ClientAuth::Require => SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT,
+        });
+    }
+    if let Some(select) = &builder.select_alpn {
+        let select = select.clone();
+        acceptor.set_alpn_select_callback(move |_, client| {
+            let offered = alpn_protocols(client);
+            select(&offered).map(str::as_bytes).ok_or(AlpnError::NOACK)
+        });
+    } else if !builder.alpn.is_empty() {
+        let alpn = builder.alpn.clone();
+        acceptor.set_alpn_select_callback(move |_, client| {
+            let offered = alpn_protocols(client);
+            alpn.iter()
+                .find_map(|p| offered.iter().find(|o| **o == p.as_str()).copied())
+                .map(str::as_bytes)
+                .ok_or(AlpnError::NOACK)
+        });
+    }
+    for configure in &builder.configure {
+        configure(&mut acceptor)?;
+    }
+    Ok(acceptor.build())
+}
+
+/// Splits a protocol list in ALPN wire format, leaving out protocols which are not UTF-8.
+fn alpn_protocols(mut wire: &[u8]) -> Vec<&str> {
+    let mut protocols = vec![];
+    while let Some((&len, rest)) = wire.split_first() {
+        let len = usize::from(len).min(rest.len());
+        let (protocol, rest) = rest.split_at(len);
+        if let Ok(protocol) = std::str::from_utf8(protocol) {
+            protocols.push(protocol);
+        }
+        wire = rest;
+    }
+    protocols
+}
+
+type ParsedIdentity = (PKey<Private>, X509, Vec<X509>);
+
+fn identity(identity: &IdentityData) -> Result<ParsedIdentity, ErrorStack> {
+    let missing = || ErrorStack::get();
+    match identity {
+        IdentityData::Pkcs12 { der, password } => {
+            let parsed = Pkcs12::from_der(der)?.parse2(password)?;
+            let key = parsed.pkey.ok_or_else(missing)?;
+            let cert = parsed.cert.ok_or_else(missing)?;
+            // PKCS12_parse returns the chain in reverse
+            let chain = parsed.ca.into_iter().flatten().rev().collect();
+            Ok((key, cert, chain))
+        }
+        IdentityData::Pkcs8 { cert, key } => {
+            let key = PKey::private_key_from_pem(key)?;
+            let mut chain = X509::stack_from_pem(cert)?.into_iter();
+            let cert = chain.next().ok_or_else(missing)?;
+            Ok((key, cert, chain.collect()))
+        }
+    }
+}
+
+fn supported_protocols(
+    min: Option<Protocol>,
+    max: Option<Protocol>,
+    ctx: &mut SslContextBuilder,
+) -> Result<(), ErrorStack> {
+    fn cvt(protocol: Protocol) -> SslVersion {
+        match protocol {
+            Protocol::Sslv3 => SslVersion::SSL3,
+            Protocol::Tlsv10 => SslVersion::TLS1,
+            Protocol::Tlsv11 => SslVersion::TLS1_1,
+            Protocol::Tlsv12 => SslVersion::TLS1_2,
+            // newer than any version named above
+            _ => SslVersion::TLS1_3,
+        }
+    }
+
+    ctx.set_min_proto_version(min.map(cvt))?;
+    ctx.set_max_proto_version(max.map(cvt))?;
+    Ok(())
+}
+
+fn x509(cert: &Certificate) -> Result<X509, ErrorStack> {
+    // a certificate that cannot be encoded fails to parse below
+    X509::from_der(&cert.to_der().unwrap_or_default())
+}
+
+pub(crate) fn certificate(cert: &X509Ref) -> crate::Result<Certificate> {
+    // an empty buffer fails to parse, reporting the error as a native-tls error
+    Certificate::from_der(&cert.to_der().unwrap_or_default())
+}
+
+/// Computes the tls-server-end-point channel binding, like native-tls does.
+pub(crate) fn tls_server_end_point(ssl: &SslRef) -> Option<Vec<u8>> {
+    let cert = if ssl.is_server() {
+        ssl.certificate().map(|cert| cert.to_owned())
+    } else {
+        ssl.peer_certificate()
+    }?;
+    let algorithms = cert
+        .signature_algorithm()
+        .object()
+        .nid()
+        .signature_algorithms()?;
+    let md = match algorithms.digest {
+        Nid::MD5 | Nid::SHA1 => MessageDigest::sha256(),
+        nid => MessageDigest::from_nid(nid)?,
+    };
+    cert.digest(md).ok().map(|digest| digest.to_vec())
+}
+
+/// Computes channel binding data other than tls-server-end-point, which native-tls provides.
+pub(crate) fn channel_binding(
+    ssl: &SslRef,
+    kind: ChannelBindingType,
+) -> Result<Vec<u8>, SessionError> {
+    let tls13 = ssl.version2() == Some(SslVersion::TLS1_3);
+    // TLS 1.3 always binds the session to the handshake, as the extension does for TLS 1.2
+    let extended_master_secret = tls13 || ssl.extms_support() == Some(true);
+    match kind {
+        ChannelBindingType::TlsUnique if tls13 => Err(SessionError::Unavailable(kind.as_str())),
+        ChannelBindingType::TlsUnique | ChannelBindingType::TlsExporter
+            if !extended_master_secret =>
+        {
+            Err(SessionError::Unsafe(kind.as_str()))
+        }
+        ChannelBindingType::TlsUnique => {
+            // the first Finished message of the handshake: the client's, unless the session
+            // was resumed
+            let mut finished = [0; 64];
+            let len = if ssl.session_reused() == ssl.is_server() {
+                ssl.finished(&mut finished)
+            } else {
+                ssl.peer_finished(&mut finished)
+            };
+            Ok(finished[..len.min(finished.len())].to_vec())
+        }
+        ChannelBindingType::TlsExporter => {
+            let mut binding = vec![0; 32];
+            ssl.export_keying_material(&mut binding, "EXPORTER-Channel-Binding", Some(&[]))?;
+            Ok(binding)
+        }
+        ChannelBindingType::TlsServerEndPoint => {
+            tls_server_end_point(ssl).ok_or(SessionError::Unavailable(kind.as_str()))
+        }
+    }
+}
+
+pub(crate) fn shutdown<S: Read + Write>(stream: &mut SslStream<S>) -> io::Result<()> {
+    match stream.shutdown() {
+        Ok(_) => Ok(()),
+        Err(ref e) if e.code() == ssl::ErrorCode::ZERO_RETURN => Ok(()),
+        Err(e) => Err(e.into_io_error().unwrap_or_else(io::Error::other)),
+    }
+}
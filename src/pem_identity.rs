@@ -0,0 +1,109 @@
+use std::convert::TryFrom;
+use std::marker::Unpin;
+
+use pkcs8::{EncryptedPrivateKeyInfo, LineEnding, SecretDocument};
+use zeroize::Zeroizing;
+
+use crate::runtime::{AsyncRead, AsyncReadExt};
+
+/// An error returned from loading an identity with
+/// [`TlsConnector::identity_from_pem`](crate::TlsConnector::identity_from_pem).
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    /// NativeTls error.
+    #[error("NativeTls({0})")]
+    NativeTls(#[from] native_tls::Error),
+    /// Io error.
+    #[error("Io({0})")]
+    Io(#[from] std::io::Error),
+    /// The private key is not a PEM-encoded PKCS #8 key.
+    #[error("failed to parse private key: {0}")]
+    Parse(String),
+    /// The private key is encrypted, but no passphrase was given.
+    #[error("private key is encrypted, but no passphrase was given")]
+    PassphraseRequired,
+    /// The private key could not be decrypted, usually because the passphrase is wrong.
+    #[error("failed to decrypt private key")]
+    Decrypt,
+}
+
+/// Reads a PEM-formatted certificate chain and a PEM-formatted PKCS #8 private key, which is
+/// decrypted with `passphrase` if it is encrypted.
+///
+/// Returns the chain and the unencrypted key, PEM-formatted. The passphrase is zeroized before
+/// returning, and the key once it is dropped.
+pub(crate) async fn read<C, K>(
+    mut cert: C,
+    mut key: K,
+    passphrase: Option<String>,
+) -> Result<(Vec<u8>, Zeroizing<String>), Error>
+where
+    C: AsyncRead + Unpin,
+    K: AsyncRead + Unpin,
+{
+    let passphrase = passphrase.map(Zeroizing::new);
+    let mut cert_pem = vec![];
+    cert.read_to_end(&mut cert_pem).await?;
+    let mut key_pem = Zeroizing::new(vec![]);
+    key.read_to_end(&mut key_pem).await?;
+
+    let key_pem = std::str::from_utf8(&key_pem).map_err(|e| Error::Parse(e.to_string()))?;
+    let (label, doc) =
+        SecretDocument::from_pem(key_pem).map_err(|e| Error::Parse(e.to_string()))?;
+    let key_pem = match label {
+        "PRIVATE KEY" => Zeroizing::new(key_pem.to_string()),
+        "ENCRYPTED PRIVATE KEY" => {
+            let passphrase = passphrase.ok_or(Error::PassphraseRequired)?;
+            let info = EncryptedPrivateKeyInfo::try_from(doc.as_bytes())
+                .map_err(|e| Error::Parse(e.to_string()))?;
+            let decrypted = info.decrypt(passphrase.as_bytes()).map_err(|e| match e {
+                pkcs8::Error::EncryptedPrivateKey(_) => Error::Decrypt,
+                e => Error::Parse(e.to_string()),
+            })?;
+            decrypted
+                .to_pem("PRIVATE KEY", LineEnding::LF)
+                .map_err(|e| Error::Parse(e.to_string()))?
+        }
+        label => return Err(Error::Parse(format!("unexpected PEM label {}", label))),
+    };
+
+    Ok((cert_pem, key_pem))
+}
+
+#[cfg(all(test, feature = "runtime-async-std"))]
+mod tests {
+    use super::*;
+    use crate::Identity;
+    use async_std::fs::File;
+
+    async fn load(key: &str, passphrase: Option<&str>) -> Result<Identity, Error> {
+        let cert = File::open("tests/client.pem").await?;
+        let key = File::open(key).await?;
+        let (cert, key) = read(cert, key, passphrase.map(String::from)).await?;
+        Ok(Identity::from_pkcs8(&cert, key.as_bytes())?)
+    }
+
+    #[async_std::test]
+    async fn loads_keys() {
+        load("tests/client-key.pem", None).await.unwrap();
+        load("tests/client-key-encrypted.pem", Some("hello"))
+            .await
+            .unwrap();
+    }
+
+    #[async_std::test]
+    async fn rejects_passphrase() {
+        assert!(matches!(
+            load("tests/client-key-encrypted.pem", None).await,
+            Err(Error::PassphraseRequired)
+        ));
+        assert!(matches!(
+            load("tests/client-key-encrypted.pem", Some("wrong")).await,
+            Err(Error::Decrypt)
+        ));
+        assert!(matches!(
+            load("tests/client.pem", None).await,
+            Err(Error::Parse(_))
+        ));
+    }
+}
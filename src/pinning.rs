@@ -0,0 +1,177 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::certificate::CertificateInfo;
+use crate::runtime::{AsyncRead, AsyncWrite};
+use crate::TlsStream;
+
+/// Pins for the leaf certificates of individual hosts, checked after the handshake.
+#[derive(Clone, Default)]
+pub(crate) struct Pins {
+    hosts: HashMap<String, HostPins>,
+    report_only: bool,
+    reporter: Option<Reporter>,
+}
+
+pub(crate) type Reporter = Arc<dyn Fn(&PinningError) + Send + Sync>;
+
+#[derive(Clone, Default)]
+struct HostPins {
+    spki_sha256: Vec<[u8; 32]>,
+    certificate_sha256: Vec<[u8; 32]>,
+}
+
+/// The peer certificate of a pinned host matched none of its pins.
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+#[error("certificate presented by {host} does not match any of its pins")]
+pub struct PinningError {
+    /// The host the connection was made to.
+    pub host: String,
+    /// SHA-256 digest of the peer's subject public key info, if its certificate could be parsed.
+    pub spki_sha256: Option<[u8; 32]>,
+    /// SHA-256 digest of the peer's certificate, if it could be parsed.
+    pub certificate_sha256: Option<[u8; 32]>,
+}
+
+impl Pins {
+    pub(crate) fn add_spki_sha256(
+        &mut self,
+        host: &str,
+        hashes: impl IntoIterator<Item = [u8; 32]>,
+    ) {
+        self.host_mut(host).spki_sha256.extend(hashes);
+    }
+
+    pub(crate) fn add_certificate_sha256(
+        &mut self,
+        host: &str,
+        fingerprints: impl IntoIterator<Item = [u8; 32]>,
+    ) {
+        self.host_mut(host).certificate_sha256.extend(fingerprints);
+    }
+
+    pub(crate) fn set_report_only(&mut self, report_only: bool) {
+        self.report_only = report_only;
+    }
+
+    pub(crate) fn set_reporter(&mut self, reporter: Reporter) {
+        self.reporter = Some(reporter);
+    }
+
+    /// Checks the peer certificate of `stream` against the pins of `host`.
+    ///
+    /// Violations are passed to the reporter and, unless in report-only mode, returned.
+    pub(crate) fn verify<S>(&self, host: &str, stream: &TlsStream<S>) -> Result<(), PinningError>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let pins = match self.hosts.get(&normalize(host)) {
+            Some(pins) => pins,
+            None => return Ok(()),
+        };
+
+        let cert = stream.peer_certificate().ok().flatten();
+        let info = cert
+            .as_ref()
+            .and_then(|cert| CertificateInfo::from_certificate(cert).ok());
+        let spki_sha256 = info.as_ref().map(|info| info.spki_sha256);
+        let certificate_sha256 = info.as_ref().map(|info| info.sha256_fingerprint);
+
+        let matched = spki_sha256.is_some_and(|hash| pins.spki_sha256.contains(&hash))
+            || certificate_sha256.is_some_and(|hash| pins.certificate_sha256.contains(&hash));
+        if matched {
+            return Ok(());
+        }
+
+        let err = PinningError {
+            host: host.to_string(),
+            spki_sha256,
+            certificate_sha256,
+        };
+        if let Some(reporter) = &self.reporter {
+            reporter(&err);
+        }
+        if self.report_only {
+            Ok(())
+        } else {
+            Err(err)
+        }
+    }
+
+    fn host_mut(&mut self, host: &str) -> &mut HostPins {
+        self.hosts.entry(normalize(host)).or_default()
+    }
+}
+
+impl fmt::Debug for Pins {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Pins")
+            .field("hosts", &self.hosts.keys())
+            .field("report_only", &self.report_only)
+            .finish()
+    }
+}
+
+fn normalize(host: &str) -> String {
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
+
+#[cfg(all(test, feature = "runtime-async-std"))]
+mod tests {
+    use super::*;
+    use crate::test_util::{ca, hello, localhost, tcp};
+    use crate::{Certificate, ConnectError, TlsConnector};
+    use async_std::net::TcpStream;
+    use std::sync::Mutex;
+
+    fn localhost_info() -> CertificateInfo {
+        let pem = std::fs::read("tests/localhost.pem").unwrap();
+        CertificateInfo::from_certificate(&Certificate::from_pem(&pem).unwrap()).unwrap()
+    }
+
+    async fn connect(connector: TlsConnector) -> Result<TlsStream<TcpStream>, ConnectError> {
+        let addr = hello(localhost().build().unwrap()).await;
+        connector
+            .add_root_certificate(ca())
+            .connect("localhost", tcp(addr).await)
+            .await
+    }
+
+    #[async_std::test]
+    async fn matching_pin() {
+        let connector = TlsConnector::new()
+            .pin_spki_sha256("localhost", vec![[0; 32], localhost_info().spki_sha256]);
+        assert!(connect(connector).await.is_ok());
+
+        let connector = TlsConnector::new()
+            .pin_certificate_sha256("LOCALHOST.", vec![localhost_info().sha256_fingerprint]);
+        assert!(connect(connector).await.is_ok());
+    }
+
+    #[async_std::test]
+    async fn mismatching_pin() {
+        let connector = TlsConnector::new().pin_spki_sha256("localhost", vec![[0; 32]]);
+        match connect(connector).await {
+            Err(ConnectError::Pinning(err)) => {
+                assert_eq!(err.host, "localhost");
+                assert_eq!(err.spki_sha256, Some(localhost_info().spki_sha256));
+            }
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    #[async_std::test]
+    async fn report_only() {
+        let reported = Arc::new(Mutex::new(vec![]));
+        let connector = TlsConnector::new()
+            .pin_spki_sha256("localhost", vec![[0; 32]])
+            .pinning_report_only(true)
+            .on_pinning_failure({
+                let reported = reported.clone();
+                move |err| reported.lock().unwrap().push(err.clone())
+            });
+        assert!(connect(connector).await.is_ok());
+        assert_eq!(reported.lock().unwrap().len(), 1);
+    }
+}
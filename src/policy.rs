@@ -0,0 +1,291 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::iter::FromIterator;
+
+use crate::host::{matches_name, Host};
+use crate::runtime::{AsyncRead, AsyncWrite};
+use crate::{Certificate, ConnectError, Identity, Protocol, TlsConnector, TlsStream};
+
+/// The settings of a [`TlsConnector`] for a host or group of hosts.
+#[derive(Clone, Default)]
+pub struct HostSettings {
+    /// Root certificates trusted in addition to, or instead of, the built-in ones.
+    pub root_certificates: Vec<Certificate>,
+    /// Trust only `root_certificates`.
+    pub disable_built_in_roots: bool,
+    /// The minimum supported protocol version, or `None` to keep the default of
+    /// [`TlsConnector`], TLS 1.2.
+    pub min_protocol_version: Option<Protocol>,
+    /// The maximum supported protocol version, or `None` for the newest version the backend
+    /// supports.
+    pub max_protocol_version: Option<Protocol>,
+    /// The client identity to authenticate with.
+    pub identity: Option<Identity>,
+    /// The protocols to request via ALPN.
+    pub alpn_protocols: Vec<String>,
+}
+
+impl HostSettings {
+    /// Builds a connector with these settings.
+    pub fn connector(&self) -> TlsConnector {
+        let mut connector = TlsConnector::new().disable_built_in_roots(self.disable_built_in_roots);
+        if let Some(protocol) = self.min_protocol_version {
+            connector = connector.min_protocol_version(Some(protocol));
+        }
+        if let Some(protocol) = self.max_protocol_version {
+            connector = connector.max_protocol_version(Some(protocol));
+        }
+        for cert in &self.root_certificates {
+            connector = connector.add_root_certificate(cert.clone());
+        }
+        if let Some(identity) = &self.identity {
+            connector = connector.identity(identity.clone());
+        }
+        if !self.alpn_protocols.is_empty() {
+            let protocols: Vec<&str> = self.alpn_protocols.iter().map(String::as_str).collect();
+            connector = connector.request_alpns(&protocols);
+        }
+        connector
+    }
+}
+
+impl fmt::Debug for HostSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HostSettings")
+            .field("root_certificates", &self.root_certificates.len())
+            .field("disable_built_in_roots", &self.disable_built_in_roots)
+            .field("min_protocol_version", &self.min_protocol_version)
+            .field("max_protocol_version", &self.max_protocol_version)
+            .field("identity", &self.identity.is_some())
+            .field("alpn_protocols", &self.alpn_protocols)
+            .finish()
+    }
+}
+
+/// A registry of [`HostSettings`], resolving the settings to connect to a host with.
+///
+/// Hosts are matched by pattern:
+///
+/// - `mail.example.com` matches only that host.
+/// - `*.example.com` matches hosts exactly one label below `example.com`.
+/// - `.example.com` matches `example.com` and every host below it.
+///
+/// An exact match takes precedence over a wildcard, which takes precedence over suffixes. Of
+/// several matching suffixes, the longest one is used. Hosts matching no pattern use the default
+/// settings.
+///
+/// The registry can be collected from `(pattern, settings)` pairs. With the `serde` feature, it
+/// can also be described in a configuration file with `PolicyConfig`.
+///
+/// # Example
+///
+/// ```no_run
+/// # #[cfg(feature = "runtime-async-std")]
+/// # fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> { async_std::task::block_on(async {
+/// #
+/// use async_std::net::TcpStream;
+/// use async_native_tls::{Certificate, ConnectorPolicy, HostSettings, Protocol};
+///
+/// let ca = Certificate::from_pem(&std::fs::read("internal-ca.pem")?)?;
+/// let policy = ConnectorPolicy::new()
+///     .host(".internal.example.com", HostSettings {
+///         root_certificates: vec![ca],
+///         ..Default::default()
+///     })
+///     .host("legacy.example.com", HostSettings {
+///         min_protocol_version: Some(Protocol::Tlsv10),
+///         ..Default::default()
+///     });
+///
+/// let stream = TcpStream::connect("db.internal.example.com:5432").await?;
+/// let stream = policy.connect("db.internal.example.com", stream).await?;
+/// #
+/// # Ok(()) }) }
+/// # #[cfg(feature = "runtime-tokio")]
+/// # fn main() {}
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ConnectorPolicy {
+    default: HostSettings,
+    exact: HashMap<String, HostSettings>,
+    wildcards: HashMap<String, HostSettings>,
+    suffixes: HashMap<String, HostSettings>,
+}
+
+impl ConnectorPolicy {
+    /// Create a new, empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the settings for hosts matching no pattern.
+    pub fn default_settings(mut self, settings: HostSettings) -> Self {
+        self.default = settings;
+        self
+    }
+
+    /// Adds the settings for hosts matching `pattern`.
+    pub fn host(mut self, pattern: &str, settings: HostSettings) -> Self {
+        self.insert(pattern, settings);
+        self
+    }
+
+    /// Adds the settings for hosts matching `pattern`, replacing those of an equal pattern.
+    pub fn insert(&mut self, pattern: &str, settings: HostSettings) {
+        let pattern = normalize(pattern);
+        if pattern.starts_with("*.") {
+            self.wildcards.insert(pattern, settings);
+        } else if let Some(suffix) = pattern.strip_prefix('.') {
+            self.suffixes.insert(suffix.to_string(), settings);
+        } else {
+            self.exact.insert(pattern, settings);
+        }
+    }
+
+    /// Returns the settings for `host`.
+    pub fn resolve(&self, host: &str) -> &HostSettings {
+        let host = normalize(host);
+        if let Some(settings) = self.exact.get(&host) {
+            return settings;
+        }
+        if let Some(settings) = self
+            .wildcards
+            .iter()
+            .find(|(pattern, _)| matches_name(pattern, &host))
+            .map(|(_, settings)| settings)
+        {
+            return settings;
+        }
+        self.suffixes
+            .iter()
+            .filter(|(suffix, _)| {
+                host == **suffix
+                    || host
+                        .strip_suffix(suffix.as_str())
+                        .is_some_and(|rest| rest.ends_with('.'))
+            })
+            .max_by_key(|(suffix, _)| suffix.len())
+            .map_or(&self.default, |(_, settings)| settings)
+    }
+
+    /// Builds a connector with the settings for `host`.
+    pub fn connector(&self, host: &str) -> TlsConnector {
+        self.resolve(host).connector()
+    }
+
+    /// Connects to `host` with the settings resolved for it.
+    pub async fn connect<S>(
+        &self,
+        host: impl Into<Host>,
+        stream: S,
+    ) -> Result<TlsStream<S>, ConnectError>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let host = host.into().as_string();
+        self.connector(&host).connect(&host, stream).await
+    }
+}
+
+impl<P: AsRef<str>> FromIterator<(P, HostSettings)> for ConnectorPolicy {
+    fn from_iter<I: IntoIterator<Item = (P, HostSettings)>>(iter: I) -> Self {
+        let mut policy = Self::new();
+        policy.extend(iter);
+        policy
+    }
+}
+
+impl<P: AsRef<str>> Extend<(P, HostSettings)> for ConnectorPolicy {
+    fn extend<I: IntoIterator<Item = (P, HostSettings)>>(&mut self, iter: I) {
+        for (pattern, settings) in iter {
+            self.insert(pattern.as_ref(), settings);
+        }
+    }
+}
+
+fn normalize(host: &str) -> String {
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alpn(protocol: &str) -> HostSettings {
+        HostSettings {
+            alpn_protocols: vec![protocol.to_string()],
+            ..Default::default()
+        }
+    }
+
+    fn resolved<'a>(policy: &'a ConnectorPolicy, host: &str) -> &'a str {
+        policy
+            .resolve(host)
+            .alpn_protocols
+            .first()
+            .map_or("default", String::as_str)
+    }
+
+    #[test]
+    fn resolves_patterns() {
+        let policy: ConnectorPolicy = vec![
+            ("mail.example.com", alpn("exact")),
+            ("*.example.com", alpn("wildcard")),
+            (".example.com", alpn("suffix")),
+            (".internal.example.com", alpn("longer suffix")),
+        ]
+        .into_iter()
+        .collect();
+
+        assert_eq!(resolved(&policy, "Mail.Example.com."), "exact");
+        assert_eq!(resolved(&policy, "www.example.com"), "wildcard");
+        assert_eq!(resolved(&policy, "example.com"), "suffix");
+        assert_eq!(resolved(&policy, "a.b.example.com"), "suffix");
+        assert_eq!(
+            resolved(&policy, "db.internal.example.com"),
+            "longer suffix"
+        );
+        assert_eq!(resolved(&policy, "badexample.com"), "default");
+        assert_eq!(resolved(&policy, "example.org"), "default");
+    }
+
+    #[cfg(feature = "runtime-async-std")]
+    #[async_std::test]
+    async fn connect() {
+        use crate::runtime::AsyncReadExt;
+        use crate::test_util::{ca, hello, localhost, tcp};
+
+        let addr = hello(localhost().build().unwrap()).await;
+        let ca = ca();
+        let policy = ConnectorPolicy::new().host(
+            "localhost",
+            HostSettings {
+                root_certificates: vec![ca],
+                ..Default::default()
+            },
+        );
+        let mut stream = policy.connect("localhost", tcp(addr).await).await.unwrap();
+        let mut buf = [0; 5];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+    }
+
+    #[cfg(feature = "runtime-async-std")]
+    #[async_std::test]
+    async fn keeps_default_protocol_floor() {
+        use crate::test_util::offered_versions;
+
+        let settings = HostSettings::default();
+        assert_eq!(
+            offered_versions(settings.connector()).await,
+            [0x0304, 0x0303]
+        );
+        let settings = HostSettings {
+            min_protocol_version: Some(Protocol::Tlsv10),
+            ..Default::default()
+        };
+        assert!(offered_versions(settings.connector())
+            .await
+            .contains(&0x0301));
+    }
+}
@@ -0,0 +1,619 @@
+use std::fmt;
+use std::future::poll_fn;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use crate::certificate::SubjectAltName;
+use crate::runtime::{AsyncRead, AsyncWrite};
+use crate::{AcceptError, CertificateError, CertificateInfo, TlsAcceptor, TlsStream};
+
+/// A condition on a client certificate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PrincipalRule {
+    /// The common name of the subject equals the given name.
+    CommonName(String),
+    /// The subject alternative names include the given email address, compared
+    /// case-insensitively.
+    Email(String),
+    /// The subject alternative names include the given URI, such as a SPIFFE ID.
+    Uri(String),
+    /// The SHA-256 fingerprint of the certificate equals the given digest.
+    Sha256Fingerprint([u8; 32]),
+}
+
+impl PrincipalRule {
+    /// Whether the certificate satisfies the rule.
+    pub fn matches(&self, cert: &CertificateInfo) -> bool {
+        match self {
+            PrincipalRule::CommonName(name) => cert.common_name.as_deref() == Some(name.as_str()),
+            PrincipalRule::Email(email) => cert.subject_alt_names.iter().any(
+                |san| matches!(san, SubjectAltName::Email(san) if san.eq_ignore_ascii_case(email)),
+            ),
+            PrincipalRule::Uri(uri) => cert
+                .subject_alt_names
+                .iter()
+                .any(|san| matches!(san, SubjectAltName::Uri(san) if san == uri)),
+            PrincipalRule::Sha256Fingerprint(digest) => cert.sha256_fingerprint == *digest,
+        }
+    }
+}
+
+/// Maps client certificates to application principals.
+///
+/// Rules are tried in the order they were added, and the principal of the first matching rule
+/// is used.
+#[derive(Debug, Clone)]
+pub struct PrincipalMap<P> {
+    rules: Vec<(PrincipalRule, P)>,
+}
+
+impl<P> Default for PrincipalMap<P> {
+    fn default() -> Self {
+        Self { rules: vec![] }
+    }
+}
+
+impl<P: Clone> PrincipalMap<P> {
+    /// Create a new, empty map.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Maps certificates satisfying `rule` to `principal`.
+    pub fn rule(mut self, rule: PrincipalRule, principal: P) -> Self {
+        self.rules.push((rule, principal));
+        self
+    }
+
+    /// Returns the principal of the first rule `cert` satisfies.
+    pub fn resolve(&self, cert: &CertificateInfo) -> Option<P> {
+        self.rules
+            .iter()
+            .find(|(rule, _)| rule.matches(cert))
+            .map(|(_, principal)| principal.clone())
+    }
+}
+
+/// An authorization decision, passed to the audit callback of an [`AuthorizingAcceptor`].
+#[derive(Debug, Clone)]
+pub struct AuditEvent<P> {
+    /// The subject of the client certificate, if one was presented.
+    pub subject: Option<String>,
+    /// The SHA-256 fingerprint of the client certificate, if one was presented.
+    pub sha256_fingerprint: Option<[u8; 32]>,
+    /// The principal the certificate was mapped to, if any.
+    pub principal: Option<P>,
+    /// The decision.
+    pub outcome: AuditOutcome,
+}
+
+/// The decision recorded in an [`AuditEvent`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuditOutcome {
+    /// The client was authorized.
+    Allowed,
+    /// The client was rejected for the given reason.
+    Denied(String),
+}
+
+/// An error returned from accepting a connection through an [`AuthorizingAcceptor`].
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    /// The handshake failed.
+    #[error("Accept({0})")]
+    Accept(#[from] AcceptError),
+    /// The client certificate could not be parsed.
+    #[error("Certificate({0})")]
+    Certificate(#[from] CertificateError),
+    /// The client is not authorized.
+    #[error("client not authorized: {reason}")]
+    Denied {
+        /// The subject of the client certificate, if one was presented.
+        subject: Option<String>,
+        /// Why the client was rejected.
+        reason: String,
+    },
+}
+
+impl From<native_tls::Error> for Error {
+    fn from(err: native_tls::Error) -> Self {
+        Error::Certificate(err.into())
+    }
+}
+
+type Authorize<P> = dyn Fn(&P, &CertificateInfo) -> Result<(), String> + Send + Sync;
+type Audit<P> = dyn Fn(&AuditEvent<P>) + Send + Sync;
+
+/// Accepts connections only from clients whose certificates map to an authorized principal.
+///
+/// After the handshake, the client certificate is mapped to a principal with a
+/// [`PrincipalMap`], and the authorization callback decides whether the principal may connect.
+/// Clients without a certificate or principal are rejected. Every decision is passed to the
+/// audit callback.
+///
+/// With an acceptor built with [`ClientAuth`](crate::ClientAuth), which uses OpenSSL, the
+/// decision is made while the client certificate is verified, and rejected clients see the
+/// handshake fail with a `handshake_failure` alert. OpenSSL derives that alert from the
+/// verification error and cannot send `access_denied` instead.
+///
+/// Otherwise, as with native-tls acceptors or sessions resumed without a certificate
+/// verification, the decision is made after the handshake, and rejected connections are closed
+/// with a `close_notify` alert, as the TLS backends cannot send other alerts once the handshake
+/// completed.
+///
+/// # Example
+///
+/// ```no_run
+/// # #[cfg(feature = "runtime-async-std")]
+/// # fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> { async_std::task::block_on(async {
+/// #
+/// use async_std::net::TcpListener;
+/// use async_native_tls::{
+///     AuthorizingAcceptor, Certificate, ClientAuth, PrincipalMap, PrincipalRule,
+///     TlsAcceptorBuilder,
+/// };
+///
+/// let clients_ca = Certificate::from_pem(&std::fs::read("clients-ca.pem")?)?;
+/// let acceptor = TlsAcceptorBuilder::from_pkcs8(
+///     std::fs::read("cert.pem")?,
+///     std::fs::read("key.pem")?,
+/// )
+/// .client_auth(ClientAuth::Require, vec![clients_ca])
+/// .build()?;
+///
+/// let principals = PrincipalMap::new()
+///     .rule(PrincipalRule::Uri("spiffe://example.com/billing".into()), "billing")
+///     .rule(PrincipalRule::CommonName("ops".into()), "ops");
+/// let acceptor = AuthorizingAcceptor::new(acceptor, principals)
+///     .authorize(|principal, _cert| match *principal {
+///         "ops" => Ok(()),
+///         _ => Err("not allowed on the admin port".to_string()),
+///     })
+///     .on_audit(|event| eprintln!("{:?}", event));
+///
+/// let listener = TcpListener::bind("127.0.0.1:8443").await?;
+/// let (stream, _) = listener.accept().await?;
+/// let (stream, principal) = acceptor.accept(stream).await?;
+/// #
+/// # Ok(()) }) }
+/// # #[cfg(feature = "runtime-tokio")]
+/// # fn main() {}
+/// ```
+pub struct AuthorizingAcceptor<P> {
+    acceptor: TlsAcceptor,
+    principals: PrincipalMap<P>,
+    authorize: Option<Arc<Authorize<P>>>,
+    audit: Option<Arc<Audit<P>>>,
+}
+
+impl<P: Clone> AuthorizingAcceptor<P> {
+    /// Create a new instance accepting connections with `acceptor` and mapping client
+    /// certificates with `principals`.
+    pub fn new(acceptor: TlsAcceptor, principals: PrincipalMap<P>) -> Self {
+        Self {
+            acceptor,
+            principals,
+            authorize: None,
+            audit: None,
+        }
+    }
+
+    /// Sets the callback deciding whether a principal may connect. It returns the reason for
+    /// rejecting the client as error.
+    ///
+    /// By default, every principal is authorized.
+    pub fn authorize<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&P, &CertificateInfo) -> Result<(), String> + Send + Sync + 'static,
+    {
+        self.authorize = Some(Arc::new(callback));
+        self
+    }
+
+    /// Sets a callback invoked with every authorization decision.
+    pub fn on_audit<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&AuditEvent<P>) + Send + Sync + 'static,
+    {
+        self.audit = Some(Arc::new(callback));
+        self
+    }
+
+    /// Accepts a new client connection, returning it along with the principal of the client.
+    pub async fn accept<S>(&self, stream: S) -> Result<(TlsStream<S>, P), Error>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+        P: Send + Sync + 'static,
+    {
+        #[cfg(all(
+            feature = "openssl",
+            not(any(target_os = "windows", target_vendor = "apple"))
+        ))]
+        if let Some(acceptor) = self.acceptor.openssl() {
+            return self.accept_openssl(acceptor, stream).await;
+        }
+
+        let stream = self.acceptor.accept(stream).await?;
+        self.authorize_stream(stream).await
+    }
+
+    /// Authorizes the client inside the certificate verification of the handshake, so that
+    /// rejected clients see the handshake fail.
+    #[cfg(all(
+        feature = "openssl",
+        not(any(target_os = "windows", target_vendor = "apple"))
+    ))]
+    async fn accept_openssl<S>(
+        &self,
+        acceptor: &openssl::ssl::SslAcceptor,
+        stream: S,
+    ) -> Result<(TlsStream<S>, P), Error>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+        P: Send + Sync + 'static,
+    {
+        use openssl::ssl::Ssl;
+        use openssl::x509::X509VerifyResult;
+        use std::sync::Mutex;
+
+        type Verified<P> =
+            Result<(CertificateInfo, Option<P>, Result<(), String>), CertificateError>;
+
+        let verified: Arc<Mutex<Option<Verified<P>>>> = Arc::new(Mutex::new(None));
+        let mut ssl = Ssl::new(acceptor.context()).map_err(AcceptError::from)?;
+        let principals = self.principals.clone();
+        let authorize = self.authorize.clone();
+        let slot = verified.clone();
+        ssl.set_verify_callback(acceptor.context().verify_mode(), move |preverify, ctx| {
+            if !preverify || ctx.error_depth() != 0 {
+                return preverify;
+            }
+            let cert = ctx
+                .current_cert()
+                .ok_or_else(|| CertificateError::Parse("no client certificate".to_string()))
+                .and_then(|cert| {
+                    cert.to_der()
+                        .map_err(|err| CertificateError::Parse(err.to_string()))
+                })
+                .and_then(|der| CertificateInfo::from_der(&der));
+            let accepted = match cert {
+                Ok(cert) => {
+                    let (principal, decision) = decide(&principals, authorize.as_deref(), &cert);
+                    let accepted = decision.is_ok();
+                    *slot.lock().unwrap() = Some(Ok((cert, principal, decision)));
+                    accepted
+                }
+                Err(err) => {
+                    *slot.lock().unwrap() = Some(Err(err));
+                    false
+                }
+            };
+            if !accepted {
+                ctx.set_error(X509VerifyResult::APPLICATION_VERIFICATION);
+            }
+            accepted
+        });
+
+        let res = crate::handshake::openssl_handshake(move |s| ssl.accept(s), stream).await;
+        let verified = verified.lock().unwrap().take();
+        match (res, verified) {
+            (_, Some(Err(err))) => Err(err.into()),
+            (Ok(stream), Some(Ok((cert, Some(principal), Ok(()))))) => {
+                self.audit(Some(&cert), &Some(principal.clone()), &Ok(()));
+                Ok((stream, principal))
+            }
+            // the client was authorized, but the rest of the handshake failed
+            (Err(err), Some(Ok((_, _, Ok(()))))) => Err(AcceptError::from(err).into()),
+            (_, Some(Ok((cert, principal, decision)))) => {
+                self.audit(Some(&cert), &principal, &decision);
+                Err(Error::Denied {
+                    subject: Some(cert.subject),
+                    reason: decision.err().unwrap_or_default(),
+                })
+            }
+            (Err(err), None) => Err(AcceptError::from(err).into()),
+            // no certificate was verified, as the client sent none or resumed a session
+            (Ok(stream), None) => self.authorize_stream(stream).await,
+        }
+    }
+
+    /// Authorizes the client of an established connection, closing it if it is rejected.
+    async fn authorize_stream<S>(
+        &self,
+        mut stream: TlsStream<S>,
+    ) -> Result<(TlsStream<S>, P), Error>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let cert = stream.peer_certificate_info()?;
+        let (principal, decision) = match &cert {
+            Some(cert) => decide(&self.principals, self.authorize.as_deref(), cert),
+            None => (None, Err("no client certificate".to_string())),
+        };
+        self.audit(cert.as_ref(), &principal, &decision);
+
+        match (principal, decision) {
+            (Some(principal), Ok(())) => Ok((stream, principal)),
+            (_, decision) => {
+                // the client is rejected either way, so a failing close_notify does not matter
+                let _ = close(&mut stream).await;
+                Err(Error::Denied {
+                    subject: cert.map(|cert| cert.subject),
+                    reason: decision.err().unwrap_or_default(),
+                })
+            }
+        }
+    }
+
+    fn audit(
+        &self,
+        cert: Option<&CertificateInfo>,
+        principal: &Option<P>,
+        decision: &Result<(), String>,
+    ) {
+        if let Some(audit) = &self.audit {
+            audit(&AuditEvent {
+                subject: cert.map(|cert| cert.subject.clone()),
+                sha256_fingerprint: cert.map(|cert| cert.sha256_fingerprint),
+                principal: principal.clone(),
+                outcome: match decision {
+                    Ok(()) => AuditOutcome::Allowed,
+                    Err(reason) => AuditOutcome::Denied(reason.clone()),
+                },
+            });
+        }
+    }
+}
+
+fn decide<P: Clone>(
+    principals: &PrincipalMap<P>,
+    authorize: Option<&Authorize<P>>,
+    cert: &CertificateInfo,
+) -> (Option<P>, Result<(), String>) {
+    let principal = match principals.resolve(cert) {
+        Some(principal) => principal,
+        None => return (None, Err("no principal for certificate".to_string())),
+    };
+    let decision = match authorize {
+        Some(authorize) => authorize(&principal, cert),
+        None => Ok(()),
+    };
+    (Some(principal), decision)
+}
+
+async fn close<S>(stream: &mut TlsStream<S>) -> std::io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    poll_fn(|cx| {
+        #[cfg(feature = "runtime-async-std")]
+        let res = Pin::new(&mut *stream).poll_close(cx);
+        #[cfg(feature = "runtime-tokio")]
+        let res = Pin::new(&mut *stream).poll_shutdown(cx);
+        res
+    })
+    .await
+}
+
+impl<P: Clone> Clone for AuthorizingAcceptor<P> {
+    fn clone(&self) -> Self {
+        Self {
+            acceptor: self.acceptor.clone(),
+            principals: self.principals.clone(),
+            authorize: self.authorize.clone(),
+            audit: self.audit.clone(),
+        }
+    }
+}
+
+impl<P: fmt::Debug> fmt::Debug for AuthorizingAcceptor<P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuthorizingAcceptor")
+            .field("principals", &self.principals)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(path: &str) -> CertificateInfo {
+        let cert = crate::Certificate::from_pem(&std::fs::read(path).unwrap()).unwrap();
+        CertificateInfo::from_certificate(&cert).unwrap()
+    }
+
+    #[test]
+    fn rules() {
+        let client = info("tests/client.pem");
+        let principals = PrincipalMap::new()
+            .rule(PrincipalRule::CommonName("localhost".into()), "server")
+            .rule(PrincipalRule::Email("Client@Example.com".into()), "email")
+            .rule(
+                PrincipalRule::Uri("spiffe://example.com/client".into()),
+                "uri",
+            );
+        assert_eq!(principals.resolve(&client), Some("email"));
+        assert_eq!(
+            principals.resolve(&info("tests/localhost.pem")),
+            Some("server")
+        );
+        assert_eq!(principals.resolve(&info("tests/ca.pem")), None);
+
+        let pinned = PrincipalMap::new()
+            .rule(PrincipalRule::Sha256Fingerprint([0; 32]), "nobody")
+            .rule(
+                PrincipalRule::Sha256Fingerprint(client.sha256_fingerprint),
+                "client",
+            );
+        assert_eq!(pinned.resolve(&client), Some("client"));
+    }
+
+    #[cfg(all(
+        feature = "runtime-async-std",
+        feature = "openssl",
+        not(any(target_os = "windows", target_vendor = "apple"))
+    ))]
+    #[async_std::test]
+    async fn denies_and_audits() {
+        use crate::runtime::AsyncReadExt;
+        use crate::test_util::{ca, localhost, tcp};
+        use crate::{ClientAuth, TlsConnector};
+        use async_std::net::TcpListener;
+        use std::sync::Mutex;
+
+        let acceptor = localhost()
+            .client_auth(ClientAuth::Require, vec![ca()])
+            .build()
+            .unwrap();
+        let events = Arc::new(Mutex::new(vec![]));
+        let recorded = events.clone();
+        let principals = PrincipalMap::new().rule(PrincipalRule::CommonName("client".into()), 7);
+        let acceptor = AuthorizingAcceptor::new(acceptor, principals)
+            .authorize(|principal, _| match principal {
+                7 => Err("suspended".to_string()),
+                _ => Ok(()),
+            })
+            .on_audit(move |event| recorded.lock().unwrap().push(event.clone()));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = async_std::task::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            acceptor
+                .accept(stream)
+                .await
+                .map(|(_, principal)| principal)
+        });
+
+        let cert = std::fs::read("tests/client.pem").unwrap();
+        let key = std::fs::read("tests/client-key.pem").unwrap();
+        let res = TlsConnector::new()
+            .danger_accept_invalid_certs(true)
+            .identity(crate::Identity::from_pkcs8(&cert, &key).unwrap())
+            .connect("localhost", tcp(addr).await)
+            .await;
+        // with TLS 1.3, the client finishes its handshake before the server rejects it
+        let rejected = match res {
+            Ok(mut stream) => {
+                let mut buf = vec![];
+                let err = stream.read_to_end(&mut buf).await.unwrap_err();
+                err.to_string().contains("handshake failure")
+            }
+            Err(err) => err.to_string().contains("handshake failure"),
+        };
+        assert!(rejected);
+
+        match server.await {
+            Err(Error::Denied { subject, reason }) => {
+                assert_eq!(subject.as_deref(), Some("O=async-native-tls, CN=client"));
+                assert_eq!(reason, "suspended");
+            }
+            res => panic!("unexpected result {:?}", res),
+        }
+        let events = events.lock().unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].principal, Some(7));
+        assert_eq!(
+            events[0].outcome,
+            AuditOutcome::Denied("suspended".to_string())
+        );
+    }
+
+    #[cfg(all(
+        feature = "runtime-async-std",
+        feature = "openssl",
+        not(any(target_os = "windows", target_vendor = "apple"))
+    ))]
+    #[async_std::test]
+    async fn handshake_fails_after_authorization() {
+        use crate::runtime::AsyncRead;
+        use crate::test_util::{ca, localhost, tcp};
+        use crate::{ClientAuth, TlsConnector};
+        use async_std::net::{TcpListener, TcpStream};
+        use std::io;
+        use std::pin::Pin;
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Mutex;
+        use std::task::{Context, Poll};
+
+        /// Fails every read once the client is authorized, as if it went away mid-handshake.
+        struct Reset {
+            stream: TcpStream,
+            authorized: Arc<AtomicBool>,
+        }
+
+        impl AsyncRead for Reset {
+            fn poll_read(
+                mut self: Pin<&mut Self>,
+                cx: &mut Context<'_>,
+                buf: &mut [u8],
+            ) -> Poll<io::Result<usize>> {
+                if self.authorized.load(Ordering::SeqCst) {
+                    return Poll::Ready(Err(io::ErrorKind::ConnectionReset.into()));
+                }
+                Pin::new(&mut self.stream).poll_read(cx, buf)
+            }
+        }
+
+        impl AsyncWrite for Reset {
+            fn poll_write(
+                mut self: Pin<&mut Self>,
+                cx: &mut Context<'_>,
+                buf: &[u8],
+            ) -> Poll<io::Result<usize>> {
+                Pin::new(&mut self.stream).poll_write(cx, buf)
+            }
+
+            fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+                Pin::new(&mut self.stream).poll_flush(cx)
+            }
+
+            fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+                Pin::new(&mut self.stream).poll_close(cx)
+            }
+        }
+
+        let acceptor = localhost()
+            .client_auth(ClientAuth::Require, vec![ca()])
+            .build()
+            .unwrap();
+        let authorized = Arc::new(AtomicBool::new(false));
+        let events = Arc::new(Mutex::new(vec![]));
+        let recorded = events.clone();
+        let principals = PrincipalMap::new().rule(PrincipalRule::CommonName("client".into()), 7);
+        let acceptor = AuthorizingAcceptor::new(acceptor, principals)
+            .authorize({
+                let authorized = authorized.clone();
+                move |_, _| {
+                    authorized.store(true, Ordering::SeqCst);
+                    Ok(())
+                }
+            })
+            .on_audit(move |event| recorded.lock().unwrap().push(event.clone()));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = async_std::task::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            acceptor
+                .accept(Reset { stream, authorized })
+                .await
+                .map(|(_, principal)| principal)
+        });
+
+        let cert = std::fs::read("tests/client.pem").unwrap();
+        let key = std::fs::read("tests/client-key.pem").unwrap();
+        let _ = TlsConnector::new()
+            .danger_accept_invalid_certs(true)
+            .identity(crate::Identity::from_pkcs8(&cert, &key).unwrap())
+            .connect("localhost", tcp(addr).await)
+            .await;
+
+        match server.await {
+            Err(Error::Accept(_)) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+        assert!(events.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,403 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::marker::Unpin;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+
+use zeroize::Zeroizing;
+
+use crate::acceptor::{Error, IdentityData};
+use crate::runtime::{unblock, AsyncRead, AsyncReadExt, AsyncWrite};
+use crate::{TlsAcceptor, TlsAcceptorBuilder, TlsStream};
+
+/// A [`TlsAcceptor`] whose identity can be replaced while it is in use.
+///
+/// Every call to [`accept`](ReloadableAcceptor::accept) uses the identity that was current when
+/// the call started, so handshakes already in flight are not affected by a reload. Clones share
+/// the same identity.
+///
+/// Reloaded identities are built with the settings of the [`TlsAcceptorBuilder`] passed to
+/// [`from_builder`](ReloadableAcceptor::from_builder), such as client authentication or ALPN,
+/// and with the defaults of [`TlsAcceptorBuilder`] otherwise.
+///
+/// # Example
+///
+/// ```no_run
+/// # #[cfg(feature = "runtime-async-std")]
+/// # fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> { async_std::task::block_on(async {
+/// #
+/// use std::time::Duration;
+/// use async_native_tls::{IdentitySource, ReloadableAcceptor};
+///
+/// let source = IdentitySource::pem("/etc/tls/cert.pem", "/etc/tls/key.pem");
+/// let acceptor = ReloadableAcceptor::load(&source).await?;
+///
+/// let mut watcher = acceptor.watch(source).await;
+/// async_std::task::spawn(async move {
+///     loop {
+///         async_std::task::sleep(Duration::from_secs(60)).await;
+///         if let Err(err) = watcher.check().await {
+///             eprintln!("failed to reload identity, keeping the previous one: {}", err);
+///         }
+///     }
+/// });
+/// #
+/// # Ok(()) }) }
+/// # #[cfg(feature = "runtime-tokio")]
+/// # fn main() {}
+/// ```
+#[derive(Clone)]
+pub struct ReloadableAcceptor {
+    current: Arc<RwLock<TlsAcceptor>>,
+    settings: Option<Arc<TlsAcceptorBuilder>>,
+}
+
+/// Files an identity is loaded from.
+#[derive(Clone)]
+pub enum IdentitySource {
+    /// A DER-formatted PKCS #12 archive and its password.
+    Pkcs12 {
+        /// Path to the archive.
+        path: PathBuf,
+        /// Password protecting the archive, zeroized on drop.
+        password: Zeroizing<String>,
+    },
+    /// A PEM-formatted certificate chain and a PEM-formatted PKCS #8 private key.
+    Pem {
+        /// Path to the certificate chain, leaf first.
+        cert: PathBuf,
+        /// Path to the private key.
+        key: PathBuf,
+    },
+}
+
+impl IdentitySource {
+    /// A PKCS #12 archive at `path`, protected by `password`.
+    pub fn pkcs12(path: impl Into<PathBuf>, password: impl Into<String>) -> Self {
+        IdentitySource::Pkcs12 {
+            path: path.into(),
+            password: Zeroizing::new(password.into()),
+        }
+    }
+
+    /// A PEM certificate chain at `cert` and a PKCS #8 private key at `key`.
+    pub fn pem(cert: impl Into<PathBuf>, key: impl Into<PathBuf>) -> Self {
+        IdentitySource::Pem {
+            cert: cert.into(),
+            key: key.into(),
+        }
+    }
+
+    /// Reads the files and builds an acceptor from them, off the async executor.
+    pub async fn load(&self) -> Result<TlsAcceptor, Error> {
+        self.load_with(None).await
+    }
+
+    /// Reads the files and builds an acceptor from them with `settings`, or with the defaults of
+    /// [`TlsAcceptorBuilder`].
+    async fn load_with(&self, settings: Option<&TlsAcceptorBuilder>) -> Result<TlsAcceptor, Error> {
+        let source = self.clone();
+        let settings = settings.cloned();
+        unblock(move || source.load_blocking(settings)).await
+    }
+
+    fn load_blocking(&self, settings: Option<TlsAcceptorBuilder>) -> Result<TlsAcceptor, Error> {
+        let identity = match self {
+            IdentitySource::Pkcs12 { path, password } => IdentityData::Pkcs12 {
+                der: fs::read(path)?,
+                password: password.clone(),
+            },
+            IdentitySource::Pem { cert, key } => IdentityData::Pkcs8 {
+                cert: fs::read(cert)?,
+                key: Zeroizing::new(fs::read(key)?),
+            },
+        };
+        build(settings.as_ref(), identity)
+    }
+
+    async fn stamps(&self) -> io::Result<Vec<Stamp>> {
+        let source = self.clone();
+        unblock(move || source.stamps_blocking()).await
+    }
+
+    fn stamps_blocking(&self) -> io::Result<Vec<Stamp>> {
+        let paths = match self {
+            IdentitySource::Pkcs12 { path, .. } => vec![path],
+            IdentitySource::Pem { cert, key } => vec![cert, key],
+        };
+        paths
+            .into_iter()
+            .map(|path| {
+                let metadata = fs::metadata(path)?;
+                Ok((metadata.modified()?, metadata.len()))
+            })
+            .collect()
+    }
+}
+
+/// The modification time and size of a file, which tell whether it changed.
+type Stamp = (SystemTime, u64);
+
+/// Builds an acceptor for `identity` with `settings`, or with the defaults of
+/// [`TlsAcceptorBuilder`].
+fn build(
+    settings: Option<&TlsAcceptorBuilder>,
+    identity: IdentityData,
+) -> Result<TlsAcceptor, Error> {
+    match settings {
+        Some(settings) => settings.clone().identity(identity),
+        None => TlsAcceptorBuilder::with_identity(identity),
+    }
+    .build()
+}
+
+impl fmt::Debug for IdentitySource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IdentitySource::Pkcs12 { path, .. } => f
+                .debug_struct("Pkcs12")
+                .field("path", path)
+                .finish_non_exhaustive(),
+            IdentitySource::Pem { cert, key } => f
+                .debug_struct("Pem")
+                .field("cert", cert)
+                .field("key", key)
+                .finish(),
+        }
+    }
+}
+
+impl ReloadableAcceptor {
+    /// Create a new instance serving the identity of `acceptor`.
+    ///
+    /// Reloaded identities are built with the defaults of [`TlsAcceptorBuilder`], not with the
+    /// settings of `acceptor`; use [`from_builder`](ReloadableAcceptor::from_builder) to keep them.
+    pub fn new(acceptor: TlsAcceptor) -> Self {
+        Self {
+            current: Arc::new(RwLock::new(acceptor)),
+            settings: None,
+        }
+    }
+
+    /// Create a new instance serving the acceptor built by `builder`, and building reloaded
+    /// identities with the same settings.
+    pub fn from_builder(builder: TlsAcceptorBuilder) -> Result<Self, Error> {
+        let acceptor = builder.clone().build()?;
+        Ok(Self {
+            current: Arc::new(RwLock::new(acceptor)),
+            settings: Some(Arc::new(builder)),
+        })
+    }
+
+    /// Create a new instance serving the identity found in `source`.
+    pub async fn load(source: &IdentitySource) -> Result<Self, Error> {
+        Ok(Self::new(source.load().await?))
+    }
+
+    /// Returns the acceptor currently in use.
+    pub fn current(&self) -> TlsAcceptor {
+        self.current
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Atomically replaces the acceptor used for new connections.
+    pub fn replace(&self, acceptor: TlsAcceptor) {
+        *self.current.write().unwrap_or_else(|e| e.into_inner()) = acceptor;
+    }
+
+    /// Replaces the identity with one read from `source`.
+    ///
+    /// On error the previous identity stays in use.
+    pub async fn reload(&self, source: &IdentitySource) -> Result<(), Error> {
+        self.replace(source.load_with(self.settings.as_deref()).await?);
+        Ok(())
+    }
+
+    /// Replaces the identity with a PKCS #12 archive read from `file`.
+    ///
+    /// On error the previous identity stays in use.
+    pub async fn reload_pkcs12<R, S>(&self, mut file: R, password: S) -> Result<(), Error>
+    where
+        R: AsyncRead + Unpin,
+        S: AsRef<str>,
+    {
+        let mut der = vec![];
+        file.read_to_end(&mut der).await?;
+        let identity = IdentityData::Pkcs12 {
+            der,
+            password: Zeroizing::new(password.as_ref().to_string()),
+        };
+        self.replace(build(self.settings.as_deref(), identity)?);
+        Ok(())
+    }
+
+    /// Returns a watcher that reloads the identity whenever the files of `source` change.
+    ///
+    /// The files are assumed to hold the identity currently in use.
+    pub async fn watch(&self, source: IdentitySource) -> IdentityWatcher {
+        let stamps = source.stamps().await.ok();
+        IdentityWatcher {
+            acceptor: self.clone(),
+            source,
+            stamps,
+        }
+    }
+
+    /// Accepts a new client connection with the provided stream, using the current identity.
+    pub async fn accept<S>(&self, stream: S) -> Result<TlsStream<S>, Error>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let acceptor = self.current();
+        acceptor.accept(stream).await
+    }
+}
+
+impl fmt::Debug for ReloadableAcceptor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReloadableAcceptor")
+            .field("settings", &self.settings)
+            .finish_non_exhaustive()
+    }
+}
+
+impl From<TlsAcceptor> for ReloadableAcceptor {
+    fn from(acceptor: TlsAcceptor) -> Self {
+        Self::new(acceptor)
+    }
+}
+
+/// Reloads a [`ReloadableAcceptor`] when the files of an [`IdentitySource`] change.
+///
+/// Changes are detected by modification time and size. The watcher does not poll by itself; call
+/// [`check`](IdentityWatcher::check) periodically from a task of your runtime.
+#[derive(Debug)]
+pub struct IdentityWatcher {
+    acceptor: ReloadableAcceptor,
+    source: IdentitySource,
+    stamps: Option<Vec<Stamp>>,
+}
+
+impl IdentityWatcher {
+    /// Reloads the identity if the files changed since the last successful load.
+    ///
+    /// Returns whether a new identity was installed. On error the previous identity stays in use
+    /// and the next call tries again.
+    pub async fn check(&mut self) -> Result<bool, Error> {
+        let stamps = self.source.stamps().await?;
+        if self.stamps.as_ref() == Some(&stamps) {
+            return Ok(false);
+        }
+        self.acceptor.reload(&self.source).await?;
+        self.stamps = Some(stamps);
+        Ok(true)
+    }
+}
+
+#[cfg(all(test, feature = "runtime-async-std"))]
+mod tests {
+    use super::*;
+    use crate::test_util::{hello, localhost, pem_der, tcp};
+    use crate::TlsConnector;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    async fn peer_certificate(acceptor: &ReloadableAcceptor) -> Vec<u8> {
+        let addr = hello(acceptor.current()).await;
+        let connector = TlsConnector::new().danger_accept_invalid_certs(true);
+        let stream = connector
+            .connect("127.0.0.1", tcp(addr).await)
+            .await
+            .unwrap();
+        stream
+            .peer_certificate()
+            .unwrap()
+            .unwrap()
+            .to_der()
+            .unwrap()
+    }
+
+    #[async_std::test]
+    async fn reload_replaces_identity() {
+        let acceptor =
+            ReloadableAcceptor::load(&IdentitySource::pkcs12("tests/identity.pfx", "hello"))
+                .await
+                .unwrap();
+        assert_eq!(
+            peer_certificate(&acceptor).await,
+            pem_der("tests/public.pem")
+        );
+
+        acceptor
+            .reload(&IdentitySource::pem(
+                "tests/localhost.pem",
+                "tests/localhost-key.pem",
+            ))
+            .await
+            .unwrap();
+        assert_eq!(
+            peer_certificate(&acceptor).await,
+            pem_der("tests/localhost.pem")
+        );
+    }
+
+    #[async_std::test]
+    async fn reload_keeps_settings() {
+        let acceptor = ReloadableAcceptor::from_builder(localhost().accept_alpns(&["h2"])).unwrap();
+        acceptor
+            .reload(&IdentitySource::pkcs12("tests/identity.pfx", "hello"))
+            .await
+            .unwrap();
+
+        let addr = hello(acceptor.current()).await;
+        let stream = TlsConnector::new()
+            .danger_accept_invalid_certs(true)
+            .request_alpns(&["h2"])
+            .connect("127.0.0.1", tcp(addr).await)
+            .await
+            .unwrap();
+        assert_eq!(stream.negotiated_alpn().unwrap(), Some(b"h2".to_vec()));
+    }
+
+    /// Sets the modification time of `path`, so that changes are seen even within the
+    /// resolution of the file system clock.
+    fn touch(path: &std::path::Path, secs: u64) {
+        let file = fs::File::options().write(true).open(path).unwrap();
+        file.set_modified(UNIX_EPOCH + Duration::from_secs(secs))
+            .unwrap();
+    }
+
+    #[async_std::test]
+    async fn failed_reload_keeps_identity() {
+        let dir =
+            std::env::temp_dir().join(format!("async-native-tls-reload-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (cert, key) = (dir.join("cert.pem"), dir.join("key.pem"));
+        fs::copy("tests/localhost.pem", &cert).unwrap();
+        fs::copy("tests/localhost-key.pem", &key).unwrap();
+        touch(&cert, 1000);
+        touch(&key, 1000);
+
+        let source = IdentitySource::pem(&cert, &key);
+        let acceptor = ReloadableAcceptor::load(&source).await.unwrap();
+        let mut watcher = acceptor.watch(source).await;
+        assert!(!watcher.check().await.unwrap());
+
+        fs::write(&key, b"not a key").unwrap();
+        touch(&key, 2000);
+        assert!(watcher.check().await.is_err());
+        assert_eq!(
+            peer_certificate(&acceptor).await,
+            pem_der("tests/localhost.pem")
+        );
+
+        fs::copy("tests/localhost-key.pem", &key).unwrap();
+        touch(&key, 3000);
+        assert!(watcher.check().await.unwrap());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
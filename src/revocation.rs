@@ -0,0 +1,497 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::SystemTime;
+
+use x509_parser::prelude::{CertificateRevocationList, FromDer, Pem};
+
+use crate::certificate::{system_time, CertificateInfo};
+use crate::runtime::{unblock, AsyncRead, AsyncWrite};
+use crate::{Certificate, TlsStream};
+
+/// Certificate revocation lists, checked against peer certificates after the handshake.
+///
+/// CRLs are looked up by the issuer name of the peer certificate. Of several CRLs of one
+/// issuer, the most recent is used. What happens if the CRL of an issuer is missing or past its
+/// `nextUpdate` is decided by the [`RevocationPolicy`].
+///
+/// CRLs added with [`add`](CrlStore::add) or [`load_file`](CrlStore::load_file) are trusted as
+/// given, so they have to come from a trusted source. Use
+/// [`add_signed_by`](CrlStore::add_signed_by) to verify their signature instead.
+///
+/// Every certificate of the peer chain is checked, except a self-issued trust anchor. native-tls
+/// only exposes the leaf certificate of the peer, so the intermediate certificates are only
+/// checked with the `openssl` feature, when the connection is made with OpenSSL.
+///
+/// # Example
+///
+/// ```no_run
+/// # #[cfg(feature = "runtime-async-std")]
+/// # fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> { async_std::task::block_on(async {
+/// #
+/// use std::sync::Arc;
+/// use async_std::net::TcpStream;
+/// use async_native_tls::{CrlStore, RevocationPolicy, TlsConnector};
+///
+/// let mut crls = CrlStore::new().policy(RevocationPolicy::FailClosed);
+/// crls.load_file("/etc/service/crls/internal-ca.crl").await?;
+///
+/// let stream = TcpStream::connect("db.internal:5432").await?;
+/// let stream = TlsConnector::new()
+///     .check_revocation(Arc::new(crls))
+///     .connect("db.internal", stream)
+///     .await?;
+/// #
+/// # Ok(()) }) }
+/// # #[cfg(feature = "runtime-tokio")]
+/// # fn main() {}
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CrlStore {
+    crls: HashMap<String, Crl>,
+    policy: RevocationPolicy,
+    now: Option<SystemTime>,
+}
+
+/// What to do when no current CRL is available for the issuer of a certificate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RevocationPolicy {
+    /// Accept the certificate, using a stale CRL if there is one.
+    #[default]
+    FailOpen,
+    /// Reject the certificate.
+    FailClosed,
+}
+
+/// The reason a certificate was revoked, as defined in
+/// [RFC 5280](https://tools.ietf.org/html/rfc5280#section-5.3.1).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RevocationReason {
+    /// No reason given.
+    Unspecified,
+    /// The private key was compromised.
+    KeyCompromise,
+    /// The private key of the CA was compromised.
+    CaCompromise,
+    /// The subject's name or other information changed.
+    AffiliationChanged,
+    /// The certificate was replaced.
+    Superseded,
+    /// The certificate is no longer needed.
+    CessationOfOperation,
+    /// The certificate is temporarily on hold.
+    CertificateHold,
+    /// The privileges of the subject were withdrawn.
+    PrivilegeWithdrawn,
+    /// The private key of the attribute authority was compromised.
+    AaCompromise,
+    /// A reason code not defined by RFC 5280.
+    Other(u8),
+}
+
+/// An error returned from loading CRLs or checking a certificate against them.
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    /// The certificate has been revoked.
+    #[error("certificate {serial} of {issuer} was revoked")]
+    Revoked {
+        /// The issuer of the certificate.
+        issuer: String,
+        /// The serial number of the certificate, as colon-separated hex.
+        serial: String,
+        /// The reason given in the CRL, if any.
+        reason: Option<RevocationReason>,
+        /// When the certificate was revoked.
+        revoked_at: SystemTime,
+    },
+    /// The CRL of the issuer is past its `nextUpdate`, under [`RevocationPolicy::FailClosed`].
+    #[error("CRL of {issuer} is stale")]
+    Stale {
+        /// The issuer of the certificate.
+        issuer: String,
+        /// When the CRL should have been replaced.
+        next_update: SystemTime,
+    },
+    /// No CRL is loaded for the issuer, under [`RevocationPolicy::FailClosed`].
+    #[error("no CRL for {issuer}")]
+    NoCrl {
+        /// The issuer of the certificate.
+        issuer: String,
+    },
+    /// The peer presented no certificate.
+    #[error("peer presented no certificate")]
+    NoPeerCertificate,
+    /// The peer certificate could not be parsed.
+    #[error("Certificate({0})")]
+    Certificate(#[from] crate::CertificateError),
+    /// A CRL file could not be read.
+    #[error("Io({0})")]
+    Io(#[from] io::Error),
+    /// A CRL could not be parsed.
+    #[error("invalid CRL: {0}")]
+    Parse(String),
+    /// The signature of a CRL does not verify with the key of its issuer.
+    #[error("CRL of {issuer} has an invalid signature")]
+    BadSignature {
+        /// The issuer named in the CRL.
+        issuer: String,
+    },
+    /// Verifying CRL signatures is not supported by the TLS backend.
+    #[error("verifying CRL signatures is not supported on this platform")]
+    Unsupported,
+}
+
+impl From<native_tls::Error> for Error {
+    fn from(err: native_tls::Error) -> Self {
+        Error::Certificate(err.into())
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Crl {
+    this_update: SystemTime,
+    next_update: Option<SystemTime>,
+    revoked: HashMap<Vec<u8>, Revoked>,
+}
+
+#[derive(Debug, Clone)]
+struct Revoked {
+    revoked_at: SystemTime,
+    reason: Option<RevocationReason>,
+}
+
+impl CrlStore {
+    /// Create a new, empty store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets what to do when no current CRL is available for an issuer.
+    ///
+    /// Defaults to [`RevocationPolicy::FailOpen`].
+    pub fn policy(mut self, policy: RevocationPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Overrides the current time used to detect stale CRLs. Defaults to the system time.
+    pub fn now(mut self, now: SystemTime) -> Self {
+        self.now = Some(now);
+        self
+    }
+
+    /// Adds the CRLs in `data`, either a single DER-encoded CRL or PEM with one or more
+    /// `X509 CRL` blocks. Returns the number of CRLs found.
+    pub fn add(&mut self, data: &[u8]) -> Result<usize, Error> {
+        let ders = split(data)?;
+        for der in &ders {
+            self.insert(der)?;
+        }
+        Ok(ders.len())
+    }
+
+    /// Like [`add`](CrlStore::add), but verifies that every CRL is signed by `issuer` first.
+    ///
+    /// Only supported with the OpenSSL backend; returns [`Error::Unsupported`] elsewhere.
+    pub fn add_signed_by(&mut self, data: &[u8], issuer: &Certificate) -> Result<usize, Error> {
+        let ders = split(data)?;
+        let issuer = issuer.to_der()?;
+        for der in &ders {
+            if !verify_signature(der, &issuer)? {
+                let (_, crl) = CertificateRevocationList::from_der(der)
+                    .map_err(|e| Error::Parse(e.to_string()))?;
+                return Err(Error::BadSignature {
+                    issuer: crl.issuer().to_string(),
+                });
+            }
+        }
+        for der in &ders {
+            self.insert(der)?;
+        }
+        Ok(ders.len())
+    }
+
+    /// Reads and adds the CRLs in the file at `path`, see [`add`](CrlStore::add).
+    pub async fn load_file(&mut self, path: impl AsRef<Path>) -> Result<usize, Error> {
+        let path = path.as_ref().to_path_buf();
+        let data = unblock(move || fs::read(&path)).await?;
+        self.add(&data)
+    }
+
+    /// Checks a certificate against the CRL of its issuer.
+    pub fn check(&self, cert: &Certificate) -> Result<(), Error> {
+        self.check_info(&CertificateInfo::from_certificate(cert)?)
+    }
+
+    /// Checks every certificate of a chain, leaf first, except a self-issued trust anchor at
+    /// its end.
+    pub fn check_chain(&self, chain: &[Certificate]) -> Result<(), Error> {
+        if chain.is_empty() {
+            return Err(Error::NoPeerCertificate);
+        }
+        for (i, cert) in chain.iter().enumerate() {
+            let info = CertificateInfo::from_certificate(cert)?;
+            // trust anchors are not subject to revocation
+            if i > 0 && i == chain.len() - 1 && info.subject == info.issuer {
+                continue;
+            }
+            self.check_info(&info)?;
+        }
+        Ok(())
+    }
+
+    /// Checks the certificate chain of the peer of `stream`, as presented by a server or a
+    /// client, see [`check_chain`](CrlStore::check_chain).
+    pub fn verify<S>(&self, stream: &TlsStream<S>) -> Result<(), Error>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let chain = stream
+            .peer_certificate_chain()?
+            .ok_or(Error::NoPeerCertificate)?;
+        self.check_chain(&chain)
+    }
+
+    fn check_info(&self, info: &CertificateInfo) -> Result<(), Error> {
+        let crl = match self.crls.get(&info.issuer) {
+            Some(crl) => crl,
+            None if self.policy == RevocationPolicy::FailClosed => {
+                return Err(Error::NoCrl {
+                    issuer: info.issuer.clone(),
+                })
+            }
+            None => return Ok(()),
+        };
+
+        if let Some(revoked) = crl.revoked.get(&info.serial) {
+            return Err(Error::Revoked {
+                issuer: info.issuer.clone(),
+                serial: info.serial_hex(),
+                reason: revoked.reason,
+                revoked_at: revoked.revoked_at,
+            });
+        }
+
+        let now = self.now.unwrap_or_else(SystemTime::now);
+        match crl.next_update {
+            Some(next_update)
+                if next_update < now && self.policy == RevocationPolicy::FailClosed =>
+            {
+                Err(Error::Stale {
+                    issuer: info.issuer.clone(),
+                    next_update,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn insert(&mut self, der: &[u8]) -> Result<(), Error> {
+        let (_, crl) =
+            CertificateRevocationList::from_der(der).map_err(|e| Error::Parse(e.to_string()))?;
+        let mut revoked = HashMap::new();
+        for entry in crl.iter_revoked_certificates() {
+            let code = entry.reason_code().map(|(_, code)| code.0);
+            // removeFromCRL entries of delta CRLs take a certificate off hold
+            if code == Some(8) {
+                continue;
+            }
+            revoked.insert(
+                entry.raw_serial().to_vec(),
+                Revoked {
+                    revoked_at: system_time(entry.revocation_date.timestamp()),
+                    reason: code.map(reason),
+                },
+            );
+        }
+        let parsed = Crl {
+            this_update: system_time(crl.last_update().timestamp()),
+            next_update: crl.next_update().map(|time| system_time(time.timestamp())),
+            revoked,
+        };
+
+        let issuer = crl.issuer().to_string();
+        match self.crls.get(&issuer) {
+            Some(existing) if existing.this_update >= parsed.this_update => {}
+            _ => {
+                self.crls.insert(issuer, parsed);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for RevocationReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RevocationReason::Unspecified => f.write_str("unspecified"),
+            RevocationReason::KeyCompromise => f.write_str("key compromise"),
+            RevocationReason::CaCompromise => f.write_str("CA compromise"),
+            RevocationReason::AffiliationChanged => f.write_str("affiliation changed"),
+            RevocationReason::Superseded => f.write_str("superseded"),
+            RevocationReason::CessationOfOperation => f.write_str("cessation of operation"),
+            RevocationReason::CertificateHold => f.write_str("certificate hold"),
+            RevocationReason::PrivilegeWithdrawn => f.write_str("privilege withdrawn"),
+            RevocationReason::AaCompromise => f.write_str("AA compromise"),
+            RevocationReason::Other(code) => write!(f, "reason {}", code),
+        }
+    }
+}
+
+pub(crate) fn reason(code: u8) -> RevocationReason {
+    match code {
+        0 => RevocationReason::Unspecified,
+        1 => RevocationReason::KeyCompromise,
+        2 => RevocationReason::CaCompromise,
+        3 => RevocationReason::AffiliationChanged,
+        4 => RevocationReason::Superseded,
+        5 => RevocationReason::CessationOfOperation,
+        6 => RevocationReason::CertificateHold,
+        9 => RevocationReason::PrivilegeWithdrawn,
+        10 => RevocationReason::AaCompromise,
+        code => RevocationReason::Other(code),
+    }
+}
+
+/// Splits PEM into the DER of its `X509 CRL` blocks, or passes DER through.
+fn split(data: &[u8]) -> Result<Vec<Vec<u8>>, Error> {
+    // DER starts with a SEQUENCE tag
+    if data.first() == Some(&0x30) {
+        return Ok(vec![data.to_vec()]);
+    }
+    let mut ders = vec![];
+    for pem in Pem::iter_from_buffer(data) {
+        let pem = pem.map_err(|e| Error::Parse(e.to_string()))?;
+        if pem.label == "X509 CRL" {
+            ders.push(pem.contents);
+        }
+    }
+    if ders.is_empty() {
+        return Err(Error::Parse("no X509 CRL blocks found".to_string()));
+    }
+    Ok(ders)
+}
+
+#[cfg(not(any(target_os = "windows", target_vendor = "apple")))]
+fn verify_signature(crl: &[u8], issuer: &[u8]) -> Result<bool, Error> {
+    use openssl::x509::{X509Crl, X509};
+
+    let parse = |e: openssl::error::ErrorStack| Error::Parse(e.to_string());
+    let key = X509::from_der(issuer)
+        .and_then(|issuer| issuer.public_key())
+        .map_err(parse)?;
+    X509Crl::from_der(crl)
+        .and_then(|crl| crl.verify(&key))
+        .map_err(parse)
+}
+
+#[cfg(any(target_os = "windows", target_vendor = "apple"))]
+fn verify_signature(_crl: &[u8], _issuer: &[u8]) -> Result<bool, Error> {
+    Err(Error::Unsupported)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn certificate(path: &str) -> Certificate {
+        Certificate::from_pem(&std::fs::read(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn revoked() {
+        let mut crls = CrlStore::new();
+        assert_eq!(
+            crls.add(&std::fs::read("tests/crl.pem").unwrap()).unwrap(),
+            1
+        );
+        match crls.check(&certificate("tests/localhost.pem")) {
+            Err(Error::Revoked { serial, reason, .. }) => {
+                assert_eq!(serial, "10:01");
+                assert_eq!(reason, Some(RevocationReason::KeyCompromise));
+            }
+            res => panic!("unexpected result {:?}", res),
+        }
+        assert!(crls.check(&certificate("tests/ca.pem")).is_ok());
+    }
+
+    #[test]
+    fn policy() {
+        let crl = std::fs::read("tests/crl.pem").unwrap();
+        let mut crls = CrlStore::new()
+            .policy(RevocationPolicy::FailClosed)
+            .now(SystemTime::now() + Duration::from_secs(200 * 365 * 86400));
+        crls.add(&crl).unwrap();
+        assert!(matches!(
+            crls.check(&certificate("tests/ca.pem")),
+            Err(Error::Stale { .. })
+        ));
+        assert!(matches!(
+            crls.check(&certificate("tests/public.pem")),
+            Err(Error::NoCrl { .. })
+        ));
+
+        let crls = crls.policy(RevocationPolicy::FailOpen);
+        assert!(crls.check(&certificate("tests/ca.pem")).is_ok());
+        assert!(crls.check(&certificate("tests/public.pem")).is_ok());
+    }
+
+    #[test]
+    fn chain() {
+        let mut crls = CrlStore::new().policy(RevocationPolicy::FailClosed);
+        crls.add(&std::fs::read("tests/crl.pem").unwrap()).unwrap();
+        let ca = certificate("tests/ca.pem");
+        let localhost = certificate("tests/localhost.pem");
+        let public = certificate("tests/public.pem");
+
+        assert!(matches!(
+            crls.check_chain(&[localhost.clone(), ca.clone()]),
+            Err(Error::Revoked { .. })
+        ));
+        // certificates past the leaf are checked too; the order is not validated here
+        assert!(matches!(
+            crls.check_chain(&[ca.clone(), localhost, ca.clone()]),
+            Err(Error::Revoked { .. })
+        ));
+        // a self-issued anchor is skipped, a self-issued leaf is not
+        assert!(crls.check_chain(&[ca.clone(), public.clone()]).is_ok());
+        assert!(matches!(
+            crls.check_chain(&[public]),
+            Err(Error::NoCrl { .. })
+        ));
+        assert!(matches!(
+            crls.check_chain(&[]),
+            Err(Error::NoPeerCertificate)
+        ));
+    }
+
+    #[cfg(feature = "runtime-async-std")]
+    #[async_std::test]
+    async fn load_file() {
+        let mut crls = CrlStore::new();
+        assert_eq!(crls.load_file("tests/crl.pem").await.unwrap(), 1);
+        assert!(matches!(
+            crls.load_file("tests/missing.crl").await,
+            Err(Error::Io(_))
+        ));
+    }
+
+    #[cfg(not(any(target_os = "windows", target_vendor = "apple")))]
+    #[test]
+    fn signature() {
+        let crl = std::fs::read("tests/crl.pem").unwrap();
+        let mut crls = CrlStore::new();
+        assert_eq!(
+            crls.add_signed_by(&crl, &certificate("tests/ca.pem"))
+                .unwrap(),
+            1
+        );
+        assert!(matches!(
+            crls.add_signed_by(&crl, &certificate("tests/localhost.pem")),
+            Err(Error::BadSignature { .. })
+        ));
+    }
+}
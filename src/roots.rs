@@ -0,0 +1,287 @@
+use std::collections::HashSet;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::runtime::unblock;
+use crate::Certificate;
+
+const BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+const END: &str = "-----END CERTIFICATE-----";
+
+/// Root certificates collected from PEM bundles and certificate directories.
+///
+/// Entries that cannot be parsed are skipped and listed in
+/// [`skipped`](RootCertificates::skipped) instead of failing the whole load. Certificates found
+/// more than once, e.g. through the hash links of a directory, are only added once.
+///
+/// # Example
+///
+/// ```no_run
+/// # #[cfg(feature = "runtime-async-std")]
+/// # fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> { async_std::task::block_on(async {
+/// #
+/// use async_native_tls::{RootCertificates, TlsConnector};
+///
+/// let mut roots = RootCertificates::new();
+/// roots.load_file("/etc/service/ca-bundle.pem").await?;
+/// roots.load_env().await?;
+/// for skipped in roots.skipped() {
+///     eprintln!("skipping root certificate: {}", skipped);
+/// }
+/// let connector = TlsConnector::new().add_root_certificates(roots);
+/// #
+/// # Ok(()) }) }
+/// # #[cfg(feature = "runtime-tokio")]
+/// # fn main() {}
+/// ```
+#[derive(Default)]
+pub struct RootCertificates {
+    certificates: Vec<Certificate>,
+    seen: HashSet<Vec<u8>>,
+    skipped: Vec<SkippedCertificate>,
+}
+
+/// A PEM entry or file that could not be loaded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkippedCertificate {
+    /// The file the entry was found in.
+    pub path: PathBuf,
+    /// The position of the entry in the file, or `None` if the file could not be read.
+    pub index: Option<usize>,
+    /// Why the entry was skipped.
+    pub reason: String,
+}
+
+impl fmt::Display for SkippedCertificate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.index {
+            Some(index) => write!(
+                f,
+                "{} (certificate {}): {}",
+                self.path.display(),
+                index,
+                self.reason
+            ),
+            None => write!(f, "{}: {}", self.path.display(), self.reason),
+        }
+    }
+}
+
+impl RootCertificates {
+    /// Create a new, empty collection.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds every certificate of the PEM bundle at `path`.
+    ///
+    /// Fails only if the file cannot be read.
+    pub async fn load_file(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref().to_path_buf();
+        let loaded = unblock(move || {
+            let pem = fs::read(&path)?;
+            Ok::<_, io::Error>((split_pem(&String::from_utf8_lossy(&pem)), path))
+        })
+        .await?;
+        self.extend(vec![loaded]);
+        Ok(())
+    }
+
+    /// Adds every certificate of the PEM files in the directory `dir`, such as a directory
+    /// prepared with `openssl rehash`.
+    ///
+    /// Fails only if the directory cannot be listed. Files without certificates are ignored.
+    pub async fn load_dir(&mut self, dir: impl AsRef<Path>) -> io::Result<()> {
+        let dir = dir.as_ref().to_path_buf();
+        let (loaded, unreadable) = unblock(move || read_dir(&dir)).await?;
+        self.extend(loaded);
+        self.skipped.extend(unreadable);
+        Ok(())
+    }
+
+    /// Adds the certificates named by the `SSL_CERT_FILE` and `SSL_CERT_DIR` environment
+    /// variables, as used by OpenSSL. `SSL_CERT_DIR` may list several directories separated by
+    /// `:` (`;` on Windows).
+    ///
+    /// Returns whether either variable was set.
+    pub async fn load_env(&mut self) -> io::Result<bool> {
+        let file = env::var_os("SSL_CERT_FILE").filter(|file| !file.is_empty());
+        let dirs = env::var_os("SSL_CERT_DIR").filter(|dirs| !dirs.is_empty());
+        let found = file.is_some() || dirs.is_some();
+        if let Some(file) = file {
+            self.load_file(file).await?;
+        }
+        if let Some(dirs) = dirs {
+            for dir in env::split_paths(&dirs) {
+                self.load_dir(dir).await?;
+            }
+        }
+        Ok(found)
+    }
+
+    /// Returns the certificates loaded so far.
+    pub fn certificates(&self) -> &[Certificate] {
+        &self.certificates
+    }
+
+    /// Returns the entries skipped so far.
+    pub fn skipped(&self) -> &[SkippedCertificate] {
+        &self.skipped
+    }
+
+    /// Returns the number of certificates loaded.
+    pub fn len(&self) -> usize {
+        self.certificates.len()
+    }
+
+    /// Returns whether no certificates were loaded.
+    pub fn is_empty(&self) -> bool {
+        self.certificates.is_empty()
+    }
+
+    fn extend(&mut self, loaded: Vec<Loaded>) {
+        for (entries, path) in loaded {
+            for (index, entry) in entries.into_iter().enumerate() {
+                let entry = entry.and_then(|cert| match cert.to_der() {
+                    Ok(der) => Ok((der, cert)),
+                    Err(err) => Err(err.to_string()),
+                });
+                match entry {
+                    Ok((der, cert)) => {
+                        if self.seen.insert(der) {
+                            self.certificates.push(cert);
+                        }
+                    }
+                    Err(reason) => self.skipped.push(SkippedCertificate {
+                        path: path.clone(),
+                        index: Some(index),
+                        reason,
+                    }),
+                }
+            }
+        }
+    }
+}
+
+impl IntoIterator for RootCertificates {
+    type Item = Certificate;
+    type IntoIter = std::vec::IntoIter<Certificate>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.certificates.into_iter()
+    }
+}
+
+impl fmt::Debug for RootCertificates {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RootCertificates")
+            .field("certificates", &self.certificates.len())
+            .field("skipped", &self.skipped)
+            .finish()
+    }
+}
+
+type Loaded = (Vec<Result<Certificate, String>>, PathBuf);
+
+fn read_dir(dir: &Path) -> io::Result<(Vec<Loaded>, Vec<SkippedCertificate>)> {
+    let mut paths = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    paths.sort();
+
+    let (mut loaded, mut unreadable) = (vec![], vec![]);
+    for path in paths {
+        if !path.is_file() {
+            continue;
+        }
+        match fs::read(&path) {
+            Ok(pem) => {
+                let entries = split_pem(&String::from_utf8_lossy(&pem));
+                if !entries.is_empty() {
+                    loaded.push((entries, path));
+                }
+            }
+            Err(err) => unreadable.push(SkippedCertificate {
+                path,
+                index: None,
+                reason: format!("failed to read: {}", err),
+            }),
+        }
+    }
+    Ok((loaded, unreadable))
+}
+
+/// Splits a PEM bundle into its certificates, parsing each on its own. Other PEM blocks and text
+/// between blocks are ignored.
+pub(crate) fn split_pem(pem: &str) -> Vec<Result<Certificate, String>> {
+    let mut entries = vec![];
+    let mut rest = pem;
+    while let Some(start) = rest.find(BEGIN) {
+        let end = match rest[start..].find(END) {
+            Some(end) => start + end + END.len(),
+            None => {
+                entries.push(Err("unterminated certificate".to_string()));
+                break;
+            }
+        };
+        entries.push(
+            Certificate::from_pem(&rest.as_bytes()[start..end]).map_err(|err| err.to_string()),
+        );
+        rest = &rest[end..];
+    }
+    entries
+}
+
+#[cfg(all(test, feature = "runtime-async-std"))]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!(
+            "async-native-tls-roots-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[async_std::test]
+    async fn bundle_with_bad_entry() {
+        let ca = fs::read_to_string("tests/ca.pem").unwrap();
+        let leaf = fs::read_to_string("tests/localhost.pem").unwrap();
+        let garbage = format!("{}\nbm90IGEgY2VydGlmaWNhdGU=\n{}\n", BEGIN, END);
+        let dir = temp_dir("bundle");
+        let bundle = dir.join("bundle.pem");
+        fs::write(&bundle, format!("# CA\n{}{}{}{}", ca, garbage, leaf, ca)).unwrap();
+
+        let mut roots = RootCertificates::new();
+        roots.load_file(&bundle).await.unwrap();
+        assert_eq!(roots.len(), 2);
+        assert_eq!(roots.skipped().len(), 1);
+        assert_eq!(roots.skipped()[0].index, Some(1));
+
+        assert!(roots.load_file(dir.join("missing.pem")).await.is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[async_std::test]
+    async fn hashed_dir() {
+        let dir = temp_dir("dir");
+        fs::copy("tests/ca.pem", dir.join("ca.pem")).unwrap();
+        fs::copy("tests/ca.pem", dir.join("0a1b2c3d.0")).unwrap();
+        fs::copy("tests/localhost.pem", dir.join("localhost.crt")).unwrap();
+        fs::write(dir.join("README"), "not a certificate").unwrap();
+        fs::create_dir(dir.join("nested")).unwrap();
+
+        let mut roots = RootCertificates::new();
+        roots.load_dir(&dir).await.unwrap();
+        assert_eq!(roots.len(), 2);
+        assert!(roots.skipped().is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -7,3 +7,31 @@ pub(crate) use futures_util::io::{AsyncReadExt, AsyncWriteExt};
 
 #[cfg(feature = "runtime-tokio")]
 pub(crate) use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite};
+
+/// Runs blocking work, such as file system access, off the async executor.
+#[cfg(feature = "runtime-async-std")]
+pub(crate) async fn unblock<T, E, F>(f: F) -> Result<T, E>
+where
+    F: FnOnce() -> Result<T, E> + Send + 'static,
+    T: Send + 'static,
+    E: From<std::io::Error> + Send + 'static,
+{
+    blocking::unblock(f).await
+}
+
+/// Runs blocking work, such as file system access, off the async executor.
+///
+/// Fails with an I/O error if the runtime cancels the work as it shuts down.
+#[cfg(feature = "runtime-tokio")]
+pub(crate) async fn unblock<T, E, F>(f: F) -> Result<T, E>
+where
+    F: FnOnce() -> Result<T, E> + Send + 'static,
+    T: Send + 'static,
+    E: From<std::io::Error> + Send + 'static,
+{
+    match tokio::task::spawn_blocking(f).await {
+        Ok(res) => res,
+        Err(err) if err.is_panic() => std::panic::resume_unwind(err.into_panic()),
+        Err(err) => Err(std::io::Error::other(err).into()),
+    }
+}
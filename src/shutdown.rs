@@ -0,0 +1,666 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::future::{poll_fn, Future};
+use std::io;
+use std::marker::Unpin;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll};
+
+use atomic_waker::AtomicWaker;
+
+use crate::runtime::{AsyncRead, AsyncWrite};
+use crate::{TlsAcceptor, TlsStream};
+
+/// A handle coordinating the graceful shutdown of a TLS server.
+///
+/// Connections accepted through [`Shutdown::accept`] are tracked until they are dropped. Once
+/// [`Shutdown::drain`] is called, no new connections are accepted and in-flight handshakes are
+/// allowed to finish. The drain sends `close_notify` on every established connection, including
+/// idle ones nobody is reading from, and connections report end of stream the next time they are
+/// read from. Whatever is still open when the deadline passes is aborted.
+///
+/// # Example
+///
+/// ```no_run
+/// # #[cfg(feature = "runtime-async-std")]
+/// # fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> { async_std::task::block_on(async {
+/// #
+/// use std::time::Duration;
+/// use async_std::prelude::*;
+/// use async_std::net::TcpListener;
+/// use async_std::fs::File;
+/// use async_native_tls::{Shutdown, TlsAcceptor};
+///
+/// let key = File::open("tests/identity.pfx").await?;
+/// let acceptor = TlsAcceptor::new(key, "hello").await?;
+/// let listener = TcpListener::bind("127.0.0.1:8443").await?;
+/// let shutdown = Shutdown::new();
+///
+/// let server = {
+///     let shutdown = shutdown.clone();
+///     async_std::task::spawn(async move {
+///         let mut incoming = listener.incoming();
+///         while let Some(stream) = incoming.next().await {
+///             if shutdown.is_shutting_down() {
+///                 break;
+///             }
+///             let (acceptor, shutdown) = (acceptor.clone(), shutdown.clone());
+///             let stream = stream.unwrap();
+///             async_std::task::spawn(async move {
+///                 let mut stream = shutdown.accept(&acceptor, stream).await.unwrap();
+///                 // handle stream here, reads return end of stream once draining
+///             });
+///         }
+///     })
+/// };
+///
+/// // later, on redeploy
+/// let report = shutdown.drain(async_std::task::sleep(Duration::from_secs(30))).await;
+/// println!("drained {}, aborted {}", report.drained, report.aborted);
+/// #
+/// # Ok(()) }) }
+/// # #[cfg(feature = "runtime-tokio")]
+/// # fn main() {}
+/// ```
+#[derive(Clone, Default)]
+pub struct Shutdown {
+    shared: Arc<Shared>,
+}
+
+/// An error returned from accepting a connection through a [`Shutdown`] handle.
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    /// The handshake failed.
+    #[error("Accept({0})")]
+    Accept(#[from] crate::AcceptError),
+    /// The server is shutting down and no longer accepts connections.
+    #[error("server is shutting down")]
+    ShuttingDown,
+    /// The drain deadline passed before the handshake completed.
+    #[error("handshake aborted by shutdown")]
+    Aborted,
+}
+
+/// The outcome of [`Shutdown::drain`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DrainReport {
+    /// Number of handshakes and connections that finished or were closed before the deadline.
+    pub drained: usize,
+    /// Number of handshakes and connections that were still open at the deadline.
+    pub aborted: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    // the default of `Shared::phase`
+    Running = 0,
+    Draining,
+    Aborted,
+}
+
+impl Phase {
+    fn from_u8(phase: u8) -> Self {
+        match phase {
+            0 => Phase::Running,
+            1 => Phase::Draining,
+            _ => Phase::Aborted,
+        }
+    }
+}
+
+/// State shared by a handle and its tracked connections.
+///
+/// Reads and writes only look at `phase` and the waker of their own connection; the registry is
+/// locked when connections come and go, when the phase changes and while draining.
+#[derive(Default)]
+struct Shared {
+    phase: AtomicU8,
+    registry: Mutex<Registry>,
+}
+
+#[derive(Default)]
+struct Registry {
+    next_id: usize,
+    tasks: HashMap<usize, Task>,
+    drainers: HashMap<usize, Arc<AtomicWaker>>,
+}
+
+struct Task {
+    waker: Arc<AtomicWaker>,
+    // set once the handshake completed, so that the drain can send `close_notify` itself
+    close: Option<Arc<dyn Close>>,
+}
+
+impl Task {
+    fn is_open(&self) -> bool {
+        self.close.as_ref().is_none_or(|close| !close.is_closed())
+    }
+}
+
+impl Shared {
+    fn phase(&self) -> Phase {
+        Phase::from_u8(self.phase.load(Ordering::Acquire))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Registry> {
+        self.registry.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Moves to `phase` and wakes every tracked task and drainer, with the registry locked.
+    fn set_phase(&self, registry: &Registry, phase: Phase) {
+        self.phase.store(phase as u8, Ordering::Release);
+        for task in registry.tasks.values() {
+            task.waker.wake();
+        }
+        registry.wake_drainers();
+    }
+}
+
+impl Registry {
+    fn wake_drainers(&self) {
+        for waker in self.drainers.values() {
+            waker.wake();
+        }
+    }
+}
+
+impl Shutdown {
+    /// Create a new handle. Clones of it share the same state.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns `true` once [`Shutdown::drain`] has been called.
+    pub fn is_shutting_down(&self) -> bool {
+        self.shared.phase() != Phase::Running
+    }
+
+    /// Accepts a new client connection and tracks it until the returned [`Connection`] is dropped.
+    ///
+    /// Fails with [`Error::ShuttingDown`] once draining has started, and with [`Error::Aborted`]
+    /// if the drain deadline passes while the handshake is still in progress.
+    pub async fn accept<S>(&self, acceptor: &TlsAcceptor, stream: S) -> Result<Connection<S>, Error>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let tracked = self.track()?;
+        let mut handshake = Box::pin(acceptor.accept(stream));
+        let stream = poll_fn(|cx| {
+            if tracked.poll_phase(cx) == Phase::Aborted {
+                return Poll::Ready(Err(Error::Aborted));
+            }
+            handshake.as_mut().poll(cx).map_err(Error::from)
+        })
+        .await?;
+
+        let stream = Arc::new(Stream {
+            stream: Mutex::new(stream),
+            closed: AtomicBool::new(false),
+        });
+        tracked.set_close(stream.clone());
+        Ok(Connection { stream, tracked })
+    }
+
+    /// Stops accepting connections and waits for the tracked ones to finish.
+    ///
+    /// Established connections are closed by the drain: it sends `close_notify` on each of them,
+    /// and their next read returns end of stream. Once `deadline` resolves, the remaining
+    /// handshakes fail with [`Error::Aborted`] and the connections that are still open fail every
+    /// further read and write.
+    pub async fn drain<F>(&self, deadline: F) -> DrainReport
+    where
+        F: Future,
+    {
+        let (pending, drainer) = {
+            let mut registry = self.shared.lock();
+            let phase = match self.shared.phase() {
+                Phase::Running => Phase::Draining,
+                phase => phase,
+            };
+            self.shared.set_phase(&registry, phase);
+            let id = registry.next_id;
+            registry.next_id += 1;
+            let waker = Arc::new(AtomicWaker::new());
+            registry.drainers.insert(id, waker.clone());
+            let drainer = Drainer {
+                shared: self.shared.clone(),
+                id,
+                waker,
+            };
+            (registry.tasks.len(), drainer)
+        };
+
+        let mut deadline = Box::pin(deadline);
+        poll_fn(|cx| {
+            drainer.waker.register(cx.waker());
+            let expired = deadline.as_mut().poll(cx).is_ready();
+            if !expired && self.shared.phase() == Phase::Draining {
+                // the streams are polled without the registry locked, as their owners take it
+                // when they are dropped
+                let open: Vec<_> = {
+                    let registry = self.shared.lock();
+                    registry
+                        .tasks
+                        .values()
+                        .filter_map(|task| task.close.clone())
+                        .filter(|close| !close.is_closed())
+                        .collect()
+                };
+                for close in open {
+                    let _ = close.poll_close_notify(cx);
+                }
+            }
+
+            let registry = self.shared.lock();
+            let active = registry
+                .tasks
+                .values()
+                .filter(|task| task.is_open())
+                .count();
+            if active == 0 || expired {
+                if active > 0 {
+                    self.shared.set_phase(&registry, Phase::Aborted);
+                }
+                return Poll::Ready(DrainReport {
+                    drained: pending.saturating_sub(active),
+                    aborted: active,
+                });
+            }
+            Poll::Pending
+        })
+        .await
+    }
+
+    fn track(&self) -> Result<Tracked, Error> {
+        let mut registry = self.shared.lock();
+        // the phase only changes with the registry locked
+        if self.shared.phase() != Phase::Running {
+            return Err(Error::ShuttingDown);
+        }
+        let id = registry.next_id;
+        registry.next_id += 1;
+        let waker = Arc::new(AtomicWaker::new());
+        registry.tasks.insert(
+            id,
+            Task {
+                waker: waker.clone(),
+                close: None,
+            },
+        );
+
+        Ok(Tracked {
+            shared: self.shared.clone(),
+            id,
+            waker,
+        })
+    }
+}
+
+impl fmt::Debug for Shutdown {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Shutdown")
+            .field("phase", &self.shared.phase())
+            .field("active", &self.shared.lock().tasks.len())
+            .finish()
+    }
+}
+
+/// Membership of a single handshake or connection in a [`Shutdown`].
+struct Tracked {
+    shared: Arc<Shared>,
+    id: usize,
+    waker: Arc<AtomicWaker>,
+}
+
+impl Tracked {
+    /// Returns the current phase, registering the task to be woken when it changes.
+    fn poll_phase(&self, cx: &mut Context<'_>) -> Phase {
+        // register first, so that a phase change after the load still wakes the task
+        self.waker.register(cx.waker());
+        self.shared.phase()
+    }
+
+    /// Hands the established stream to the registry and lets the drainers close it.
+    fn set_close(&self, close: Arc<dyn Close>) {
+        let mut registry = self.shared.lock();
+        if let Some(task) = registry.tasks.get_mut(&self.id) {
+            task.close = Some(close);
+        }
+        registry.wake_drainers();
+    }
+}
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        let mut registry = self.shared.lock();
+        registry.tasks.remove(&self.id);
+        registry.wake_drainers();
+    }
+}
+
+/// Registration of a single [`Shutdown::drain`] call.
+struct Drainer {
+    shared: Arc<Shared>,
+    id: usize,
+    waker: Arc<AtomicWaker>,
+}
+
+impl Drop for Drainer {
+    fn drop(&mut self) {
+        self.shared.lock().drainers.remove(&self.id);
+    }
+}
+
+/// A stream that a drain can send `close_notify` on without owning it.
+trait Close: Send + Sync {
+    fn poll_close_notify(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>>;
+
+    fn is_closed(&self) -> bool;
+}
+
+/// The stream of a [`Connection`], shared with the registry.
+struct Stream<S> {
+    stream: Mutex<TlsStream<S>>,
+    closed: AtomicBool,
+}
+
+impl<S> Stream<S> {
+    fn lock(&self) -> MutexGuard<'_, TlsStream<S>> {
+        self.stream.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Acquire)
+    }
+}
+
+impl<S> Close for Stream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    fn poll_close_notify(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if self.is_closed() {
+            return Poll::Ready(Ok(()));
+        }
+        let mut stream = self.lock();
+        #[cfg(feature = "runtime-async-std")]
+        let res = Pin::new(&mut *stream).poll_close(cx);
+        #[cfg(feature = "runtime-tokio")]
+        let res = Pin::new(&mut *stream).poll_shutdown(cx);
+
+        // a failed close leaves nothing to wait for either
+        if res.is_ready() {
+            self.closed.store(true, Ordering::Release);
+        }
+        res
+    }
+
+    fn is_closed(&self) -> bool {
+        Stream::is_closed(self)
+    }
+}
+
+/// A [`TlsStream`] accepted through a [`Shutdown`] handle.
+///
+/// Reads and writes pass through to the TLS stream while the server is running. Once draining has
+/// started, the drain sends `close_notify` and the next read returns end of stream.
+pub struct Connection<S> {
+    stream: Arc<Stream<S>>,
+    tracked: Tracked,
+}
+
+impl<S> Connection<S> {
+    /// Returns the TLS stream.
+    ///
+    /// The stream is shared with a running [`Shutdown::drain`], which locks it while sending
+    /// `close_notify`; don't hold the guard across an `.await`.
+    pub fn get_ref(&self) -> MutexGuard<'_, TlsStream<S>> {
+        self.stream.lock()
+    }
+
+    /// Returns the TLS stream for mutation.
+    ///
+    /// See [`Connection::get_ref`] for how long the guard may be held.
+    pub fn get_mut(&mut self) -> MutexGuard<'_, TlsStream<S>> {
+        self.stream.lock()
+    }
+}
+
+impl<S> Connection<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    fn poll_close_notify(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if self.stream.is_closed() {
+            return Poll::Ready(Ok(()));
+        }
+        let res = self.stream.poll_close_notify(cx);
+        if res.is_ready() {
+            self.tracked.shared.lock().wake_drainers();
+        }
+        res
+    }
+}
+
+impl<S> fmt::Debug for Connection<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Connection")
+            .field("closed", &self.stream.is_closed())
+            .finish_non_exhaustive()
+    }
+}
+
+fn aborted() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::ConnectionAborted,
+        "connection aborted by shutdown",
+    )
+}
+
+#[cfg(feature = "runtime-async-std")]
+impl<S> AsyncRead for Connection<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.tracked.poll_phase(cx) {
+            Phase::Running => Pin::new(&mut *self.stream.lock()).poll_read(cx, buf),
+            Phase::Draining => self.poll_close_notify(cx).map_ok(|()| 0),
+            Phase::Aborted => Poll::Ready(Err(aborted())),
+        }
+    }
+}
+
+#[cfg(feature = "runtime-tokio")]
+impl<S> AsyncRead for Connection<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.tracked.poll_phase(cx) {
+            Phase::Running => Pin::new(&mut *self.stream.lock()).poll_read(cx, buf),
+            Phase::Draining => self.poll_close_notify(cx),
+            Phase::Aborted => Poll::Ready(Err(aborted())),
+        }
+    }
+}
+
+impl<S> AsyncWrite for Connection<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if self.tracked.poll_phase(cx) == Phase::Aborted {
+            return Poll::Ready(Err(aborted()));
+        }
+        Pin::new(&mut *self.stream.lock()).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if self.tracked.poll_phase(cx) == Phase::Aborted {
+            return Poll::Ready(Err(aborted()));
+        }
+        Pin::new(&mut *self.stream.lock()).poll_flush(cx)
+    }
+
+    #[cfg(feature = "runtime-async-std")]
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_close_notify(cx)
+    }
+
+    #[cfg(feature = "runtime-tokio")]
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_close_notify(cx)
+    }
+}
+
+#[cfg(all(test, feature = "runtime-async-std"))]
+mod tests {
+    use super::*;
+    use crate::runtime::{AsyncReadExt, AsyncWriteExt};
+    use crate::test_util::{localhost, tcp};
+    use crate::TlsConnector;
+    use async_std::net::{TcpListener, TcpStream};
+    use async_std::task;
+    use std::time::Duration;
+
+    async fn server(shutdown: Shutdown, read: bool) -> std::net::SocketAddr {
+        let acceptor = localhost().build().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        task::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let (acceptor, shutdown) = (acceptor.clone(), shutdown.clone());
+                task::spawn(async move {
+                    let mut stream = match shutdown.accept(&acceptor, stream).await {
+                        Ok(stream) => stream,
+                        Err(_) => return,
+                    };
+                    stream.write_all(b"hello").await.unwrap();
+                    if read {
+                        let mut buf = Vec::new();
+                        let _ = stream.read_to_end(&mut buf).await;
+                    } else {
+                        task::sleep(Duration::from_secs(60)).await;
+                    }
+                });
+            }
+        });
+        addr
+    }
+
+    async fn client(addr: std::net::SocketAddr) -> crate::TlsStream<TcpStream> {
+        let connector = TlsConnector::new().danger_accept_invalid_certs(true);
+        let mut stream = connector
+            .connect("127.0.0.1", tcp(addr).await)
+            .await
+            .unwrap();
+        let mut buf = [0; 5];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+        stream
+    }
+
+    #[async_std::test]
+    async fn drain_sends_close_notify() {
+        let shutdown = Shutdown::new();
+        let addr = server(shutdown.clone(), true).await;
+        let mut stream = client(addr).await;
+
+        let drain = task::spawn({
+            let shutdown = shutdown.clone();
+            async move { shutdown.drain(task::sleep(Duration::from_secs(10))).await }
+        });
+
+        let mut res = Vec::new();
+        stream.read_to_end(&mut res).await.unwrap();
+        assert!(res.is_empty());
+
+        let report = drain.await;
+        assert_eq!(
+            report,
+            DrainReport {
+                drained: 1,
+                aborted: 0
+            }
+        );
+    }
+
+    #[async_std::test]
+    async fn drain_closes_idle_connections() {
+        let shutdown = Shutdown::new();
+        let addr = server(shutdown.clone(), false).await;
+        let mut stream = client(addr).await;
+
+        let report = shutdown.drain(task::sleep(Duration::from_secs(10))).await;
+        assert_eq!(
+            report,
+            DrainReport {
+                drained: 1,
+                aborted: 0
+            }
+        );
+
+        let mut res = Vec::new();
+        stream.read_to_end(&mut res).await.unwrap();
+        assert!(res.is_empty());
+    }
+
+    #[async_std::test]
+    async fn drain_aborts_after_deadline() {
+        let shutdown = Shutdown::new();
+        let addr = server(shutdown.clone(), false).await;
+        // never starts the handshake
+        let _stream = tcp(addr).await;
+        while shutdown.shared.lock().tasks.is_empty() {
+            task::sleep(Duration::from_millis(5)).await;
+        }
+
+        let report = shutdown.drain(task::sleep(Duration::from_millis(50))).await;
+        assert_eq!(
+            report,
+            DrainReport {
+                drained: 0,
+                aborted: 1
+            }
+        );
+    }
+
+    #[async_std::test]
+    async fn rejects_after_shutdown() {
+        let shutdown = Shutdown::new();
+        assert_eq!(
+            shutdown
+                .drain(task::sleep(Duration::from_secs(1)))
+                .await
+                .aborted,
+            0
+        );
+        assert!(shutdown.is_shutting_down());
+
+        let acceptor = localhost().build().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let _client = tcp(addr).await;
+        let (stream, _) = listener.accept().await.unwrap();
+        assert!(matches!(
+            shutdown.accept(&acceptor, stream).await,
+            Err(Error::ShuttingDown)
+        ));
+    }
+}
@@ -0,0 +1,119 @@
+//! Fixtures shared by the tests of several modules.
+
+use std::future::Future;
+use std::net::SocketAddr;
+
+use async_std::net::{TcpListener, TcpStream};
+use async_std::task::JoinHandle;
+
+use crate::runtime::{AsyncReadExt, AsyncWriteExt};
+use crate::{Certificate, TlsAcceptor, TlsAcceptorBuilder, TlsConnector, TlsStream};
+
+/// A builder for an acceptor with the `localhost` identity, issued by `tests/ca.pem`.
+pub(crate) fn localhost() -> TlsAcceptorBuilder {
+    TlsAcceptorBuilder::from_pkcs8(
+        std::fs::read("tests/localhost.pem").unwrap(),
+        std::fs::read("tests/localhost-key.pem").unwrap(),
+    )
+}
+
+/// The CA certificate issuing the test identities.
+pub(crate) fn ca() -> Certificate {
+    Certificate::from_pem(&std::fs::read("tests/ca.pem").unwrap()).unwrap()
+}
+
+/// Accepts a single connection with `acceptor`, writes `hello` to it and passes it to `then`.
+///
+/// The task resolves to `None` if the handshake fails.
+pub(crate) async fn serve<F, Fut, T>(
+    acceptor: TlsAcceptor,
+    then: F,
+) -> (SocketAddr, JoinHandle<Option<T>>)
+where
+    F: FnOnce(TlsStream<TcpStream>) -> Fut + Send + 'static,
+    Fut: Future<Output = T> + Send + 'static,
+    T: Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let task = async_std::task::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = acceptor.accept(stream).await.ok()?;
+        stream.write_all(b"hello").await.ok()?;
+        Some(then(stream).await)
+    });
+    (addr, task)
+}
+
+/// Accepts a single connection with `acceptor` and writes `hello` to it.
+pub(crate) async fn hello(acceptor: TlsAcceptor) -> SocketAddr {
+    serve(acceptor, |_| async {}).await.0
+}
+
+/// Connects to `addr` over TCP.
+pub(crate) async fn tcp(addr: SocketAddr) -> TcpStream {
+    TcpStream::connect(addr).await.unwrap()
+}
+
+/// Reads a PEM-encoded certificate and returns it DER-encoded.
+pub(crate) fn pem_der(path: &str) -> Vec<u8> {
+    Certificate::from_pem(&std::fs::read(path).unwrap())
+        .unwrap()
+        .to_der()
+        .unwrap()
+}
+
+/// Accepts connections with `acceptor` until the test ends, writing `hello` to each and closing
+/// it cleanly.
+pub(crate) async fn hello_all(acceptor: TlsAcceptor) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    async_std::task::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            if let Ok(mut stream) = acceptor.accept(stream).await {
+                let _ = stream.write_all(b"hello").await;
+                let _ = stream.close().await;
+            }
+        }
+    });
+    addr
+}
+
+/// Returns the protocol versions `connector` offers in the `supported_versions` extension of its
+/// ClientHello.
+pub(crate) async fn offered_versions(connector: TlsConnector) -> Vec<u16> {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let client = async_std::task::spawn(async move {
+        let _ = connector.connect("localhost", tcp(addr).await).await;
+    });
+    let (mut stream, _) = listener.accept().await.unwrap();
+    let mut header = [0; 5];
+    stream.read_exact(&mut header).await.unwrap();
+    let mut hello = vec![0; u16::from_be_bytes([header[3], header[4]]) as usize];
+    stream.read_exact(&mut hello).await.unwrap();
+    drop(stream);
+    client.await;
+
+    let u16_at = |i: usize| u16::from_be_bytes([hello[i], hello[i + 1]]) as usize;
+    // handshake header, legacy_version and random
+    let mut i = 4 + 2 + 32;
+    i += 1 + hello[i] as usize;
+    i += 2 + u16_at(i);
+    i += 1 + hello[i] as usize;
+    let end = i + 2 + u16_at(i);
+    i += 2;
+    while i < end {
+        let (kind, len) = (u16_at(i), u16_at(i + 2));
+        i += 4;
+        if kind == 0x2b {
+            return hello[i + 1..i + len]
+                .chunks(2)
+                .map(|version| u16::from_be_bytes([version[0], version[1]]))
+                .collect();
+        }
+        i += len;
+    }
+    vec![]
+}
@@ -0,0 +1,639 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use crate::{ConnectError, MtaStsError, MtaStsMode, MtaStsPolicy};
+
+/// The result type of a failed session, as defined in
+/// [RFC 8460, section 4.3](https://tools.ietf.org/html/rfc8460#section-4.3).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum TlsRptResultType {
+    /// The receiving MX did not offer STARTTLS.
+    StarttlsNotSupported,
+    /// The certificate presented did not match the MX host.
+    CertificateHostMismatch,
+    /// The certificate has expired.
+    CertificateExpired,
+    /// The certificate did not chain to a trusted root.
+    CertificateNotTrusted,
+    /// Any other failure to validate the session.
+    ValidationFailure,
+    /// The TLSA records of the MX were invalid.
+    TlsaInvalid,
+    /// DNSSEC validation of the TLSA records failed.
+    DnssecInvalid,
+    /// The sender requires DANE, but the MX has no TLSA records.
+    DaneRequired,
+    /// The MTA-STS policy could not be fetched.
+    StsPolicyFetchError,
+    /// The MTA-STS policy was malformed.
+    StsPolicyInvalid,
+    /// The certificate of the MTA-STS policy host was invalid.
+    StsWebpkiInvalid,
+}
+
+impl TlsRptResultType {
+    /// Returns the name used in reports, e.g. `certificate-expired`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TlsRptResultType::StarttlsNotSupported => "starttls-not-supported",
+            TlsRptResultType::CertificateHostMismatch => "certificate-host-mismatch",
+            TlsRptResultType::CertificateExpired => "certificate-expired",
+            TlsRptResultType::CertificateNotTrusted => "certificate-not-trusted",
+            TlsRptResultType::ValidationFailure => "validation-failure",
+            TlsRptResultType::TlsaInvalid => "tlsa-invalid",
+            TlsRptResultType::DnssecInvalid => "dnssec-invalid",
+            TlsRptResultType::DaneRequired => "dane-required",
+            TlsRptResultType::StsPolicyFetchError => "sts-policy-fetch-error",
+            TlsRptResultType::StsPolicyInvalid => "sts-policy-invalid",
+            TlsRptResultType::StsWebpkiInvalid => "sts-webpki-invalid",
+        }
+    }
+}
+
+impl fmt::Display for TlsRptResultType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl From<&ConnectError> for TlsRptResultType {
+    /// Classifies a handshake failure.
+    ///
+    /// Connectors using the OpenSSL backend report why the server certificate failed
+    /// validation, which is classified by its verification code. Other failures, including
+    /// every failure of the other backends, are a `validation-failure`.
+    fn from(err: &ConnectError) -> Self {
+        match err {
+            #[cfg(all(
+                feature = "openssl",
+                not(any(target_os = "windows", target_vendor = "apple"))
+            ))]
+            ConnectError::InvalidCertificate { code, .. } => verify_result(*code),
+            _ => TlsRptResultType::ValidationFailure,
+        }
+    }
+}
+
+/// Classifies an OpenSSL certificate verification failure by its `X509_V_ERR_*` code, as
+/// defined in `openssl/x509_vfy.h`; the openssl crate does not name them.
+#[cfg(all(
+    feature = "openssl",
+    not(any(target_os = "windows", target_vendor = "apple"))
+))]
+fn verify_result(code: openssl::x509::X509VerifyResult) -> TlsRptResultType {
+    const UNABLE_TO_GET_ISSUER_CERT: i32 = 2;
+    const CERT_NOT_YET_VALID: i32 = 9;
+    const CERT_HAS_EXPIRED: i32 = 10;
+    const DEPTH_ZERO_SELF_SIGNED_CERT: i32 = 18;
+    const SELF_SIGNED_CERT_IN_CHAIN: i32 = 19;
+    const UNABLE_TO_GET_ISSUER_CERT_LOCALLY: i32 = 20;
+    const UNABLE_TO_VERIFY_LEAF_SIGNATURE: i32 = 21;
+    const CERT_UNTRUSTED: i32 = 27;
+    const HOSTNAME_MISMATCH: i32 = 62;
+    const IP_ADDRESS_MISMATCH: i32 = 64;
+
+    match code.as_raw() {
+        CERT_NOT_YET_VALID | CERT_HAS_EXPIRED => TlsRptResultType::CertificateExpired,
+        HOSTNAME_MISMATCH | IP_ADDRESS_MISMATCH => TlsRptResultType::CertificateHostMismatch,
+        UNABLE_TO_GET_ISSUER_CERT
+        | DEPTH_ZERO_SELF_SIGNED_CERT
+        | SELF_SIGNED_CERT_IN_CHAIN
+        | UNABLE_TO_GET_ISSUER_CERT_LOCALLY
+        | UNABLE_TO_VERIFY_LEAF_SIGNATURE
+        | CERT_UNTRUSTED => TlsRptResultType::CertificateNotTrusted,
+        _ => TlsRptResultType::ValidationFailure,
+    }
+}
+
+impl From<&MtaStsError> for TlsRptResultType {
+    fn from(err: &MtaStsError) -> Self {
+        match err {
+            MtaStsError::Connect(err) => err.into(),
+            MtaStsError::Fetch(_) => TlsRptResultType::StsPolicyFetchError,
+            MtaStsError::InvalidPolicy(_) => TlsRptResultType::StsPolicyInvalid,
+            MtaStsError::MxMismatch { .. } | MtaStsError::Open(_) => {
+                TlsRptResultType::ValidationFailure
+            }
+        }
+    }
+}
+
+#[cfg(feature = "x509")]
+impl From<&crate::DaneError> for TlsRptResultType {
+    fn from(err: &crate::DaneError) -> Self {
+        use crate::DaneError;
+
+        match err {
+            DaneError::Connect(err) => err.into(),
+            DaneError::NoUsableRecords => TlsRptResultType::TlsaInvalid,
+            DaneError::NameMismatch { .. } => TlsRptResultType::CertificateHostMismatch,
+            DaneError::NoPeerCertificate
+            | DaneError::Mismatch { .. }
+            | DaneError::Certificate(_) => TlsRptResultType::ValidationFailure,
+        }
+    }
+}
+
+/// The kind of policy a session was evaluated against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum TlsRptPolicyType {
+    /// An MTA-STS policy.
+    Sts,
+    /// DANE TLSA records.
+    Tlsa,
+    /// The domain published no policy.
+    NoPolicyFound,
+}
+
+impl TlsRptPolicyType {
+    /// Returns the name used in reports, e.g. `sts`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TlsRptPolicyType::Sts => "sts",
+            TlsRptPolicyType::Tlsa => "tlsa",
+            TlsRptPolicyType::NoPolicyFound => "no-policy-found",
+        }
+    }
+}
+
+/// The policy applied to sessions with a domain, under which they are aggregated.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub struct TlsRptPolicy {
+    /// The domain the policy belongs to.
+    pub policy_domain: String,
+    /// The kind of policy.
+    pub policy_type: TlsRptPolicyType,
+    /// The policy as published, one entry per line or record.
+    pub policy_string: Vec<String>,
+    /// The MX patterns of an MTA-STS policy.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Vec::is_empty"))]
+    pub mx_host: Vec<String>,
+}
+
+impl TlsRptPolicy {
+    /// An MTA-STS policy of `domain`.
+    pub fn sts(domain: &str, policy: &MtaStsPolicy) -> Self {
+        let mode = match policy.mode {
+            MtaStsMode::Enforce => "enforce",
+            MtaStsMode::Testing => "testing",
+            MtaStsMode::None => "none",
+        };
+        let mut policy_string = vec!["version: STSv1".to_string(), format!("mode: {}", mode)];
+        policy_string.extend(policy.mx.iter().map(|mx| format!("mx: {}", mx)));
+        policy_string.push(format!("max_age: {}", policy.max_age.as_secs()));
+        Self {
+            policy_domain: normalize(domain),
+            policy_type: TlsRptPolicyType::Sts,
+            policy_string,
+            mx_host: policy.mx.clone(),
+        }
+    }
+
+    /// The TLSA records of `domain`.
+    #[cfg(feature = "x509")]
+    pub fn tlsa(domain: &str, records: &[crate::TlsaRecord]) -> Self {
+        let policy_string = records
+            .iter()
+            .map(|record| {
+                let data: String = record.data.iter().map(|b| format!("{:02x}", b)).collect();
+                format!(
+                    "{} {} {} {}",
+                    record.usage, record.selector, record.matching_type, data
+                )
+            })
+            .collect();
+        Self {
+            policy_domain: normalize(domain),
+            policy_type: TlsRptPolicyType::Tlsa,
+            policy_string,
+            mx_host: vec![],
+        }
+    }
+
+    /// No policy was found for `domain`.
+    pub fn none(domain: &str) -> Self {
+        Self {
+            policy_domain: normalize(domain),
+            policy_type: TlsRptPolicyType::NoPolicyFound,
+            policy_string: vec![],
+            mx_host: vec![],
+        }
+    }
+}
+
+/// Describes a failed session. Sessions with equal details are counted together.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub struct FailureDetails {
+    /// Why the session failed.
+    pub result_type: TlsRptResultType,
+    /// The IP address the session was made from.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub sending_mta_ip: Option<IpAddr>,
+    /// The host name of the receiving MX.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub receiving_mx_hostname: Option<String>,
+    /// The HELO or EHLO name announced by the receiving MX.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub receiving_mx_helo: Option<String>,
+    /// The IP address of the receiving MX.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub receiving_ip: Option<IpAddr>,
+    /// A URI pointing to more information about the failure.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub additional_information: Option<String>,
+    /// A free-form code for the failure.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub failure_reason_code: Option<String>,
+}
+
+impl FailureDetails {
+    /// Details of a failure with `result_type` and nothing else known.
+    pub fn new(result_type: TlsRptResultType) -> Self {
+        Self {
+            result_type,
+            sending_mta_ip: None,
+            receiving_mx_hostname: None,
+            receiving_mx_helo: None,
+            receiving_ip: None,
+            additional_information: None,
+            failure_reason_code: None,
+        }
+    }
+}
+
+/// Aggregates session outcomes per policy into TLS-RPT reports, as defined in
+/// [RFC 8460](https://tools.ietf.org/html/rfc8460).
+///
+/// # Example
+///
+/// ```no_run
+/// # #[cfg(feature = "runtime-async-std")]
+/// # fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> { async_std::task::block_on(async {
+/// #
+/// use std::time::{Duration, SystemTime};
+/// use async_std::net::TcpStream;
+/// use async_native_tls::{TlsConnector, TlsRptCollector, TlsRptPolicy};
+///
+/// let collector = TlsRptCollector::new();
+/// let policy = TlsRptPolicy::none("example.com");
+///
+/// let stream = TcpStream::connect("mx1.example.com:25").await?;
+/// // issue STARTTLS here
+/// let res = TlsConnector::new().connect("mx1.example.com", stream).await;
+/// collector.record(&policy, "mx1.example.com", &res);
+///
+/// let end = SystemTime::now();
+/// let report = collector.report("Example Inc.", "mailto:tls@example.com", "1", end - Duration::from_secs(86400), end);
+/// # #[cfg(feature = "serde")]
+/// println!("{}", serde_json::to_string(&report)?);
+/// #
+/// # Ok(()) }) }
+/// # #[cfg(feature = "runtime-tokio")]
+/// # fn main() {}
+/// ```
+#[derive(Debug, Default)]
+pub struct TlsRptCollector {
+    sessions: Mutex<BTreeMap<TlsRptPolicy, Sessions>>,
+}
+
+#[derive(Debug, Default)]
+struct Sessions {
+    successful: u64,
+    failures: BTreeMap<FailureDetails, u64>,
+}
+
+impl TlsRptCollector {
+    /// Create a new, empty collector.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a successful session under `policy`.
+    pub fn record_success(&self, policy: &TlsRptPolicy) {
+        self.with_sessions(policy, |sessions| sessions.successful += 1);
+    }
+
+    /// Records a failed session under `policy`.
+    pub fn record_failure(&self, policy: &TlsRptPolicy, details: FailureDetails) {
+        self.with_sessions(policy, |sessions| {
+            *sessions.failures.entry(details).or_default() += 1
+        });
+    }
+
+    /// Records the outcome of connecting to the MX host `mx` under `policy`.
+    ///
+    /// Accepts the results of [`TlsConnector::connect`](crate::TlsConnector::connect),
+    /// [`MtaSts::connect`](crate::MtaSts::connect) and, with the `x509` feature,
+    /// `DaneVerifier::connect`. A failure returned by
+    /// [`MtaSts::connect`](crate::MtaSts::connect) along with the stream is recorded by passing
+    /// it as an error result.
+    pub fn record<T, E>(&self, policy: &TlsRptPolicy, mx: &str, result: &Result<T, E>)
+    where
+        for<'e> &'e E: Into<TlsRptResultType>,
+    {
+        match result {
+            Ok(_) => self.record_success(policy),
+            Err(err) => {
+                let mut details = FailureDetails::new(err.into());
+                details.receiving_mx_hostname = Some(normalize(mx));
+                self.record_failure(policy, details);
+            }
+        }
+    }
+
+    /// Builds a report of all sessions recorded since the last report, and resets the counts.
+    pub fn report(
+        &self,
+        organization_name: &str,
+        contact_info: &str,
+        report_id: &str,
+        start: SystemTime,
+        end: SystemTime,
+    ) -> TlsRptReport {
+        let sessions = std::mem::take(&mut *self.lock());
+        let policies = sessions
+            .into_iter()
+            .map(|(policy, sessions)| PolicyReport {
+                policy,
+                summary: SessionSummary {
+                    successful: sessions.successful,
+                    failed: sessions.failures.values().sum(),
+                },
+                failure_details: sessions
+                    .failures
+                    .into_iter()
+                    .map(|(details, count)| FailureReport { details, count })
+                    .collect(),
+            })
+            .collect();
+        TlsRptReport {
+            organization_name: organization_name.to_string(),
+            date_range: DateRange { start, end },
+            contact_info: contact_info.to_string(),
+            report_id: report_id.to_string(),
+            policies,
+        }
+    }
+
+    fn with_sessions(&self, policy: &TlsRptPolicy, f: impl FnOnce(&mut Sessions)) {
+        let mut sessions = self.lock();
+        match sessions.get_mut(policy) {
+            Some(entry) => f(entry),
+            None => f(sessions.entry(policy.clone()).or_default()),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BTreeMap<TlsRptPolicy, Sessions>> {
+        self.sessions.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// An aggregate TLS-RPT report.
+///
+/// With the `serde` feature, it serializes into the JSON format of RFC 8460.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub struct TlsRptReport {
+    /// The organization sending the report.
+    pub organization_name: String,
+    /// The reporting period.
+    pub date_range: DateRange,
+    /// How to contact the organization about the report.
+    pub contact_info: String,
+    /// A unique identifier of the report.
+    pub report_id: String,
+    /// The sessions, per policy.
+    pub policies: Vec<PolicyReport>,
+}
+
+/// The period covered by a [`TlsRptReport`], serialized as RFC 3339 timestamps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct DateRange {
+    /// Start of the reporting period.
+    #[cfg_attr(
+        feature = "serde",
+        serde(rename = "start-datetime", serialize_with = "rfc3339")
+    )]
+    pub start: SystemTime,
+    /// End of the reporting period.
+    #[cfg_attr(
+        feature = "serde",
+        serde(rename = "end-datetime", serialize_with = "rfc3339")
+    )]
+    pub end: SystemTime,
+}
+
+/// The sessions evaluated against one policy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub struct PolicyReport {
+    /// The policy applied.
+    pub policy: TlsRptPolicy,
+    /// The number of sessions.
+    pub summary: SessionSummary,
+    /// The failed sessions, grouped by their details.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Vec::is_empty"))]
+    pub failure_details: Vec<FailureReport>,
+}
+
+/// The number of sessions evaluated against a policy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct SessionSummary {
+    /// The number of successful sessions.
+    #[cfg_attr(feature = "serde", serde(rename = "total-successful-session-count"))]
+    pub successful: u64,
+    /// The number of failed sessions.
+    #[cfg_attr(feature = "serde", serde(rename = "total-failure-session-count"))]
+    pub failed: u64,
+}
+
+/// The number of failed sessions with equal details.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct FailureReport {
+    /// What the sessions have in common.
+    #[cfg_attr(feature = "serde", serde(flatten))]
+    pub details: FailureDetails,
+    /// The number of sessions.
+    #[cfg_attr(feature = "serde", serde(rename = "failed-session-count"))]
+    pub count: u64,
+}
+
+fn normalize(domain: &str) -> String {
+    domain.trim_end_matches('.').to_ascii_lowercase()
+}
+
+#[cfg(feature = "serde")]
+fn rfc3339<S: serde::Serializer>(time: &SystemTime, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(&humantime::format_rfc3339_seconds(*time))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn classifies_errors() {
+        let fetch = MtaStsError::Fetch(std::io::ErrorKind::NotFound.into());
+        assert_eq!(
+            TlsRptResultType::from(&fetch),
+            TlsRptResultType::StsPolicyFetchError
+        );
+        let invalid = MtaStsError::InvalidPolicy("missing mode".into());
+        assert_eq!(
+            TlsRptResultType::from(&invalid).as_str(),
+            "sts-policy-invalid"
+        );
+    }
+
+    /// Fails handshakes with an expired, an untrusted and a mismatched server certificate.
+    #[cfg(feature = "runtime-async-std")]
+    async fn failed_handshakes(
+        connector: fn() -> crate::TlsConnector,
+    ) -> Vec<(ConnectError, TlsRptResultType)> {
+        use crate::test_util::{ca, hello, localhost, tcp};
+        use crate::TlsAcceptorBuilder;
+
+        let expired = TlsAcceptorBuilder::from_pkcs8(
+            std::fs::read("tests/expired.pem").unwrap(),
+            std::fs::read("tests/localhost-key.pem").unwrap(),
+        );
+        let cases = vec![
+            (
+                expired,
+                connector().add_root_certificate(ca()),
+                "localhost",
+                TlsRptResultType::CertificateExpired,
+            ),
+            (
+                localhost(),
+                connector(),
+                "localhost",
+                TlsRptResultType::CertificateNotTrusted,
+            ),
+            (
+                localhost(),
+                connector().add_root_certificate(ca()),
+                "mismatch.example",
+                TlsRptResultType::CertificateHostMismatch,
+            ),
+        ];
+        let mut failures = vec![];
+        for (acceptor, connector, host, expected) in cases {
+            let addr = hello(acceptor.build().unwrap()).await;
+            let res = connector.connect(host, tcp(addr).await).await;
+            failures.push((res.map(|_| ()).unwrap_err(), expected));
+        }
+        failures
+    }
+
+    #[cfg(feature = "runtime-async-std")]
+    #[async_std::test]
+    async fn classifies_handshakes() {
+        for (err, _) in failed_handshakes(crate::TlsConnector::new).await {
+            assert_eq!(
+                TlsRptResultType::from(&err),
+                TlsRptResultType::ValidationFailure,
+                "{}",
+                err
+            );
+        }
+    }
+
+    #[cfg(all(
+        feature = "runtime-async-std",
+        feature = "openssl",
+        not(any(target_os = "windows", target_vendor = "apple"))
+    ))]
+    #[async_std::test]
+    async fn classifies_openssl_handshakes() {
+        use crate::TlsConnectorExt;
+
+        let connector = || crate::TlsConnector::new().configure_openssl(|_| Ok(()));
+        for (err, expected) in failed_handshakes(connector).await {
+            assert!(
+                matches!(err, ConnectError::InvalidCertificate { .. }),
+                "{}",
+                err
+            );
+            assert_eq!(TlsRptResultType::from(&err), expected, "{}", err);
+        }
+    }
+
+    #[test]
+    fn aggregates_report() {
+        let policy: MtaStsPolicy =
+            "version: STSv1\nmode: enforce\nmx: *.example.com\nmax_age: 86400"
+                .parse()
+                .unwrap();
+        let sts = TlsRptPolicy::sts("Example.com.", &policy);
+        let none = TlsRptPolicy::none("example.net");
+
+        let collector = TlsRptCollector::new();
+        collector.record_success(&sts);
+        collector.record_success(&sts);
+        collector.record::<(), MtaStsError>(
+            &sts,
+            "mx2.example.com",
+            &Err(MtaStsError::MxMismatch {
+                mx: "mx2.example.com".into(),
+                patterns: policy.mx.clone(),
+            }),
+        );
+        let mut details = FailureDetails::new(TlsRptResultType::StarttlsNotSupported);
+        details.receiving_ip = Some([192, 0, 2, 1].into());
+        collector.record_failure(&none, details.clone());
+        collector.record_failure(&none, details);
+
+        let start = UNIX_EPOCH + Duration::from_secs(1_459_468_800);
+        let report = collector.report(
+            "Company-X",
+            "sts-reporting@company-x.example",
+            "5065427c-23d3-47ca-b6e0-946ea0e8c795",
+            start,
+            start + Duration::from_secs(86399),
+        );
+        assert_eq!(report.policies.len(), 2);
+        assert_eq!(report.policies[0].summary.failed, 1);
+        assert_eq!(report.policies[1].summary.failed, 2);
+        #[cfg(feature = "serde")]
+        assert_eq!(
+            serde_json::to_string(&report).unwrap(),
+            concat!(
+                r#"{"organization-name":"Company-X","#,
+                r#""date-range":{"start-datetime":"2016-04-01T00:00:00Z","end-datetime":"2016-04-01T23:59:59Z"},"#,
+                r#""contact-info":"sts-reporting@company-x.example","#,
+                r#""report-id":"5065427c-23d3-47ca-b6e0-946ea0e8c795","policies":["#,
+                r#"{"policy":{"policy-domain":"example.com","policy-type":"sts","#,
+                r#""policy-string":["version: STSv1","mode: enforce","mx: *.example.com","max_age: 86400"],"#,
+                r#""mx-host":["*.example.com"]},"#,
+                r#""summary":{"total-successful-session-count":2,"total-failure-session-count":1},"#,
+                r#""failure-details":[{"result-type":"validation-failure","#,
+                r#""receiving-mx-hostname":"mx2.example.com","failed-session-count":1}]},"#,
+                r#"{"policy":{"policy-domain":"example.net","policy-type":"no-policy-found","policy-string":[]},"#,
+                r#""summary":{"total-successful-session-count":0,"total-failure-session-count":2},"#,
+                r#""failure-details":[{"result-type":"starttls-not-supported","#,
+                r#""receiving-ip":"192.0.2.1","failed-session-count":2}]}]}"#,
+            )
+        );
+
+        let empty = collector.report("Company-X", "", "2", start, start);
+        assert!(empty.policies.is_empty());
+    }
+}
@@ -17,18 +17,99 @@ use crate::std_adapter::StdAdapter;
 /// data. Bytes read from a `TlsStream` are decrypted from `S` and bytes written
 /// to a `TlsStream` are encrypted when passing through to `S`.
 #[derive(Debug)]
-pub struct TlsStream<S>(native_tls::TlsStream<StdAdapter<S>>);
+pub struct TlsStream<S>(Inner<S>);
+
+/// An error returned from deriving data from the TLS session of a [`TlsStream`].
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    /// NativeTls error.
+    #[error("NativeTls({0})")]
+    NativeTls(#[from] native_tls::Error),
+    /// OpenSsl error.
+    #[cfg(all(
+        feature = "openssl",
+        not(any(target_os = "windows", target_vendor = "apple"))
+    ))]
+    #[error("OpenSsl({0})")]
+    OpenSsl(#[from] openssl::error::ErrorStack),
+    /// The TLS backend of the stream does not support the operation.
+    #[error("{0} is not supported by the TLS backend of this stream")]
+    Unsupported(&'static str),
+    /// The channel binding is not defined for the session, such as tls-unique with TLS 1.3.
+    #[error("{0} is not available for this session")]
+    Unavailable(&'static str),
+    /// The channel binding is unsafe for the session, as it was negotiated without the extended
+    /// master secret extension of [RFC 7627](https://tools.ietf.org/html/rfc7627).
+    #[error("{0} is unsafe without the extended master secret extension")]
+    Unsafe(&'static str),
+}
+
+/// A channel binding type, for binding authentication mechanisms such as SCRAM-PLUS to the TLS
+/// session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChannelBindingType {
+    /// tls-unique, as defined in [RFC 5929](https://tools.ietf.org/html/rfc5929). Only defined
+    /// for TLS 1.2 and earlier.
+    TlsUnique,
+    /// tls-server-end-point, as defined in [RFC 5929](https://tools.ietf.org/html/rfc5929).
+    TlsServerEndPoint,
+    /// tls-exporter, as defined in [RFC 9266](https://tools.ietf.org/html/rfc9266).
+    TlsExporter,
+}
+
+impl ChannelBindingType {
+    /// The registered name of the channel binding type, such as `tls-unique`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChannelBindingType::TlsUnique => "tls-unique",
+            ChannelBindingType::TlsServerEndPoint => "tls-server-end-point",
+            ChannelBindingType::TlsExporter => "tls-exporter",
+        }
+    }
+}
+
+/// The TLS session, driven by native-tls or, for settings it does not expose, by OpenSSL.
+#[derive(Debug)]
+enum Inner<S> {
+    Native(native_tls::TlsStream<StdAdapter<S>>),
+    #[cfg(all(
+        feature = "openssl",
+        not(any(target_os = "windows", target_vendor = "apple"))
+    ))]
+    OpenSsl(openssl::ssl::SslStream<StdAdapter<S>>),
+}
 
 impl<S> TlsStream<S> {
     pub(crate) fn new(stream: native_tls::TlsStream<StdAdapter<S>>) -> Self {
-        Self(stream)
+        Self(Inner::Native(stream))
     }
+
+    #[cfg(all(
+        feature = "openssl",
+        not(any(target_os = "windows", target_vendor = "apple"))
+    ))]
+    pub(crate) fn from_openssl(stream: openssl::ssl::SslStream<StdAdapter<S>>) -> Self {
+        Self(Inner::OpenSsl(stream))
+    }
+
+    /// The OpenSSL session, if the stream was established through OpenSSL.
+    #[cfg(all(
+        feature = "openssl",
+        not(any(target_os = "windows", target_vendor = "apple"))
+    ))]
+    pub(crate) fn ssl_ref(&self) -> Option<&openssl::ssl::SslRef> {
+        match &self.0 {
+            Inner::Native(_) => None,
+            Inner::OpenSsl(s) => Some(s.ssl()),
+        }
+    }
+
     fn with_context<F, R>(&mut self, ctx: &mut Context<'_>, f: F) -> R
     where
-        F: FnOnce(&mut native_tls::TlsStream<StdAdapter<S>>) -> R,
+        F: FnOnce(&mut Inner<S>) -> R,
         StdAdapter<S>: Read + Write,
     {
-        self.0.get_mut().context = ctx as *mut _ as *mut ();
+        self.0.adapter_mut().context = ctx as *mut _ as *mut ();
         let g = Guard(self);
         f(&mut (g.0).0)
     }
@@ -38,7 +119,7 @@ impl<S> TlsStream<S> {
     where
         S: AsyncRead + AsyncWrite + Unpin,
     {
-        &self.0.get_ref().inner
+        &self.0.adapter().inner
     }
 
     /// Returns a mutable reference to the inner stream.
@@ -46,7 +127,7 @@ impl<S> TlsStream<S> {
     where
         S: AsyncRead + AsyncWrite + Unpin,
     {
-        &mut self.0.get_mut().inner
+        &mut self.0.adapter_mut().inner
     }
 
     /// Returns the number of bytes that can be read without resulting in any network calls.
@@ -54,7 +135,14 @@ impl<S> TlsStream<S> {
     where
         S: AsyncRead + AsyncWrite + Unpin,
     {
-        self.0.buffered_read_size()
+        match &self.0 {
+            Inner::Native(s) => s.buffered_read_size(),
+            #[cfg(all(
+                feature = "openssl",
+                not(any(target_os = "windows", target_vendor = "apple"))
+            ))]
+            Inner::OpenSsl(s) => Ok(s.ssl().pending()),
+        }
     }
 
     /// Returns the peer's leaf certificate, if available.
@@ -62,7 +150,129 @@ impl<S> TlsStream<S> {
     where
         S: AsyncRead + AsyncWrite + Unpin,
     {
-        self.0.peer_certificate()
+        match &self.0 {
+            Inner::Native(s) => s.peer_certificate(),
+            #[cfg(all(
+                feature = "openssl",
+                not(any(target_os = "windows", target_vendor = "apple"))
+            ))]
+            Inner::OpenSsl(s) => s
+                .ssl()
+                .peer_certificate()
+                .map(|cert| crate::ossl::certificate(&cert))
+                .transpose(),
+        }
+    }
+
+    /// Returns whether the session was resumed from an earlier connection rather than
+    /// established with a full handshake.
+    ///
+    /// Only streams established through OpenSSL, such as those of connectors with
+    /// `TlsConnectorExt::resume_sessions` of the `openssl` feature, can tell; others always
+    /// report `false`.
+    pub fn session_resumed(&self) -> bool
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        match &self.0 {
+            Inner::Native(_) => false,
+            #[cfg(all(
+                feature = "openssl",
+                not(any(target_os = "windows", target_vendor = "apple"))
+            ))]
+            Inner::OpenSsl(s) => s.ssl().session_reused(),
+        }
+    }
+
+    /// Returns the protocol negotiated via ALPN, if any.
+    pub fn negotiated_alpn(&self) -> crate::Result<Option<Vec<u8>>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        match &self.0 {
+            Inner::Native(s) => s.negotiated_alpn(),
+            #[cfg(all(
+                feature = "openssl",
+                not(any(target_os = "windows", target_vendor = "apple"))
+            ))]
+            Inner::OpenSsl(s) => Ok(s.ssl().selected_alpn_protocol().map(<[u8]>::to_vec)),
+        }
+    }
+
+    /// Returns the certificate chain of the peer, leaf first, if available.
+    ///
+    /// Streams established through OpenSSL, such as those accepted with
+    /// [`ClientAuth`](crate::ClientAuth), return the chain as verified, or for sessions resumed
+    /// by a connector, as verified when the session was established. Otherwise only the leaf
+    /// certificate is exposed, so the chain holds just that.
+    pub fn peer_certificate_chain(&self) -> crate::Result<Option<Vec<crate::Certificate>>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        #[cfg(all(
+            feature = "openssl",
+            not(any(target_os = "windows", target_vendor = "apple"))
+        ))]
+        if let Inner::OpenSsl(s) = &self.0 {
+            if let Some(chain) = s.ssl().verified_chain() {
+                return chain
+                    .iter()
+                    .map(crate::ossl::certificate)
+                    .collect::<crate::Result<_>>()
+                    .map(Some);
+            }
+            if let Some(chain) = crate::ossl::resumed_chain(s.ssl()) {
+                return chain
+                    .iter()
+                    .map(|cert| crate::ossl::certificate(cert))
+                    .collect::<crate::Result<_>>()
+                    .map(Some);
+            }
+        }
+        Ok(self.peer_certificate()?.map(|cert| vec![cert]))
+    }
+
+    /// Returns the certificate chain the server presented, leaf first, if available.
+    ///
+    /// Unlike [`TlsStream::peer_certificate_chain`], this never includes certificates of the
+    /// local trust store that the server did not send. Only streams connected through OpenSSL
+    /// with a full handshake expose more than the leaf certificate.
+    #[cfg(feature = "x509")]
+    pub(crate) fn presented_certificate_chain(
+        &self,
+    ) -> crate::Result<Option<Vec<crate::Certificate>>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        #[cfg(all(
+            feature = "openssl",
+            not(any(target_os = "windows", target_vendor = "apple"))
+        ))]
+        if let Inner::OpenSsl(s) = &self.0 {
+            // clients see the leaf first in the chain, unlike servers
+            if let Some(chain) = s.ssl().peer_cert_chain().filter(|chain| !chain.is_empty()) {
+                return chain
+                    .iter()
+                    .map(crate::ossl::certificate)
+                    .collect::<crate::Result<_>>()
+                    .map(Some);
+            }
+        }
+        Ok(self.peer_certificate()?.map(|cert| vec![cert]))
+    }
+
+    /// Returns the parsed contents of the peer's leaf certificate, if available.
+    #[cfg(feature = "x509")]
+    pub fn peer_certificate_info(
+        &self,
+    ) -> std::result::Result<Option<crate::CertificateInfo>, crate::CertificateError>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        match self.peer_certificate()? {
+            Some(cert) => Ok(Some(crate::CertificateInfo::from_certificate(&cert)?)),
+            None => Ok(None),
+        }
     }
 
     /// Returns the tls-server-end-point channel binding data as defined in [RFC 5929](https://tools.ietf.org/html/rfc5929).
@@ -70,7 +280,105 @@ impl<S> TlsStream<S> {
     where
         S: AsyncRead + AsyncWrite + Unpin,
     {
-        self.0.tls_server_end_point()
+        match &self.0 {
+            Inner::Native(s) => s.tls_server_end_point(),
+            #[cfg(all(
+                feature = "openssl",
+                not(any(target_os = "windows", target_vendor = "apple"))
+            ))]
+            Inner::OpenSsl(s) => Ok(crate::ossl::tls_server_end_point(s.ssl())),
+        }
+    }
+
+    /// Returns the channel binding data of the given type.
+    ///
+    /// tls-server-end-point is available for all streams. tls-unique and tls-exporter are only
+    /// supported for streams established through OpenSSL, such as those of connectors and
+    /// acceptors with OpenSSL settings of the `openssl` feature; other streams fail with
+    /// [`Error::Unsupported`]. tls-unique is not defined for TLS 1.3, and both fail with
+    /// [`Error::Unsafe`] for TLS 1.2 sessions without the extended master secret extension,
+    /// which are exposed to the triple handshake attack.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "runtime-async-std")]
+    /// # fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> { async_std::task::block_on(async {
+    /// #
+    /// use async_std::net::TcpStream;
+    /// use async_native_tls::{ChannelBindingType, SessionError};
+    ///
+    /// let stream = TcpStream::connect("imap.example.com:993").await?;
+    /// let stream = async_native_tls::connect("imap.example.com", stream).await?;
+    /// let (kind, binding) = match stream.channel_binding(ChannelBindingType::TlsExporter) {
+    ///     Ok(binding) => (ChannelBindingType::TlsExporter, binding),
+    ///     Err(SessionError::Unsupported(_)) | Err(SessionError::Unsafe(_)) => {
+    ///         let kind = ChannelBindingType::TlsServerEndPoint;
+    ///         (kind, stream.channel_binding(kind)?)
+    ///     }
+    ///     Err(err) => return Err(err.into()),
+    /// };
+    /// println!("SCRAM-SHA-256-PLUS with p={}, {} bytes", kind.as_str(), binding.len());
+    /// #
+    /// # Ok(()) }) }
+    /// # #[cfg(feature = "runtime-tokio")]
+    /// # fn main() {}
+    /// ```
+    pub fn channel_binding(&self, kind: ChannelBindingType) -> Result<Vec<u8>, Error>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        if kind == ChannelBindingType::TlsServerEndPoint {
+            return self
+                .tls_server_end_point()?
+                .ok_or(Error::Unavailable(kind.as_str()));
+        }
+        match &self.0 {
+            Inner::Native(_) => Err(Error::Unsupported(kind.as_str())),
+            #[cfg(all(
+                feature = "openssl",
+                not(any(target_os = "windows", target_vendor = "apple"))
+            ))]
+            Inner::OpenSsl(s) => crate::ossl::channel_binding(s.ssl(), kind),
+        }
+    }
+
+    /// Exports keying material from the TLS session as defined in
+    /// [RFC 5705](https://tools.ietf.org/html/rfc5705), for binding application protocols to
+    /// the session. Both peers derive the same `len` bytes for the same `label` and `context`.
+    ///
+    /// Only supported for streams established through OpenSSL, such as those of connectors and
+    /// acceptors with OpenSSL settings of the `openssl` feature; fails with
+    /// [`Error::Unsupported`] otherwise, as native-tls does not expose the exporter.
+    #[cfg_attr(
+        not(all(
+            feature = "openssl",
+            not(any(target_os = "windows", target_vendor = "apple"))
+        )),
+        allow(unused_variables)
+    )]
+    pub fn export_keying_material(
+        &self,
+        label: &str,
+        context: Option<&[u8]>,
+        len: usize,
+    ) -> Result<Vec<u8>, Error>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        match &self.0 {
+            Inner::Native(_) => Err(Error::Unsupported("exporting keying material")),
+            #[cfg(all(
+                feature = "openssl",
+                not(any(target_os = "windows", target_vendor = "apple"))
+            ))]
+            Inner::OpenSsl(s) => {
+                let mut material = vec![0; len];
+                s.ssl()
+                    .export_keying_material(&mut material, label, context)?;
+                Ok(material)
+            }
+        }
     }
 }
 
@@ -144,6 +452,87 @@ where
     }
 }
 
+impl<S> Inner<S>
+where
+    StdAdapter<S>: Read + Write,
+{
+    fn adapter(&self) -> &StdAdapter<S> {
+        match self {
+            Inner::Native(s) => s.get_ref(),
+            #[cfg(all(
+                feature = "openssl",
+                not(any(target_os = "windows", target_vendor = "apple"))
+            ))]
+            Inner::OpenSsl(s) => s.get_ref(),
+        }
+    }
+
+    fn adapter_mut(&mut self) -> &mut StdAdapter<S> {
+        match self {
+            Inner::Native(s) => s.get_mut(),
+            #[cfg(all(
+                feature = "openssl",
+                not(any(target_os = "windows", target_vendor = "apple"))
+            ))]
+            Inner::OpenSsl(s) => s.get_mut(),
+        }
+    }
+
+    fn shutdown(&mut self) -> io::Result<()> {
+        match self {
+            Inner::Native(s) => s.shutdown(),
+            #[cfg(all(
+                feature = "openssl",
+                not(any(target_os = "windows", target_vendor = "apple"))
+            ))]
+            Inner::OpenSsl(s) => crate::ossl::shutdown(s),
+        }
+    }
+}
+
+impl<S> Read for Inner<S>
+where
+    StdAdapter<S>: Read + Write,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Inner::Native(s) => s.read(buf),
+            #[cfg(all(
+                feature = "openssl",
+                not(any(target_os = "windows", target_vendor = "apple"))
+            ))]
+            Inner::OpenSsl(s) => s.read(buf),
+        }
+    }
+}
+
+impl<S> Write for Inner<S>
+where
+    StdAdapter<S>: Read + Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Inner::Native(s) => s.write(buf),
+            #[cfg(all(
+                feature = "openssl",
+                not(any(target_os = "windows", target_vendor = "apple"))
+            ))]
+            Inner::OpenSsl(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Inner::Native(s) => s.flush(),
+            #[cfg(all(
+                feature = "openssl",
+                not(any(target_os = "windows", target_vendor = "apple"))
+            ))]
+            Inner::OpenSsl(s) => s.flush(),
+        }
+    }
+}
+
 struct Guard<'a, S>(&'a mut TlsStream<S>)
 where
     StdAdapter<S>: Read + Write;
@@ -153,7 +542,7 @@ where
     StdAdapter<S>: Read + Write,
 {
     fn drop(&mut self) {
-        (self.0).0.get_mut().context = null_mut();
+        (self.0).0.adapter_mut().context = null_mut();
     }
 }
 
@@ -164,3 +553,138 @@ fn cvt<T>(r: io::Result<T>) -> Poll<io::Result<T>> {
         Err(e) => Poll::Ready(Err(e)),
     }
 }
+
+#[cfg(all(test, feature = "runtime-async-std"))]
+mod tests {
+    use super::*;
+    use crate::test_util::{hello, localhost, tcp};
+    use crate::TlsConnector;
+
+    #[cfg(all(
+        feature = "openssl",
+        not(any(target_os = "windows", target_vendor = "apple"))
+    ))]
+    #[async_std::test]
+    async fn exports_keying_material() {
+        use crate::runtime::{AsyncReadExt, AsyncWriteExt};
+        use crate::test_util::serve;
+        use crate::{TlsAcceptorBuilderExt, TlsConnectorExt};
+
+        let acceptor = localhost().configure_openssl(|_| Ok(())).build().unwrap();
+        let (addr, server) = serve(acceptor, |mut stream| async move {
+            stream.close().await.unwrap();
+            stream
+                .export_keying_material("EXPORTER-test", Some(b"context"), 32)
+                .unwrap()
+        })
+        .await;
+
+        let mut stream = TlsConnector::new()
+            .danger_accept_invalid_certs(true)
+            .configure_openssl(|_| Ok(()))
+            .connect("localhost", tcp(addr).await)
+            .await
+            .unwrap();
+        stream.read_to_end(&mut vec![]).await.unwrap();
+        let client = stream
+            .export_keying_material("EXPORTER-test", Some(b"context"), 32)
+            .unwrap();
+        assert_eq!(client.len(), 32);
+        assert_eq!(Some(client.clone()), server.await);
+        assert_ne!(
+            client,
+            stream
+                .export_keying_material("EXPORTER-test", None, 32)
+                .unwrap()
+        );
+    }
+
+    #[cfg(all(
+        feature = "openssl",
+        not(any(target_os = "windows", target_vendor = "apple"))
+    ))]
+    async fn pair(
+        acceptor: &crate::TlsAcceptor,
+        connector: &TlsConnector,
+    ) -> (
+        TlsStream<async_std::net::TcpStream>,
+        TlsStream<async_std::net::TcpStream>,
+    ) {
+        let (addr, server) =
+            crate::test_util::serve(acceptor.clone(), |stream| async { stream }).await;
+        let client = connector
+            .connect("localhost", tcp(addr).await)
+            .await
+            .unwrap();
+        (server.await.unwrap(), client)
+    }
+
+    #[cfg(all(
+        feature = "openssl",
+        not(any(target_os = "windows", target_vendor = "apple"))
+    ))]
+    #[async_std::test]
+    async fn channel_bindings() {
+        use crate::runtime::AsyncWriteExt;
+        use crate::{TlsAcceptorBuilderExt, TlsConnectorExt};
+        use openssl::ssl::SslOptions;
+        use ChannelBindingType::*;
+
+        let acceptor = || localhost().configure_openssl(|_| Ok(()));
+        let connector = TlsConnector::new()
+            .danger_accept_invalid_certs(true)
+            .resume_sessions();
+
+        // a full and a resumed TLS 1.2 handshake
+        let tls12 = acceptor().build().unwrap();
+        for resumed in [false, true].iter() {
+            let (mut server, mut client) = pair(&tls12, &connector).await;
+            assert_eq!(client.session_resumed(), *resumed);
+            for kind in [TlsUnique, TlsServerEndPoint, TlsExporter].iter() {
+                let binding = client.channel_binding(*kind).unwrap();
+                assert_eq!(binding, server.channel_binding(*kind).unwrap());
+            }
+            assert_eq!(client.channel_binding(TlsUnique).unwrap().len(), 12);
+            server.close().await.unwrap();
+            client.close().await.unwrap();
+        }
+
+        let tls13 = acceptor()
+            .configure_openssl(|builder| {
+                builder.clear_options(SslOptions::NO_TLSV1_3);
+                Ok(())
+            })
+            .build()
+            .unwrap();
+        let (server, client) = pair(&tls13, &connector).await;
+        assert!(matches!(
+            client.channel_binding(TlsUnique),
+            Err(Error::Unavailable(_))
+        ));
+        assert_eq!(
+            client.channel_binding(TlsExporter).unwrap(),
+            server.channel_binding(TlsExporter).unwrap()
+        );
+    }
+
+    #[async_std::test]
+    async fn native_unsupported() {
+        let addr = hello(localhost().build().unwrap()).await;
+        let stream = TlsConnector::new()
+            .danger_accept_invalid_certs(true)
+            .connect("localhost", tcp(addr).await)
+            .await
+            .unwrap();
+        assert!(matches!(
+            stream.export_keying_material("EXPORTER-test", None, 32),
+            Err(Error::Unsupported(_))
+        ));
+        assert!(matches!(
+            stream.channel_binding(ChannelBindingType::TlsUnique),
+            Err(Error::Unsupported(_))
+        ));
+        assert!(stream
+            .channel_binding(ChannelBindingType::TlsServerEndPoint)
+            .is_ok());
+    }
+}
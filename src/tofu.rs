@@ -0,0 +1,393 @@
+use std::collections::BTreeMap;
+use std::fmt::{self, Write};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use sha2::{Digest, Sha256};
+
+use crate::host::Host;
+use crate::runtime::{unblock, AsyncRead, AsyncWrite};
+use crate::{ConnectError, TlsConnector, TlsStream};
+
+/// A trust-on-first-use store of certificate fingerprints, for peers with self-signed
+/// certificates.
+///
+/// The SHA-256 fingerprint of the certificate a host presents on first contact is recorded, and
+/// later connections are only accepted if the host presents the same certificate. A changed
+/// certificate is rejected until the rotation is [approved](TofuStore::approve).
+///
+/// Stores opened from a file persist every change, one `host fingerprint` pair per line, in the
+/// spirit of SSH `known_hosts` files. Lines starting with `#` are ignored.
+///
+/// # Example
+///
+/// ```no_run
+/// # #[cfg(feature = "runtime-async-std")]
+/// # fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> { async_std::task::block_on(async {
+/// #
+/// use async_std::net::TcpStream;
+/// use async_native_tls::{TlsConnector, TofuError, TofuStore};
+///
+/// let store = TofuStore::open("known_devices").await?;
+/// let stream = TcpStream::connect("device.local:443").await?;
+/// match store.connect(TlsConnector::new(), "device.local", stream).await {
+///     Ok((stream, trust)) => println!("connected, {:?}", trust),
+///     Err(TofuError::Changed { presented, .. }) => {
+///         // after confirming the rotation out of band:
+///         store.approve("device.local", presented).await?;
+///     }
+///     Err(err) => return Err(err.into()),
+/// }
+/// #
+/// # Ok(()) }) }
+/// # #[cfg(feature = "runtime-tokio")]
+/// # fn main() {}
+/// ```
+pub struct TofuStore {
+    path: Option<PathBuf>,
+    hosts: Mutex<BTreeMap<String, [u8; 32]>>,
+    /// Counts the changes, so that a snapshot is never persisted over a newer one.
+    generation: AtomicU64,
+    /// The generation last persisted, locked while persisting.
+    persisted: Arc<Mutex<u64>>,
+}
+
+/// How the certificate of a peer was trusted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TofuTrust {
+    /// The host was unknown and its fingerprint has been recorded.
+    FirstUse,
+    /// The certificate matched the recorded fingerprint.
+    Known,
+}
+
+/// An error returned from connecting through a [`TofuStore`].
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    /// Connecting failed.
+    #[error("Connect({0})")]
+    Connect(#[from] ConnectError),
+    /// The server presented no certificate.
+    #[error("server presented no certificate")]
+    NoPeerCertificate,
+    /// The certificate of the host differs from the recorded one.
+    #[error("certificate of {host} changed from {} to {}", hex(.known), hex(.presented))]
+    Changed {
+        /// The host connected to.
+        host: String,
+        /// The recorded fingerprint.
+        known: [u8; 32],
+        /// The fingerprint of the presented certificate.
+        presented: [u8; 32],
+    },
+    /// Reading or writing the store failed.
+    #[error("Io({0})")]
+    Io(#[from] io::Error),
+}
+
+impl From<native_tls::Error> for Error {
+    fn from(err: native_tls::Error) -> Self {
+        Error::Connect(err.into())
+    }
+}
+
+impl TofuStore {
+    /// Create a store that is not persisted.
+    pub fn in_memory() -> Self {
+        Self {
+            path: None,
+            hosts: Mutex::new(BTreeMap::new()),
+            generation: AtomicU64::new(0),
+            persisted: Arc::new(Mutex::new(0)),
+        }
+    }
+
+    /// Opens the store persisted at `path`, which is created on the first change if missing.
+    pub async fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let (path, hosts) = unblock(move || {
+            let hosts = match fs::read_to_string(&path) {
+                Ok(text) => parse(&text)?,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+                Err(err) => return Err(err),
+            };
+            Ok((path, hosts))
+        })
+        .await?;
+        Ok(Self {
+            path: Some(path),
+            hosts: Mutex::new(hosts),
+            generation: AtomicU64::new(0),
+            persisted: Arc::new(Mutex::new(0)),
+        })
+    }
+
+    /// Returns the recorded fingerprint of `host`.
+    pub fn fingerprint(&self, host: &str) -> Option<[u8; 32]> {
+        self.lock().get(&normalize(host)).copied()
+    }
+
+    /// Connects to `host`, trusting its certificate on first use.
+    ///
+    /// Certificate validation of `connector` is disabled; the recorded fingerprint takes its
+    /// place.
+    pub async fn connect<S>(
+        &self,
+        connector: TlsConnector,
+        host: impl Into<Host>,
+        stream: S,
+    ) -> Result<(TlsStream<S>, TofuTrust), Error>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let host = host.into().as_string();
+        let stream = connector
+            .danger_accept_invalid_certs(true)
+            .connect(&host, stream)
+            .await?;
+        let trust = self.verify(&host, &stream).await?;
+        Ok((stream, trust))
+    }
+
+    /// Checks the peer certificate of `stream` against the fingerprint recorded for `host`,
+    /// recording it if there is none.
+    pub async fn verify<S>(&self, host: &str, stream: &TlsStream<S>) -> Result<TofuTrust, Error>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let cert = stream.peer_certificate()?.ok_or(Error::NoPeerCertificate)?;
+        let presented: [u8; 32] = Sha256::digest(cert.to_der()?).into();
+        let host = normalize(host);
+
+        let snapshot = {
+            let mut hosts = self.lock();
+            match hosts.get(&host) {
+                Some(known) if *known == presented => return Ok(TofuTrust::Known),
+                Some(known) => {
+                    return Err(Error::Changed {
+                        host,
+                        known: *known,
+                        presented,
+                    })
+                }
+                None => {
+                    hosts.insert(host, presented);
+                    self.snapshot(&hosts)
+                }
+            }
+        };
+        self.persist(snapshot).await?;
+        Ok(TofuTrust::FirstUse)
+    }
+
+    /// Approves a rotated certificate of `host`, replacing its recorded fingerprint.
+    pub async fn approve(&self, host: &str, fingerprint: [u8; 32]) -> io::Result<()> {
+        let snapshot = {
+            let mut hosts = self.lock();
+            hosts.insert(normalize(host), fingerprint);
+            self.snapshot(&hosts)
+        };
+        self.persist(snapshot).await
+    }
+
+    /// Forgets `host`, so that its next certificate is trusted on first use again.
+    pub async fn forget(&self, host: &str) -> io::Result<()> {
+        let snapshot = {
+            let mut hosts = self.lock();
+            hosts.remove(&normalize(host));
+            self.snapshot(&hosts)
+        };
+        self.persist(snapshot).await
+    }
+
+    /// Copies the changed `hosts`, along with the generation of the change.
+    fn snapshot(&self, hosts: &BTreeMap<String, [u8; 32]>) -> (u64, BTreeMap<String, [u8; 32]>) {
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        (generation, hosts.clone())
+    }
+
+    async fn persist(&self, snapshot: (u64, BTreeMap<String, [u8; 32]>)) -> io::Result<()> {
+        let path = match &self.path {
+            Some(path) => path.clone(),
+            None => return Ok(()),
+        };
+        let persisted = self.persisted.clone();
+        let (generation, hosts) = snapshot;
+        unblock(move || {
+            // held until the rename, so that concurrent changes are written one at a time
+            let mut persisted = persisted.lock().unwrap_or_else(|e| e.into_inner());
+            if *persisted > generation {
+                // a newer snapshot, including this change, is already on disk
+                return Ok(());
+            }
+            let mut text = String::new();
+            for (host, fingerprint) in &hosts {
+                let _ = writeln!(text, "{} {}", host, hex(fingerprint));
+            }
+            // write a sibling file and rename it, so readers never see a partial store
+            let mut tmp = path.clone().into_os_string();
+            tmp.push(".tmp");
+            fs::write(&tmp, text)?;
+            fs::rename(&tmp, &path)?;
+            *persisted = generation;
+            Ok(())
+        })
+        .await
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BTreeMap<String, [u8; 32]>> {
+        self.hosts.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl fmt::Debug for TofuStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TofuStore")
+            .field("path", &self.path)
+            .field("hosts", &self.lock().keys())
+            .finish()
+    }
+}
+
+fn parse(text: &str) -> io::Result<BTreeMap<String, [u8; 32]>> {
+    let invalid = |line: usize| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid entry on line {}", line + 1),
+        )
+    };
+    let mut hosts = BTreeMap::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.split_whitespace();
+        let (host, fingerprint) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(host), Some(fingerprint), None) => (host, fingerprint),
+            _ => return Err(invalid(i)),
+        };
+        let fingerprint = unhex(fingerprint).ok_or_else(|| invalid(i))?;
+        hosts.insert(normalize(host), fingerprint);
+    }
+    Ok(hosts)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(text: &str) -> Option<[u8; 32]> {
+    if text.len() != 64 || !text.is_ascii() {
+        return None;
+    }
+    let mut bytes = [0; 32];
+    for (byte, pair) in bytes.iter_mut().zip(text.as_bytes().chunks(2)) {
+        let pair = std::str::from_utf8(pair).ok()?;
+        *byte = u8::from_str_radix(pair, 16).ok()?;
+    }
+    Some(bytes)
+}
+
+fn normalize(host: &str) -> String {
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
+
+#[cfg(all(test, feature = "runtime-async-std"))]
+mod tests {
+    use super::*;
+    use crate::test_util::{hello, localhost, tcp};
+    use crate::TlsAcceptorBuilder;
+    use async_std::net::TcpStream;
+
+    /// Connects to a server presenting the `localhost` test certificate, or the identity in
+    /// `tests/identity.pfx` if `rotated` is set.
+    async fn server(rotated: bool) -> TcpStream {
+        let acceptor = if rotated {
+            TlsAcceptorBuilder::from_pkcs12(std::fs::read("tests/identity.pfx").unwrap(), "hello")
+        } else {
+            localhost()
+        };
+        tcp(hello(acceptor.build().unwrap()).await).await
+    }
+
+    #[async_std::test]
+    async fn rejects_changed_certificate() {
+        let store = TofuStore::in_memory();
+        let connect = |stream| store.connect(TlsConnector::new(), "device.local", stream);
+
+        let (_, trust) = connect(server(false).await).await.unwrap();
+        assert_eq!(trust, TofuTrust::FirstUse);
+        let (_, trust) = connect(server(false).await).await.unwrap();
+        assert_eq!(trust, TofuTrust::Known);
+
+        let presented = match connect(server(true).await).await {
+            Err(Error::Changed {
+                known, presented, ..
+            }) => {
+                assert_eq!(Some(known), store.fingerprint("device.local"));
+                presented
+            }
+            res => panic!("unexpected result {:?}", res.map(|(_, trust)| trust)),
+        };
+        store.approve("Device.Local.", presented).await.unwrap();
+        let (_, trust) = connect(server(true).await).await.unwrap();
+        assert_eq!(trust, TofuTrust::Known);
+    }
+
+    #[async_std::test]
+    async fn persists() {
+        let path =
+            std::env::temp_dir().join(format!("async-native-tls-tofu-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let store = TofuStore::open(&path).await.unwrap();
+        store
+            .connect(TlsConnector::new(), "device.local", server(false).await)
+            .await
+            .unwrap();
+        store.approve("other.local", [7; 32]).await.unwrap();
+        store.forget("other.local").await.unwrap();
+
+        let reopened = TofuStore::open(&path).await.unwrap();
+        assert_eq!(
+            reopened.fingerprint("device.local"),
+            store.fingerprint("device.local")
+        );
+        assert_eq!(reopened.fingerprint("other.local"), None);
+
+        std::fs::write(&path, "# comment\ndevice.local not-a-fingerprint\n").unwrap();
+        assert!(TofuStore::open(&path).await.is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[async_std::test]
+    async fn concurrent_approvals() {
+        let path = std::env::temp_dir().join(format!(
+            "async-native-tls-tofu-concurrent-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+
+        let store = TofuStore::open(&path).await.unwrap();
+        let approvals = (0..16u8).map(|i| {
+            let store = &store;
+            async move { store.approve(&format!("host{}.local", i), [i; 32]).await }
+        });
+        for res in futures::future::join_all(approvals).await {
+            res.unwrap();
+        }
+        let reopened = TofuStore::open(&path).await.unwrap();
+        for i in 0..16u8 {
+            assert_eq!(
+                reopened.fingerprint(&format!("host{}.local", i)),
+                Some([i; 32])
+            );
+        }
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}